@@ -1,18 +1,282 @@
 // SPDX-License-Identifier: MPL-2.0
-use clap::{arg, command, value_parser, ArgAction, CommandFactory, Parser};
+use crate::{
+	compare::IgnorableField,
+	conflict::ConflictPolicy,
+	direction::Direction,
+	hotspot::HotspotStrategy,
+	paths, remote,
+	rename::RegexRename,
+	report::{LogFormat, Verbosity},
+	schema::{self, SchemaKind},
+	select::{AnimationFilter, MatchMode, NameNormalization, SourcePriority, StructureFilter},
+	transform::OffsetMode,
+};
+use clap::{
+	builder::{PossibleValuesParser, TypedValueParser},
+	value_parser, ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum,
+};
 use clap_complete::{Generator, Shell};
 use color_eyre::eyre::{eyre, Result};
-use std::path::PathBuf;
+use std::{
+	ffi::OsString,
+	path::{Path, PathBuf},
+	time::Duration,
+};
+
+/// The operation requested on the command line
+#[derive(Debug)]
+pub enum Command {
+	/// Copy icon states between DMI files
+	Copy(Box<DmiCopyArgs>),
+	/// Check that two DMI files contain the same icon states
+	AssertEq(AssertEqArgs),
+	/// Summarize a single DMI file
+	Info(InfoArgs),
+	/// Render a contact sheet of every state in a DMI file
+	Thumbnail(ThumbnailArgs),
+	/// Remove the hotspot from every state in a DMI file
+	StripHotspots(StripHotspotsArgs),
+	/// Check whether a DMI file has some states
+	Probe(ProbeArgs),
+	/// Print the raw metadata of a DMI file
+	DumpMetadata(DumpMetadataArgs),
+	/// Re-encode a DMI file without changing its states
+	Normalize(NormalizeArgs),
+	/// Check a DMI file's states against an inventory file
+	CheckInventory(CheckInventoryArgs),
+	/// Check how many distinct colors each state of a DMI file uses
+	CheckColors(CheckColorsArgs),
+	/// Find states with the same content across a directory of DMI files
+	FindDuplicates(FindDuplicatesArgs),
+	/// Apply the changes listed in a copy's report
+	ApplyPatch(ApplyPatchArgs),
+	/// Reconcile the states of two DMI files in both directions
+	Sync(SyncArgs),
+	/// Browse the states of a DMI file interactively
+	Browse(BrowseArgs),
+	/// Show a state of a DMI file in the terminal
+	Preview(PreviewArgs),
+	/// Check that a DMI file survives a save and reload unchanged
+	Selftest(SelftestArgs),
+	/// Check that DMI files are valid
+	Validate(ValidateArgs),
+	/// Write every frame of some states to its own PNG file
+	DumpFrames(DumpFramesArgs),
+	/// Rebuild a state from frames written by dump-frames
+	AssembleFrames(AssembleFramesArgs),
+	/// Check the files in a checksum manifest
+	VerifyChecksums(PathBuf),
+	/// List the states of a copy's source files as JSON
+	ListSourceStates {
+		/// The source files
+		from: Vec<PathBuf>,
+		/// Whether the sources are gzip-compressed, regardless of their
+		/// extensions
+		gzip: bool,
+	},
+}
+
+/// How structured output is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+	/// Human-readable text
+	#[default]
+	Text,
+	/// JSON, for scripting
+	Json,
+}
+
+/// What to do when a replaced state's frame count differs from the target's
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FrameMatching {
+	/// Refuse to replace the state
+	Error,
+	/// Repeat the source's last frame until it has as many as the target
+	Pad,
+	/// Replace the state anyway, with its frames lining up by position
+	Replace,
+}
+
+/// What to do when a requested state isn't in the source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MissingSourcePolicy {
+	/// Fail before anything is copied
+	Error,
+	/// Warn about the state and copy the rest
+	#[default]
+	Warn,
+	/// Copy the rest without a warning
+	Ignore,
+}
+
+/// How hard to compress a saved DMI's image data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum PngCompression {
+	/// Compress quickly, at the cost of a larger file
+	Fast,
+	/// The same compression the dmi crate uses
+	#[default]
+	Default,
+	/// Compress as much as possible, at the cost of speed
+	Best,
+}
 
 /// Arguments for copying icon states between DMI files
 #[derive(Debug)]
 pub struct DmiCopyArgs {
-	/// The original .dmi file to read the target icon states from
-	pub from: PathBuf,
+	/// The original .dmi files to read the target icon states from
+	pub from: Vec<PathBuf>,
 	/// The target .dmi file to copy the icon states into
 	pub to: PathBuf,
 	/// A list of the icon states to copy
 	pub icon_states: Vec<String>,
+	/// An external command that each copied frame is piped through
+	pub transform_cmd: Option<String>,
+	/// When set, copied states' delays are rounded to multiples of this many
+	/// milliseconds
+	pub normalize_delays: Option<f32>,
+	/// When set, every frame of copied animated states is given this delay,
+	/// in ticks
+	pub frame_delay: Option<f32>,
+	/// Whether to merge consecutive identical frames of copied states
+	pub dedupe_frames: bool,
+	/// When set, only this direction of states already in the target is
+	/// replaced
+	pub dir: Option<Direction>,
+	/// The directory to write the temporary output file to, instead of the
+	/// target's own directory
+	pub temp_dir: Option<PathBuf>,
+	/// A file to write a JSON report of the copy to
+	pub report_file: Option<PathBuf>,
+	/// Whether to remove hotspots that can't be copied intact, rather than
+	/// warning about them
+	pub strip_invalid_hotspots: bool,
+	/// Whose hotspot a replaced state ends up with
+	pub hotspot_strategy: HotspotStrategy,
+	/// Whether replacements keep the delays, loop flag, and rewind flag of the
+	/// states they replace
+	pub keep_target_metadata: bool,
+	/// Whether to fail instead of replacing a state with one of a different
+	/// size
+	pub fail_on_dimension_change: bool,
+	/// A file to write the names of the target's states to after the copy
+	pub emit_states_file: Option<PathBuf>,
+	/// A DMI file to write just the added and replaced states to
+	pub emit_patch: Option<PathBuf>,
+	/// A file to write the hashes of the saved files to
+	pub write_checksums: Option<PathBuf>,
+	/// A file to append a JSON line describing the copy to
+	pub append_log: Option<PathBuf>,
+	/// The most frames a copied state may have
+	pub max_frames: Option<u32>,
+	/// When set, frames past this many are removed from copied states
+	pub max_frames_keep: Option<u32>,
+	/// Whether to remove frames by sampling evenly rather than truncating
+	pub sample_frames: bool,
+	/// The most states that may be copied at once
+	pub max_copies: Option<usize>,
+	/// Where in the target to insert new states, rather than at the end
+	pub at_index: Option<usize>,
+	/// How much of the copy's progress is printed
+	pub verbosity: Verbosity,
+	/// How the copy's progress is printed
+	pub log_format: LogFormat,
+	/// Whether both files are gzip-compressed, regardless of their
+	/// extensions
+	pub gzip: bool,
+	/// Only copy animated or only copy static states
+	pub animation_filter: Option<AnimationFilter>,
+	/// Whether to save the target in a canonical, reproducible form
+	pub canonical: bool,
+	/// Whether to only report what would be copied, without saving
+	pub dry_run: bool,
+	/// The icon size both files must have
+	pub icon_size: Option<(u32, u32)>,
+	/// Whether to refuse to copy unless every file has icons of the same
+	/// size, listing the files by size if they don't
+	pub strict_dimensions: bool,
+	/// A substitution applied to the names of copied states
+	pub rename_with_regex: Option<RegexRename>,
+	/// A file mapping the names of copied states to new names, applied
+	/// before `rename_with_regex`
+	pub rename_map: Option<PathBuf>,
+	/// Whether to fail once the copy is done if any warnings were printed
+	pub warnings_as_errors: bool,
+	/// Whether to skip new states whose content is already in the target
+	/// under another name
+	pub skip_if_content_exists: bool,
+	/// When set, the requested states that matched nothing in the source are
+	/// listed after the copy, in this format
+	pub list_unmatched: Option<OutputFormat>,
+	/// What to do when a requested state isn't in the source
+	pub on_missing_source: MissingSourcePolicy,
+	/// Whether to leave existing states alone if the target was modified
+	/// more recently than the source they're copied from
+	pub no_clobber_newer: bool,
+	/// How to handle replacing a state with a different frame count. Without
+	/// one, replacing such a state is an error.
+	pub match_frames: Option<FrameMatching>,
+	/// The name to give the source's default state in the target
+	pub default_as: Option<String>,
+	/// Whether every state in the source is selected, rather than only the
+	/// requested ones
+	pub all: bool,
+	/// Whether `all` selects the source's default state too
+	pub include_default_state: bool,
+	/// How many times to retry writing the target after a transient failure
+	pub retries: u32,
+	/// Whether a state that fails to copy is skipped, rather than stopping the
+	/// whole copy
+	pub skip_errors: bool,
+	/// Whether to write through a symlinked target, rather than replacing the
+	/// link with a regular file
+	pub follow_symlinks: bool,
+	/// Only copy the selected states with these frame and direction counts
+	pub structure_filter: StructureFilter,
+	/// What to do with states that already exist in the target
+	pub on_conflict: ConflictPolicy,
+	/// Which source wins when several have a state with the same name
+	pub source_priority: SourcePriority,
+	/// How the requested state names are matched
+	pub match_mode: MatchMode,
+	/// How state names are canonicalized before they're matched, both
+	/// against the requested names and against the target's states
+	pub normalize_names: Vec<NameNormalization>,
+	/// Whether to list what was compared for states found to be identical
+	pub explain_identical: bool,
+	/// Whether to show how each replaced state's fields change
+	pub explain_diff: bool,
+	/// Whether sizes are printed in KiB and MiB, rather than bytes
+	pub human: bool,
+	/// Fields left out when deciding whether a state is identical to the
+	/// target's
+	pub compare_ignoring: Vec<IgnorableField>,
+	/// The number of directions to reshape copied states to
+	pub set_dirs: Option<u8>,
+	/// The direction kept when collapsing states to a single direction
+	pub keep_dir: Direction,
+	/// When set, every frame of copied states is shifted by this many pixels
+	/// right and down
+	pub offset: Option<(i32, i32)>,
+	/// What fills the pixels uncovered by `offset`
+	pub offset_mode: OffsetMode,
+	/// When set, pixels of copied states with less alpha than this are made
+	/// fully transparent
+	pub alpha_threshold: Option<u8>,
+	/// A key color whose pixels are made fully transparent in copied states
+	pub mask_color: Option<[u8; 3]>,
+	/// How far each channel of a pixel can be from `mask_color` and still be
+	/// masked
+	pub mask_tolerance: u8,
+	/// A directory to cache decoded source files in
+	pub cache_dir: Option<PathBuf>,
+	/// Whether to recover what states can be read from damaged sources,
+	/// rather than failing
+	pub lenient: bool,
+	/// Whether sources may be http URLs to download
+	pub allow_remote: bool,
+	/// How long a remote source may take to download before it's given up on
+	pub timeout: Duration,
 }
 
 /// Represents all possible ways to provide arguments
@@ -20,12 +284,17 @@ pub struct DmiCopyArgs {
 #[command(
 	about = "Copy icon states between DMI files",
 	after_help = "EXAMPLES:\n    Natural syntax:\n        dmi-copy state1 state2 state3 from \
-	              original.dmi to target.dmi\n\n    Traditional syntax:\n        dmi-copy --from \
+	              original.dmi to target.dmi\n        dmi-copy from original.dmi to target.dmi \
+	              state1 state2 state3\n\n    Traditional syntax:\n        dmi-copy --from \
 	              original.dmi --to target.dmi --state state1,state2,state3\n        dmi-copy \
 	              --from original.dmi --to target.dmi --state state1 --state state2",
-	help_template = "{about}\n\nUSAGE:\n    Natural syntax:  {name} <STATES>... from <FROM> to \
-	                 <TO>\n    Flag syntax:    {name} --from <FROM> --to <TO> --state \
-	                 <STATES>...\n\nOPTIONS:\n{options}\n\n{after-help}"
+	help_template = "{about}\n\nUSAGE:\n    Natural syntax:  {name} <STATES>... from <FROM>... to \
+	                 <TO>\n                     {name} from <FROM>... to <TO> <STATES>...\n    \
+	                 Flag syntax:    {name} --from <FROM> --to <TO> --state <STATES>...\n                     \
+	                 {name} --from <FROM> --to <TO> -- <STATES>...\n    \
+	                 Subcommands:     {name} <COMMAND> \
+	                 [ARGS]...\n\nCOMMANDS:\n{subcommands}\n\nOPTIONS:\n{options}\n\n{after-help}",
+	args_conflicts_with_subcommands = true
 )]
 struct CliArgs {
 	/// Non-flag arguments for natural syntax
@@ -37,23 +306,33 @@ struct CliArgs {
     )]
 	natural_args: Vec<String>,
 
-	/// Source DMI file (traditional syntax)
+	/// Source DMI files (traditional syntax)
 	#[arg(
         long = "from",
         value_name = "FILE",
         value_parser = value_parser!(PathBuf),
-        requires_all = &["to_flag", "state_flag"],
+        action = ArgAction::Append,
         id = "from_flag",
-        help = "The source .dmi file to copy states from"
+        help = "The source .dmi file to copy states from (can be given more than once)"
     )]
-	from: Option<PathBuf>,
+	from: Option<Vec<PathBuf>>,
+
+	/// Directory to look for sources in
+	#[arg(
+		long = "source-dir",
+		value_name = "DIR",
+		value_parser = value_parser!(PathBuf),
+		env = "DMI_COPY_SOURCE_DIR",
+		help = "Look for relative source files in DIR when they don't exist in the current \
+		        directory"
+	)]
+	source_dir: Option<PathBuf>,
 
 	/// Target DMI file (traditional syntax)
 	#[arg(
         long = "to",
         value_name = "FILE",
         value_parser = value_parser!(PathBuf),
-        requires_all = &["from_flag", "state_flag"],
         id = "to_flag",
         help = "The target .dmi file to copy states into"
     )]
@@ -68,10 +347,707 @@ struct CliArgs {
         action = ArgAction::Append,
         requires_all = &["from_flag", "to_flag"],
         id = "state_flag",
-        help = "Icon states to copy (can be comma-separated)"
+        help = "Icon states to copy (can be comma-separated, prefix with ! to exclude)"
     )]
 	states: Option<Vec<Vec<String>>>,
 
+	/// Icon states to copy, given after `--` (traditional syntax)
+	#[arg(
+		value_name = "STATES",
+		last = true,
+		requires_all = &["from_flag", "to_flag"],
+		conflicts_with = "natural_args",
+		help = "Icon states to copy, after --, as an alternative to --state"
+	)]
+	trailing_states: Vec<String>,
+
+	/// Minimum frame count, exclusive
+	#[arg(
+		long = "frames-gt",
+		value_name = "N",
+		help = "Only copy states with more than N frames, selecting every such state if no names \
+		        are given"
+	)]
+	frames_gt: Option<u32>,
+
+	/// Maximum frame count, exclusive
+	#[arg(
+		long = "frames-lt",
+		value_name = "N",
+		help = "Only copy states with fewer than N frames, selecting every such state if no names \
+		        are given"
+	)]
+	frames_lt: Option<u32>,
+
+	/// Exact frame count
+	#[arg(
+		long = "frames",
+		value_name = "N",
+		help = "Only copy states with exactly N frames, selecting every such state if no names \
+		        are given"
+	)]
+	frames: Option<u32>,
+
+	/// Exact direction count
+	#[arg(
+		long = "dirs",
+		value_name = "N",
+		help = "Only copy states with exactly N directions, selecting every such state if no \
+		        names are given"
+	)]
+	dirs: Option<u8>,
+
+	/// Copy every state in the source
+	#[arg(
+		long = "all",
+		conflicts_with = "from_default",
+		help = "Copy every state in the source, except any excluded with !NAME and the default \
+		        state"
+	)]
+	all: bool,
+
+	/// Copy the default state with --all
+	#[arg(
+		long = "include-default-state",
+		requires = "all",
+		conflicts_with = "exclude_default_state",
+		help = "Copy the source's default (unnamed) state with --all too"
+	)]
+	include_default_state: bool,
+
+	/// Leave the default state alone with --all
+	#[arg(
+		long = "exclude-default-state",
+		requires = "all",
+		help = "Don't copy the source's default (unnamed) state with --all, which is the default"
+	)]
+	exclude_default_state: bool,
+
+	/// Copy the source's default state
+	#[arg(
+		long = "from-default",
+		help = "Copy the source's default (unnamed) state, along with any other given states"
+	)]
+	from_default: bool,
+
+	/// New name for the source's default state
+	#[arg(
+		long = "as",
+		value_name = "NAME",
+		requires = "from_default",
+		help = "Copy the default state into the target under NAME, with --from-default"
+	)]
+	default_as: Option<String>,
+
+	/// External command to transform copied frames with
+	#[arg(
+		long = "transform-cmd",
+		value_name = "CMD",
+		help = "Pipe each frame of the copied states through CMD (PNG on stdin, PNG on stdout)"
+	)]
+	transform_cmd: Option<String>,
+
+	/// Round delays to a common time base
+	#[arg(
+		long = "normalize-delays",
+		help = "Round the copied states' delays to whole ticks, or to multiples of --tick; \
+		        combine with --dedupe-frames to merge frames that end up identical"
+	)]
+	normalize_delays: bool,
+
+	/// Time base for normalized delays
+	#[arg(
+		long = "tick",
+		value_name = "MS",
+		requires = "normalize_delays",
+		value_parser = parse_tick,
+		default_value = "100",
+		help = "The time base --normalize-delays rounds to, in milliseconds"
+	)]
+	tick: f32,
+
+	/// Uniform delay for every frame
+	#[arg(
+		long = "frame-delay",
+		value_name = "TICKS",
+		value_parser = parse_delay,
+		conflicts_with = "normalize_delays",
+		help = "Give every frame of the copied animated states a delay of TICKS"
+	)]
+	frame_delay: Option<f32>,
+
+	/// Merge consecutive identical frames
+	#[arg(
+		long = "dedupe-frames",
+		help = "Merge consecutive identical frames of the copied states, combining their delays"
+	)]
+	dedupe_frames: bool,
+
+	/// Only replace a single direction of existing states
+	#[arg(
+		long = "dir",
+		value_name = "DIR",
+		value_enum,
+		help = "Only replace the frames facing DIR (and the delays) of states already in the \
+		        target"
+	)]
+	dir: Option<Direction>,
+
+	/// Directory for the temporary output file
+	#[arg(
+		long = "temp-dir",
+		value_name = "DIR",
+		value_parser = value_parser!(PathBuf),
+		help = "Write the temporary output file to DIR instead of next to the target"
+	)]
+	temp_dir: Option<PathBuf>,
+
+	/// Directory to cache decoded sources in
+	#[arg(
+		long = "cache-dir",
+		value_name = "DIR",
+		value_parser = value_parser!(PathBuf),
+		help = "Cache decoded source files in DIR, so unchanged sources load faster next time"
+	)]
+	cache_dir: Option<PathBuf>,
+
+	/// Recover damaged sources
+	#[arg(
+		long = "lenient",
+		visible_alias = "lenient-load",
+		help = "Recover the readable states of damaged sources instead of failing, warning about \
+		        every state that had to be skipped"
+	)]
+	lenient: bool,
+
+	/// Allow sources to be URLs
+	#[arg(
+		long = "allow-remote",
+		help = "Allow sources to be http:// URLs, which are downloaded into memory"
+	)]
+	allow_remote: bool,
+
+	/// Network timeout for remote sources
+	#[arg(
+		long = "timeout",
+		value_name = "SECONDS",
+		requires = "allow_remote",
+		value_parser = value_parser!(u64).range(1..),
+		default_value_t = 30,
+		help = "Give up on a remote source that takes longer than SECONDS to download"
+	)]
+	timeout: u64,
+
+	/// File to write a checksum manifest to
+	#[arg(
+		long = "write-checksums",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "After saving, write the SHA-256 hash of the target to FILE, in sha256sum's format"
+	)]
+	write_checksums: Option<PathBuf>,
+
+	/// Checksum manifest to verify
+	#[arg(
+		long = "verify-checksums",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		conflicts_with_all = &["natural_args", "from_flag", "to_flag", "state_flag"],
+		help = "Check that every file listed in a --write-checksums manifest still matches its \
+		        hash, without copying"
+	)]
+	verify_checksums: Option<PathBuf>,
+
+	/// File to write a JSON report to
+	#[arg(
+		long = "report-file",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "Write a JSON report of the added, replaced, identical, and skipped states to FILE"
+	)]
+	report_file: Option<PathBuf>,
+
+	/// File to list the target's states in
+	#[arg(
+		long = "emit-states-file",
+		visible_alias = "output-states-file",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "Write the sorted names of the target's states after the copy to FILE, one per \
+		        line, in the format check-inventory reads"
+	)]
+	emit_states_file: Option<PathBuf>,
+
+	/// Drop hotspots that can't be copied intact
+	#[arg(
+		long = "strip-invalid-hotspots",
+		help = "Remove the hotspots of copied states that have per-frame hotspots, or hotspots \
+		        outside their icons, rather than warning about them"
+	)]
+	strip_invalid_hotspots: bool,
+
+	/// Whose hotspot replaced states keep
+	#[arg(
+		long = "hotspot-strategy",
+		value_name = "STRATEGY",
+		value_enum,
+		default_value_t,
+		help = "Whose hotspot a replaced state ends up with: the source's, the target's, or none. \
+		        A state only has one hotspot, so they can't be combined"
+	)]
+	hotspot_strategy: HotspotStrategy,
+
+	/// Take only the images of replaced states from the source
+	#[arg(
+		long = "keep-target-metadata",
+		help = "When replacing a state, only take its images from the source, keeping the \
+		        target's delays, loop, and rewind"
+	)]
+	keep_target_metadata: bool,
+
+	/// Fail if a replacement changes a state's size
+	#[arg(
+		long = "fail-on-dimension-change",
+		help = "Fail instead of replacing a state with one whose images are a different size"
+	)]
+	fail_on_dimension_change: bool,
+
+	/// DMI file to write the changed states to
+	#[arg(
+		long = "emit-patch",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "Also write just the added and replaced states to FILE, a standalone DMI with the \
+		        target's dimensions that can be copied from to apply the same changes elsewhere"
+	)]
+	emit_patch: Option<PathBuf>,
+
+	/// File to append an audit log line to
+	#[arg(
+		long = "append-log",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "Append a JSON line recording the time, user, files, and affected states of this copy \
+		        to FILE"
+	)]
+	append_log: Option<PathBuf>,
+
+	/// Maximum number of copied states
+	#[arg(
+		long = "max-copies",
+		value_name = "N",
+		help = "Refuse to copy anything if more than N states are selected"
+	)]
+	max_copies: Option<usize>,
+
+	/// Position to insert new states at
+	#[arg(
+		long = "at-index",
+		value_name = "N",
+		// Sorting the target would move the states right back.
+		conflicts_with = "canonical",
+		help = "Insert new states at position N in the target, in source order, rather than at \
+		        the end (clamped to the number of states)"
+	)]
+	at_index: Option<usize>,
+
+	/// Maximum frame count of a copied state
+	#[arg(
+		long = "max-frames",
+		value_name = "N",
+		value_parser = value_parser!(u32),
+		help = "Refuse to copy any state with more than N frames in the source, even if \
+		        --max-frames-keep would cut it down"
+	)]
+	max_frames: Option<u32>,
+
+	/// Frame count to cut copied states down to
+	#[arg(
+		long = "max-frames-keep",
+		value_name = "N",
+		value_parser = value_parser!(u32).range(1..),
+		help = "Cut copied states with more than N frames down to their first N frames"
+	)]
+	max_frames_keep: Option<u32>,
+
+	/// Sample frames evenly instead of truncating
+	#[arg(
+		long = "sample",
+		requires = "max_frames_keep",
+		help = "With --max-frames-keep, keep every Nth frame instead of the first frames, merging \
+		        the delays of dropped frames so the animation's length is unchanged"
+	)]
+	sample: bool,
+
+	/// Print nothing but errors
+	#[arg(
+		short = 'q',
+		long = "quiet",
+		help = "Don't print anything except errors"
+	)]
+	quiet: bool,
+
+	/// Only print the final counts
+	#[arg(
+		long = "summary-only",
+		conflicts_with = "quiet",
+		help = "Only print the number of added, replaced, identical, and skipped states"
+	)]
+	summary_only: bool,
+
+	/// Print the states grouped by outcome at the end
+	#[arg(
+		long = "group-output",
+		conflicts_with_all = &["quiet", "summary_only"],
+		help = "Instead of printing each state as it's copied, list them at the end grouped by \
+		        whether they were added, replaced, identical, skipped, or failed"
+	)]
+	group_output: bool,
+
+	/// How progress is printed
+	#[arg(
+		long = "log-format",
+		value_name = "FORMAT",
+		value_enum,
+		default_value_t,
+		help = "Print progress as plain text, or as structured tracing events with the file, \
+		        state, and action as fields"
+	)]
+	log_format: LogFormat,
+
+	/// Treat both files as gzip-compressed
+	#[arg(
+		long = "gzip",
+		help = "Treat both files as gzip-compressed, even without a .gz extension"
+	)]
+	gzip: bool,
+
+	/// Only copy animated states
+	#[arg(
+		long = "only-animated",
+		conflicts_with = "only_static",
+		help = "Only copy the selected states that have more than one frame"
+	)]
+	only_animated: bool,
+
+	/// Only copy static states
+	#[arg(
+		long = "only-static",
+		help = "Only copy the selected states that have a single frame"
+	)]
+	only_static: bool,
+
+	/// Save in a reproducible form
+	#[arg(
+		long = "canonical",
+		help = "Sort the target's states by name and write its metadata in a reproducible order"
+	)]
+	canonical: bool,
+
+	/// Don't save the target
+	#[arg(
+		long = "dry-run",
+		help = "Report what would be copied without writing to the target"
+	)]
+	dry_run: bool,
+
+	/// Required icon size
+	#[arg(
+		long = "icon-size",
+		value_name = "WxH",
+		value_parser = parse_icon_size,
+		help = "Refuse to copy unless both files have icons of this size, e.g. 32x32"
+	)]
+	icon_size: Option<(u32, u32)>,
+
+	/// Require every file to have the same icon size
+	#[arg(
+		long = "strict-dimensions",
+		help = "Refuse to copy unless every source and the target have icons of the same size, \
+		        --icon-size if given, listing the files by size otherwise"
+	)]
+	strict_dimensions: bool,
+
+	/// Regex substitution to rename copied states with
+	#[arg(
+		long = "rename-with-regex",
+		value_name = "s/PATTERN/REPLACEMENT/",
+		value_parser = RegexRename::parse,
+		help = "Rename copied states with a sed-style substitution, e.g. 's/^old_(.*)$/new_$1/'"
+	)]
+	rename_with_regex: Option<RegexRename>,
+
+	/// File of old=new state names to rename copied states with
+	#[arg(
+		long = "rename-map",
+		value_name = "FILE",
+		value_parser = value_parser!(PathBuf),
+		help = "Rename copied states with a file of old=new lines, or tab- or comma-separated \
+		        pairs; unmapped states keep their names"
+	)]
+	rename_map: Option<PathBuf>,
+
+	/// What to do with states that already exist in the target
+	#[arg(
+		long = "on-conflict",
+		value_name = "POLICY",
+		value_enum,
+		conflicts_with_all = &["if_target_missing_only", "if_source_differs"],
+		help = "What to do with states that already exist in the target [default: differs]"
+	)]
+	on_conflict: Option<ConflictPolicy>,
+
+	/// Shorthand for --on-conflict missing-only
+	#[arg(
+		long = "if-target-missing-only",
+		conflicts_with = "if_source_differs",
+		help = "Only add states the target doesn't have, same as --on-conflict missing-only"
+	)]
+	if_target_missing_only: bool,
+
+	/// Shorthand for --on-conflict differs
+	#[arg(
+		long = "if-source-differs",
+		help = "Only replace states whose content differs, same as --on-conflict differs"
+	)]
+	if_source_differs: bool,
+
+	/// How to handle frame count differences when replacing
+	#[arg(
+		long = "match-frames",
+		value_name = "MODE",
+		value_enum,
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "error",
+		help = "When a replaced state's frame count differs from the target's, fail (error, the \
+		        default), repeat the source's last frame to match (pad), or replace it anyway \
+		        (replace)"
+	)]
+	match_frames: Option<FrameMatching>,
+
+	/// List the source's states instead of copying
+	#[arg(
+		long = "list-source-states",
+		requires = "from_flag",
+		help = "Print the name, dirs, frames, and delays of every state in the --from file as \
+		        JSON, without copying"
+	)]
+	list_source_states: bool,
+
+	/// Number of times to retry a failed write
+	#[arg(
+		long = "retry",
+		value_name = "N",
+		default_value_t = 0,
+		help = "Retry writing the target up to N times, with exponential backoff, if it fails for \
+		        a transient reason like a network filesystem timing out"
+	)]
+	retries: u32,
+
+	/// Skip states that fail, rather than stopping
+	#[arg(
+		long = "skip-errors",
+		help = "If a state fails to transform or merge, skip it and copy the rest, exiting with \
+		        an error at the end"
+	)]
+	skip_errors: bool,
+
+	/// Write through symlinked targets
+	#[arg(
+		long = "follow-symlinks",
+		help = "If the target is a symlink, write to the file it points to and keep the link. By \
+		        default, the link itself is replaced with a regular file"
+	)]
+	follow_symlinks: bool,
+
+	/// Which source wins when several have the same state
+	#[arg(
+		long = "source-priority",
+		value_name = "PRIORITY",
+		value_enum,
+		default_value_t,
+		help = "Which --from file wins when several have a state with the same name"
+	)]
+	source_priority: SourcePriority,
+
+	/// How state names are matched
+	#[arg(
+		long = "match",
+		value_name = "MODE",
+		value_enum,
+		default_value_t,
+		help = "How requested states are matched against the source's: by exact name, by \
+		        substring, or as globs or regular expressions"
+	)]
+	match_mode: MatchMode,
+
+	/// Canonicalize state names before matching them
+	#[arg(
+		long = "normalize-names",
+		value_name = "NORMALIZATIONS",
+		value_enum,
+		value_delimiter = ',',
+		help = "Treat state names that only differ by whitespace, case, or separators as the \
+		        same, when selecting states and finding them in the target"
+	)]
+	normalize_names: Vec<NameNormalization>,
+
+	/// Direction count to reshape copied states to
+	#[arg(
+		long = "set-dirs",
+		value_name = "N",
+		value_parser = PossibleValuesParser::new(["1", "4", "8"])
+			.map(|dirs| dirs.parse::<u8>().expect("only numbers are possible")),
+		help = "Reshape copied states to have N directions, duplicating directions when \
+		        expanding and dropping them when collapsing"
+	)]
+	set_dirs: Option<u8>,
+
+	/// Direction to keep when collapsing to one direction
+	#[arg(
+		long = "keep-dir",
+		value_name = "DIR",
+		value_enum,
+		default_value_t = Direction::South,
+		help = "The direction to keep when collapsing states with --set-dirs 1"
+	)]
+	keep_dir: Direction,
+
+	/// Pixel shift for copied frames
+	#[arg(
+		long = "offset",
+		value_name = "DX,DY",
+		value_parser = parse_offset,
+		allow_hyphen_values = true,
+		help = "Shift every frame of the copied states DX pixels right and DY pixels down, \
+		        keeping their size"
+	)]
+	offset: Option<(i32, i32)>,
+
+	/// How to fill pixels uncovered by --offset
+	#[arg(
+		long = "offset-mode",
+		value_name = "MODE",
+		value_enum,
+		default_value_t,
+		requires = "offset",
+		help = "What fills the pixels uncovered by --offset"
+	)]
+	offset_mode: OffsetMode,
+
+	/// Alpha below which copied pixels are cleared
+	#[arg(
+		long = "alpha-threshold",
+		value_name = "ALPHA",
+		value_parser = value_parser!(u8).range(1..),
+		help = "Make pixels of copied states with an alpha below ALPHA (1-255) fully transparent, \
+		        to clean up faint fringes"
+	)]
+	alpha_threshold: Option<u8>,
+
+	/// Key color to make transparent
+	#[arg(
+		long = "mask-color",
+		value_name = "RRGGBB",
+		value_parser = parse_color,
+		help = "Make pixels of copied states with the color RRGGBB fully transparent, for sprites \
+		        that use a key color like FF00FF instead of alpha"
+	)]
+	mask_color: Option<[u8; 3]>,
+
+	/// How close a color has to be to the mask color
+	#[arg(
+		long = "mask-tolerance",
+		value_name = "N",
+		default_value_t = 0,
+		requires = "mask_color",
+		help = "With --mask-color, also mask colors whose channels are each within N of it"
+	)]
+	mask_tolerance: u8,
+
+	/// Explain why states are considered identical
+	#[arg(
+		long = "explain-identical",
+		help = "For states identical in both files, list every aspect that was compared"
+	)]
+	explain_identical: bool,
+
+	/// Show the changes to replaced states
+	#[arg(
+		long = "explain-diff",
+		help = "For replaced states, show how each changing field goes from the target's value to \
+		        the source's, e.g. 'frames: 4 → 6'; pairs well with --dry-run"
+	)]
+	explain_diff: bool,
+
+	/// Print sizes in binary units
+	#[arg(
+		long = "human",
+		help = "Print the sizes compared by --on-conflict smaller in KiB and MiB, rather than \
+		        bytes"
+	)]
+	human: bool,
+
+	/// Fields to leave out of comparisons
+	#[arg(
+		long = "compare-ignoring",
+		value_name = "FIELDS",
+		value_enum,
+		value_delimiter = ',',
+		help = "Treat states that only differ in these fields as identical, leaving the target's \
+		        alone (can be comma-separated)"
+	)]
+	compare_ignoring: Vec<IgnorableField>,
+
+	/// Treat warnings as errors
+	#[arg(
+		long = "warnings-as-errors",
+		help = "Exit with an error after copying if any warnings were printed"
+	)]
+	warnings_as_errors: bool,
+
+	/// List requested states that matched nothing
+	#[arg(
+		long = "list-unmatched",
+		value_name = "FORMAT",
+		value_enum,
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "text",
+		help = "After copying, list the requested states that matched nothing in the source, as \
+		        text or a JSON array"
+	)]
+	list_unmatched: Option<OutputFormat>,
+
+	/// What to do with requested states missing from the source
+	#[arg(
+		long = "on-missing-source",
+		value_name = "POLICY",
+		value_enum,
+		default_value_t,
+		help = "What to do when a requested state isn't in the source: fail before copying \
+		        (error), warn and copy the rest (warn, which --warnings-as-errors turns into a \
+		        failure), or copy the rest quietly (ignore)"
+	)]
+	on_missing_source: MissingSourcePolicy,
+
+	/// Skip new states whose content is already in the target
+	#[arg(
+		long = "skip-if-content-exists",
+		help = "Don't add a state if a state with exactly the same content is already in the \
+		        target, under any name"
+	)]
+	skip_if_content_exists: bool,
+
+	/// Don't replace states in a newer target
+	#[arg(
+		long = "no-clobber-newer",
+		help = "Don't replace states copied from a source that's older than the target, only add \
+		        new ones"
+	)]
+	no_clobber_newer: bool,
+
 	/// Generate shell completion script
 	#[arg(
         long = "generate-completion",
@@ -80,6 +1056,391 @@ struct CliArgs {
         help = "Generate completion script for specified shell"
     )]
 	generate_completion: Option<Shell>,
+
+	/// Print the JSON Schema of a structured output
+	#[arg(
+		long = "print-schema",
+		value_name = "OUTPUT",
+		value_enum,
+		help = "Print the JSON Schema of a structured output, to validate it against"
+	)]
+	print_schema: Option<SchemaKind>,
+
+	#[command(subcommand)]
+	command: Option<SubCommand>,
+}
+
+/// Subcommands other than copying
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+	/// Check that two DMI files contain identical icon states, in any order
+	AssertEq(AssertEqArgs),
+	/// Print the dimensions, state count, and frame count of a DMI file
+	Info(InfoArgs),
+	/// Render the first frame of every state into a labeled PNG grid
+	Thumbnail(ThumbnailArgs),
+	/// Remove the hotspot from every state in a DMI file, in place
+	StripHotspots(StripHotspotsArgs),
+	/// Exit successfully if a DMI file has the given states, and with 1 if not
+	Probe(ProbeArgs),
+	/// Print the raw metadata text of a DMI file, without parsing it
+	DumpMetadata(DumpMetadataArgs),
+	/// Load and re-save a DMI file in place, for a consistent encoding
+	Normalize(NormalizeArgs),
+	/// Exit successfully if a DMI file has exactly the states listed in an
+	/// inventory file, and with 1 if not
+	CheckInventory(CheckInventoryArgs),
+	/// Exit successfully if no state of a DMI file uses more than a number of
+	/// distinct colors, and with 1 if any do
+	CheckColors(CheckColorsArgs),
+	/// List the states with identical content, under the same name or not,
+	/// across every DMI file in a directory and its subdirectories
+	FindDuplicates(FindDuplicatesArgs),
+	/// Add and replace exactly the states listed in a --report-file, such as
+	/// one written by a reviewed --dry-run. States are looked up by the names
+	/// in the report, so reports of copies that renamed states can't be
+	/// applied. States are copied as they are in the source, so options that
+	/// changed them during the dry run, like --offset or
+	/// --keep-target-metadata, aren't replayed
+	ApplyPatch(ApplyPatchArgs),
+	/// Copy states missing from either of two DMI files into the other, so
+	/// they end up with the same states
+	Sync(SyncArgs),
+	/// Step through the states of a DMI file with previews, marking states to
+	/// copy into a target
+	Browse(BrowseArgs),
+	/// Draw a state's first frame in the terminal, or play its animation with
+	/// --animate. Colors are drawn with truecolor escape codes, falling back to
+	/// ASCII art when stdout isn't a terminal
+	Preview(PreviewArgs),
+	/// Save a DMI file to a temporary file and load it again, exiting with 1
+	/// and listing the states that changed if it doesn't match the original
+	Selftest(SelftestArgs),
+	/// Check that DMI files load and have no states that couldn't be saved,
+	/// exiting with 1 and listing the problems if any don't. With
+	/// --strict-png, also check their PNG chunks for bad CRCs and other
+	/// problems that strict decoders reject
+	Validate(ValidateArgs),
+	/// Write every image of some states to PNG files named like
+	/// walk_south_0.png, with a JSON file per state describing its dirs,
+	/// frames, and delays
+	DumpFrames(DumpFramesArgs),
+	/// Rebuild a state from the PNG files and JSON file written by
+	/// dump-frames, and add it to a DMI file
+	AssembleFrames(AssembleFramesArgs),
+}
+
+/// Arguments for comparing a DMI file against an expected one
+#[derive(Debug, Args)]
+pub struct AssertEqArgs {
+	/// The DMI file to check
+	pub actual: PathBuf,
+	/// The DMI file it is expected to match
+	pub expected: PathBuf,
+}
+
+/// Arguments for summarizing a DMI file
+#[derive(Debug, Args)]
+pub struct InfoArgs {
+	/// The DMI file to summarize
+	pub file: PathBuf,
+	/// The output format
+	#[arg(long = "format", value_enum, default_value_t)]
+	pub format: OutputFormat,
+}
+
+/// Arguments for checking whether a DMI file has some states
+#[derive(Debug, Args)]
+pub struct ProbeArgs {
+	/// The DMI file to look in
+	pub file: PathBuf,
+	/// The states to look for
+	#[arg(
+		long = "state",
+		alias = "states",
+		value_name = "STATE",
+		value_delimiter = ',',
+		required = true,
+		help = "States to look for (can be comma-separated)"
+	)]
+	pub states: Vec<String>,
+	/// Require every state to exist (the default)
+	#[arg(long = "all-present", conflicts_with = "any_present")]
+	pub all_present: bool,
+	/// Require at least one state to exist
+	#[arg(long = "any-present")]
+	pub any_present: bool,
+}
+
+/// Arguments for printing the raw metadata of a DMI file
+#[derive(Debug, Args)]
+pub struct DumpMetadataArgs {
+	/// The DMI file to read
+	pub file: PathBuf,
+}
+
+/// Which file wins when a state differs between two synced files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SyncPreference {
+	/// The first file's state wins
+	A,
+	/// The second file's state wins
+	B,
+	/// The state from the most recently modified file wins
+	Newer,
+}
+
+/// Arguments for syncing two DMI files
+#[derive(Debug, Args)]
+pub struct SyncArgs {
+	/// The first DMI file
+	pub a: PathBuf,
+	/// The second DMI file
+	pub b: PathBuf,
+	/// Which file wins when a state is in both but differs, and --base can't
+	/// tell which changed it; without it, such states are an error
+	#[arg(long = "prefer", value_name = "FILE", value_enum)]
+	pub prefer: Option<SyncPreference>,
+	/// A file recording each state's content as of the last sync, so that a
+	/// state changed in only one file since then goes from that file without
+	/// --prefer. It's created if it's missing, and updated after every sync
+	#[arg(long = "base", value_name = "FILE")]
+	pub base: Option<PathBuf>,
+	/// Show what would be synced, without writing anything
+	#[arg(long = "dry-run")]
+	pub dry_run: bool,
+}
+
+/// Arguments for extracting the frames of a DMI file's states
+#[derive(Debug, Args)]
+pub struct DumpFramesArgs {
+	/// The DMI file to read
+	pub file: PathBuf,
+	/// The states to extract, defaulting to every state
+	#[arg(long = "state", value_name = "STATE", value_delimiter = ',')]
+	pub states: Vec<String>,
+	/// The directory to write the frames to, which is created if needed
+	#[arg(long = "out-dir", value_name = "DIR")]
+	pub out_dir: PathBuf,
+}
+
+/// Arguments for rebuilding a state from its dumped frames
+#[derive(Debug, Args)]
+pub struct AssembleFramesArgs {
+	/// The JSON file describing the state, as written by dump-frames
+	#[arg(long = "meta", value_name = "FILE")]
+	pub meta: PathBuf,
+	/// The directory holding the frames, defaulting to the JSON file's
+	#[arg(long = "in-dir", value_name = "DIR")]
+	pub in_dir: Option<PathBuf>,
+	/// What to do if the target already has the state
+	#[arg(
+		long = "on-conflict",
+		value_name = "POLICY",
+		value_enum,
+		default_value_t
+	)]
+	pub on_conflict: ConflictPolicy,
+	/// When a replaced state's frame count differs from the target's, fail
+	/// (error, the default), repeat the source's last frame to match (pad),
+	/// or replace it anyway (replace)
+	#[arg(
+		long = "match-frames",
+		value_name = "MODE",
+		value_enum,
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "error"
+	)]
+	pub match_frames: Option<FrameMatching>,
+	/// The DMI file to add the state to
+	pub target: PathBuf,
+}
+
+/// Arguments for checking that a DMI file round-trips
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+	/// The DMI file to check
+	pub file: PathBuf,
+}
+
+/// Arguments for validating DMI files
+#[derive(Debug, Args)]
+pub struct ValidateArgs {
+	/// The DMI files to check
+	#[arg(required = true)]
+	pub files: Vec<PathBuf>,
+	/// Also check the PNG chunk structure, beyond what decoding needs
+	#[arg(long = "strict-png")]
+	pub strict_png: bool,
+	/// Fail files whose states have more than this many frames in total
+	#[arg(long = "max-total-frames", value_name = "N")]
+	pub max_total_frames: Option<u64>,
+}
+
+/// Arguments for browsing a DMI file interactively
+#[derive(Debug, Args)]
+pub struct BrowseArgs {
+	/// The DMI file to browse
+	pub file: PathBuf,
+	/// The DMI file to copy marked states into, unless another is given when
+	/// copying
+	#[arg(long = "to", value_name = "FILE")]
+	pub to: Option<PathBuf>,
+	/// What to do with marked states the target already has
+	#[arg(
+		long = "on-conflict",
+		value_name = "POLICY",
+		value_enum,
+		default_value_t
+	)]
+	pub on_conflict: ConflictPolicy,
+	/// Fail instead of replacing a state with one of a different size
+	#[arg(long = "fail-on-dimension-change")]
+	pub fail_on_dimension_change: bool,
+	/// When a replaced state's frame count differs from the target's, fail
+	/// (error, the default), repeat the source's last frame to match (pad),
+	/// or replace it anyway (replace)
+	#[arg(
+		long = "match-frames",
+		value_name = "MODE",
+		value_enum,
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "error"
+	)]
+	pub match_frames: Option<FrameMatching>,
+}
+
+/// Arguments for previewing a state in the terminal
+#[derive(Debug, Args)]
+pub struct PreviewArgs {
+	/// The DMI file to read
+	pub file: PathBuf,
+	/// The state to show, defaulting to the first one
+	#[arg(long = "state", value_name = "STATE")]
+	pub state: Option<String>,
+	/// The direction to show
+	#[arg(long = "dir", value_name = "DIR", value_enum, default_value_t = Direction::South)]
+	pub dir: Direction,
+	/// Play the state's animation, until it's done or interrupted
+	#[arg(long = "animate")]
+	pub animate: bool,
+}
+
+/// Arguments for finding duplicate states across a directory
+#[derive(Debug, Args)]
+pub struct FindDuplicatesArgs {
+	/// The directory to search for DMI files
+	#[arg(long = "dir", value_name = "DIR", default_value = ".")]
+	pub dir: PathBuf,
+	/// The output format
+	#[arg(long = "format", value_enum, default_value_t)]
+	pub format: OutputFormat,
+}
+
+/// Arguments for applying a copy's report to a DMI file
+#[derive(Debug, Args)]
+pub struct ApplyPatchArgs {
+	/// The report to apply, as written by --report-file
+	#[arg(long = "patch", value_name = "FILE")]
+	pub patch: PathBuf,
+	/// The DMI file to take the states from, as they are
+	#[arg(long = "from", value_name = "FILE")]
+	pub from: PathBuf,
+	/// The DMI file to apply the changes to, which must be the one the report
+	/// is for
+	pub target: PathBuf,
+}
+
+/// Arguments for checking a DMI file's states against an inventory file
+#[derive(Debug, Args)]
+pub struct CheckInventoryArgs {
+	/// The DMI file to check
+	pub file: PathBuf,
+	/// The inventory file, with one state name per line
+	#[arg(
+		long = "expect",
+		value_name = "FILE",
+		help = "The inventory of state names, one per line, with # for comments and \"\" for the \
+		        default state [default: the DMI's path with a .states extension]"
+	)]
+	pub expect: Option<PathBuf>,
+	/// Allow the DMI file to have states that aren't in the inventory
+	#[arg(long = "subset")]
+	pub subset: bool,
+}
+
+/// Arguments for checking the color counts of a DMI file's states
+#[derive(Debug, Args)]
+pub struct CheckColorsArgs {
+	/// The DMI file to check
+	pub file: PathBuf,
+	/// The most distinct colors a state may use across all of its frames
+	#[arg(long = "max-colors", value_name = "N")]
+	pub max_colors: usize,
+	/// How many of the worst offenders to name in the summary
+	#[arg(long = "top", value_name = "N", default_value_t = 5)]
+	pub top: usize,
+}
+
+/// Arguments for re-encoding a DMI file
+#[derive(Debug, Args)]
+pub struct NormalizeArgs {
+	/// The DMI file to normalize
+	pub file: PathBuf,
+	/// Sort states by name and write metadata in a fixed order
+	#[arg(long = "canonical")]
+	pub canonical: bool,
+	/// How hard to compress the image data
+	#[arg(long = "compression", value_enum, default_value_t)]
+	pub compression: PngCompression,
+	/// Keep ancillary PNG chunks, like tEXt, pHYs, or gAMA, that would
+	/// otherwise be dropped. Chunks that depend on the color type, like tRNS,
+	/// are dropped anyway.
+	#[arg(long = "preserve-chunks")]
+	pub preserve_chunks: bool,
+	/// Print file sizes in KiB and MiB, rather than bytes
+	#[arg(long = "human")]
+	pub human: bool,
+}
+
+/// Arguments for removing every hotspot from a DMI file
+#[derive(Debug, Args)]
+pub struct StripHotspotsArgs {
+	/// The DMI file to strip hotspots from
+	pub file: PathBuf,
+}
+
+/// Arguments for rendering a contact sheet of a DMI file
+#[derive(Debug, Args)]
+pub struct ThumbnailArgs {
+	/// The DMI file to render
+	pub file: PathBuf,
+	/// The PNG file to write the contact sheet to
+	pub output: PathBuf,
+	/// The number of states per row, defaulting to a roughly square grid
+	#[arg(long = "columns", value_parser = value_parser!(u32).range(1..))]
+	pub columns: Option<u32>,
+	/// Don't draw state names under each cell
+	#[arg(long = "no-labels")]
+	pub no_labels: bool,
+}
+
+/// Resolve a relative source path that doesn't exist in the current directory
+/// against the source directory, if it exists there
+fn resolve_source(path: PathBuf, source_dir: Option<&Path>) -> PathBuf {
+	match source_dir {
+		Some(source_dir) if path.is_relative() && !path.exists() && !remote::is_url(&path) => {
+			let resolved = source_dir.join(&path);
+			if resolved.exists() {
+				resolved
+			} else {
+				path
+			}
+		}
+		_ => path,
+	}
 }
 
 /// Parse a comma-separated state argument into individual states
@@ -91,29 +1452,123 @@ fn parse_state_arg(arg: &str) -> Result<Vec<String>, String> {
 		.collect())
 }
 
-impl DmiCopyArgs {
-	/// Parse command line arguments into DmiCopyArgs
+/// Parse a positive number of milliseconds
+fn parse_tick(arg: &str) -> Result<f32, String> {
+	arg.parse::<f32>()
+		.ok()
+		.filter(|tick| tick.is_finite() && *tick > 0.0)
+		.ok_or_else(|| format!("expected a positive number of milliseconds, got '{arg}'"))
+}
+
+/// Parse a positive number of ticks
+fn parse_delay(arg: &str) -> Result<f32, String> {
+	arg.parse::<f32>()
+		.ok()
+		.filter(|delay| delay.is_finite() && *delay > 0.0)
+		.ok_or_else(|| format!("expected a positive number of ticks, got '{arg}'"))
+}
+
+/// Parse an icon size formatted like `32x32`
+fn parse_icon_size(arg: &str) -> Result<(u32, u32), String> {
+	let (width, height) = arg
+		.split_once(['x', 'X'])
+		.ok_or_else(|| format!("expected a size like 32x32, got '{arg}'"))?;
+	let parse = |value: &str| {
+		value
+			.trim()
+			.parse::<u32>()
+			.ok()
+			.filter(|&value| value > 0)
+			.ok_or_else(|| format!("invalid dimension '{value}'"))
+	};
+	Ok((parse(width)?, parse(height)?))
+}
+
+/// Parse a pixel offset formatted like `2,-1`
+fn parse_offset(arg: &str) -> Result<(i32, i32), String> {
+	let (dx, dy) = arg
+		.split_once(',')
+		.ok_or_else(|| format!("expected an offset like 2,-1, got '{arg}'"))?;
+	let parse = |value: &str| {
+		value
+			.trim()
+			.parse::<i32>()
+			.map_err(|_| format!("invalid offset '{value}'"))
+	};
+	Ok((parse(dx)?, parse(dy)?))
+}
+
+/// Parse a color formatted like `FF00FF`, with an optional leading `#`
+fn parse_color(arg: &str) -> Result<[u8; 3], String> {
+	let hex = arg.strip_prefix('#').unwrap_or(arg);
+	let channel = |index: usize| {
+		hex.get(index * 2..index * 2 + 2)
+			.and_then(|channel| u8::from_str_radix(channel, 16).ok())
+	};
+	// from_str_radix allows a sign, so the digits are checked first.
+	let digits = hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit());
+	match (digits, channel(0), channel(1), channel(2)) {
+		(true, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+		_ => Err(format!("expected a color like FF00FF, got '{arg}'")),
+	}
+}
+
+impl Command {
+	/// Parse command line arguments into the requested Command
 	pub fn parse() -> Result<Self> {
-		match CliArgs::try_parse() {
-			Ok(cli) => {
+		match parse_cli(std::env::args_os().collect()) {
+			Ok(mut cli) => {
 				// Handle completion generation if requested
 				if let Some(shell) = cli.generate_completion {
-					print_completions(shell, &mut CliArgs::command());
+					write_completions(shell, &mut std::io::stdout());
+					std::process::exit(0);
+				}
+				if let Some(kind) = cli.print_schema {
+					print!("{}", schema::schema(kind));
 					std::process::exit(0);
 				}
 
-				if !cli.natural_args.is_empty() {
-					// Handle natural syntax
-					Self::parse_natural_syntax(&cli.natural_args)
-				} else {
-					// Handle traditional flag syntax
-					if let (Some(from), Some(to), Some(states)) = (cli.from, cli.to, cli.states) {
-						Ok(DmiCopyArgs {
-							from,
-							to,
-							icon_states: states.into_iter().flatten().collect(),
-						})
-					} else {
+				if let Some(command) = cli.command.take() {
+					return Ok(match command {
+						SubCommand::AssertEq(args) => Command::AssertEq(args),
+						SubCommand::Info(args) => Command::Info(args),
+						SubCommand::Thumbnail(args) => Command::Thumbnail(args),
+						SubCommand::StripHotspots(args) => Command::StripHotspots(args),
+						SubCommand::Probe(args) => Command::Probe(args),
+						SubCommand::DumpMetadata(args) => Command::DumpMetadata(args),
+						SubCommand::Normalize(args) => Command::Normalize(args),
+						SubCommand::CheckInventory(args) => Command::CheckInventory(args),
+						SubCommand::CheckColors(args) => Command::CheckColors(args),
+						SubCommand::FindDuplicates(args) => Command::FindDuplicates(args),
+						SubCommand::ApplyPatch(args) => Command::ApplyPatch(args),
+						SubCommand::Sync(args) => Command::Sync(args),
+						SubCommand::Browse(args) => Command::Browse(args),
+						SubCommand::Preview(args) => Command::Preview(args),
+						SubCommand::Selftest(args) => Command::Selftest(args),
+						SubCommand::Validate(args) => Command::Validate(args),
+						SubCommand::DumpFrames(args) => Command::DumpFrames(args),
+						SubCommand::AssembleFrames(args) => Command::AssembleFrames(args),
+					});
+				}
+
+				if let Some(manifest) = cli.verify_checksums {
+					return Ok(Command::VerifyChecksums(manifest));
+				}
+
+				if cli.list_source_states {
+					let from = cli.from.expect("--list-source-states requires --from");
+					return Ok(Command::ListSourceStates {
+						from: paths::expand_all(
+							from.into_iter()
+								.map(|path| resolve_source(path, cli.source_dir.as_deref())),
+						)?,
+						gzip: cli.gzip,
+					});
+				}
+
+				match DmiCopyArgs::from_cli(cli)? {
+					Some(args) => Ok(Command::Copy(Box::new(args))),
+					None => {
 						// Show help if no arguments are provided
 						CliArgs::command().print_help().unwrap();
 						std::process::exit(0);
@@ -122,69 +1577,340 @@ impl DmiCopyArgs {
 			}
 			Err(err) => {
 				err.print().unwrap();
-				std::process::exit(1);
+				// Help and version output are reported as "errors" too, but
+				// shouldn't fail.
+				std::process::exit(if err.use_stderr() { 1 } else { 0 });
+			}
+		}
+	}
+}
+
+impl DmiCopyArgs {
+	/// Convert parsed CLI arguments into DmiCopyArgs, returning `None` if no
+	/// copy was specified
+	fn from_cli(cli: CliArgs) -> Result<Option<Self>> {
+		let (mut icon_states, from, to) = match (cli.from, cli.to) {
+			// Handle a spec string, which is a single argument
+			_ if is_spec(&cli.natural_args) => Self::parse_spec(&cli.natural_args[0])?,
+			// Handle natural syntax
+			_ if !cli.natural_args.is_empty() => Self::parse_natural_syntax(&cli.natural_args)?,
+			// Handle traditional flag syntax
+			(Some(from), Some(to)) => {
+				let states = cli
+					.states
+					.into_iter()
+					.flatten()
+					.flatten()
+					.chain(cli.trailing_states)
+					.collect();
+				(states, from, to)
+			}
+			(Some(_), None) => return Err(eyre!("Missing destination file, use --to")),
+			(None, Some(_)) => return Err(eyre!("Missing source file, use --from")),
+			(None, None) if cli.from_default => {
+				return Err(eyre!("--from-default needs a source and target file"))
+			}
+			(None, None) => return Ok(None),
+		};
+		let from = paths::expand_all(
+			from.into_iter()
+				.map(|path| resolve_source(path, cli.source_dir.as_deref())),
+		)?;
+		let to = match paths::expand_all([to.clone()])?.as_slice() {
+			[to] => to.clone(),
+			matches => {
+				return Err(eyre!(
+					"The target '{}' matches {} files, but a copy has a single target",
+					to.display(),
+					matches.len()
+				))
 			}
+		};
+		if cli.from_default {
+			// The default state is the one with an empty name
+			icon_states.push(String::new());
+		}
+		if remote::is_url(&to) {
+			return Err(eyre!("The target must be a local file, not a URL"));
+		}
+		if cli.group_output && cli.log_format == LogFormat::Tracing {
+			return Err(eyre!(
+				"--group-output can't be used with --log-format tracing, whose events are already \
+				 structured"
+			));
+		}
+		let structure_filter = StructureFilter {
+			frames_gt: cli.frames_gt,
+			frames_lt: cli.frames_lt,
+			frames: cli.frames,
+			dirs: cli.dirs,
+		};
+		if cli.all {
+			if let Some(state) = icon_states.iter().find(|state| !state.starts_with('!')) {
+				return Err(eyre!(
+					"--all copies every state, so '{state}' can't be requested too; only \
+					 exclusions can be given with it"
+				));
+			}
+		} else if structure_filter.is_empty() {
+			// Structural predicates can select states on their own
+			if icon_states.is_empty() {
+				return Err(eyre!("No icon states specified"));
+			}
+			if icon_states.iter().all(|state| state.starts_with('!')) {
+				return Err(eyre!("No icon states to copy, only exclusions were given"));
+			}
+		}
+
+		Ok(Some(DmiCopyArgs {
+			from,
+			to,
+			icon_states,
+			transform_cmd: cli.transform_cmd,
+			normalize_delays: cli.normalize_delays.then_some(cli.tick),
+			frame_delay: cli.frame_delay,
+			dedupe_frames: cli.dedupe_frames,
+			dir: cli.dir,
+			temp_dir: cli.temp_dir,
+			report_file: cli.report_file,
+			fail_on_dimension_change: cli.fail_on_dimension_change,
+			keep_target_metadata: cli.keep_target_metadata,
+			hotspot_strategy: cli.hotspot_strategy,
+			strip_invalid_hotspots: cli.strip_invalid_hotspots,
+			emit_patch: cli.emit_patch,
+			emit_states_file: cli.emit_states_file,
+			write_checksums: cli.write_checksums,
+			append_log: cli.append_log,
+			max_frames: cli.max_frames,
+			max_frames_keep: cli.max_frames_keep,
+			sample_frames: cli.sample,
+			max_copies: cli.max_copies,
+			at_index: cli.at_index,
+			verbosity: if cli.quiet {
+				Verbosity::Quiet
+			} else if cli.summary_only {
+				Verbosity::SummaryOnly
+			} else if cli.group_output {
+				Verbosity::Grouped
+			} else {
+				Verbosity::Normal
+			},
+			log_format: cli.log_format,
+			gzip: cli.gzip,
+			animation_filter: if cli.only_animated {
+				Some(AnimationFilter::Animated)
+			} else if cli.only_static {
+				Some(AnimationFilter::Static)
+			} else {
+				None
+			},
+			canonical: cli.canonical,
+			dry_run: cli.dry_run,
+			icon_size: cli.icon_size,
+			strict_dimensions: cli.strict_dimensions,
+			rename_with_regex: cli.rename_with_regex,
+			rename_map: cli.rename_map,
+			warnings_as_errors: cli.warnings_as_errors,
+			skip_if_content_exists: cli.skip_if_content_exists,
+			list_unmatched: cli.list_unmatched,
+			on_missing_source: cli.on_missing_source,
+			no_clobber_newer: cli.no_clobber_newer,
+			match_frames: cli.match_frames,
+			default_as: cli.default_as,
+			all: cli.all,
+			include_default_state: cli.include_default_state,
+			retries: cli.retries,
+			follow_symlinks: cli.follow_symlinks,
+			skip_errors: cli.skip_errors,
+			structure_filter,
+			on_conflict: if cli.if_target_missing_only {
+				ConflictPolicy::MissingOnly
+			} else if cli.if_source_differs {
+				ConflictPolicy::Differs
+			} else {
+				cli.on_conflict.unwrap_or_default()
+			},
+			source_priority: cli.source_priority,
+			match_mode: cli.match_mode,
+			normalize_names: cli.normalize_names,
+			explain_identical: cli.explain_identical,
+			explain_diff: cli.explain_diff,
+			human: cli.human,
+			compare_ignoring: cli.compare_ignoring,
+			set_dirs: cli.set_dirs,
+			keep_dir: cli.keep_dir,
+			offset: cli.offset,
+			offset_mode: cli.offset_mode,
+			alpha_threshold: cli.alpha_threshold,
+			mask_color: cli.mask_color,
+			mask_tolerance: cli.mask_tolerance,
+			cache_dir: cli.cache_dir,
+			lenient: cli.lenient,
+			allow_remote: cli.allow_remote,
+			timeout: Duration::from_secs(cli.timeout),
+		}))
+	}
+
+	/// Parse a spec string like `states=walk,run;from=a.dmi;to=b.dmi` into
+	/// the icon states, sources, and target. Several sources can be given,
+	/// separated by commas, and `states` can be left out with --all.
+	fn parse_spec(spec: &str) -> Result<(Vec<String>, Vec<PathBuf>, PathBuf)> {
+		let (mut states, mut from, mut to) = (None, None, None);
+		for entry in spec.split(';').filter(|entry| !entry.trim().is_empty()) {
+			let (key, value) = entry.split_once('=').ok_or_else(|| {
+				eyre!(
+					"Malformed spec entry '{}', expected key=value",
+					entry.trim()
+				)
+			})?;
+			let slot = match key.trim() {
+				"states" => &mut states,
+				"from" => &mut from,
+				"to" => &mut to,
+				key => {
+					return Err(eyre!(
+						"Unknown spec key '{key}', expected states, from, or to"
+					))
+				}
+			};
+			if slot.replace(value.trim()).is_some() {
+				return Err(eyre!("Spec key '{}' given more than once", key.trim()));
+			}
+		}
+		let list = |value: &str| {
+			value
+				.split(',')
+				.map(str::trim)
+				.filter(|item| !item.is_empty())
+				.map(str::to_string)
+				.collect::<Vec<_>>()
+		};
+		let from = list(from.ok_or_else(|| eyre!("Missing source file in spec, use from="))?);
+		if from.is_empty() {
+			return Err(eyre!("Missing source file in spec, use from="));
 		}
+		let to = to
+			.filter(|to| !to.is_empty())
+			.ok_or_else(|| eyre!("Missing destination file in spec, use to="))?;
+		Ok((
+			states.map(list).unwrap_or_default(),
+			from.into_iter().map(PathBuf::from).collect(),
+			PathBuf::from(to),
+		))
 	}
 
-	/// Parse the natural command syntax
-	fn parse_natural_syntax(args: &[String]) -> Result<Self> {
+	/// Parse the natural command syntax into the icon states, sources, and
+	/// target.
+	///
+	/// The icon states may be given either before `from` or after the target
+	/// file, but not split across both. Several sources may be given after
+	/// `from`.
+	fn parse_natural_syntax(args: &[String]) -> Result<(Vec<String>, Vec<PathBuf>, PathBuf)> {
 		let mut icon_states = Vec::new();
-		let mut from = None;
+		let mut from = Vec::new();
 		let mut to = None;
 		let mut current_mode = ParseMode::States;
+		let mut states_before_from = false;
 
 		for arg in args {
 			match arg.as_str() {
-				"from" => {
-					if !icon_states.is_empty() {
+				"from" => match current_mode {
+					_ if !from.is_empty() => {
+						return Err(eyre!("Source file specified more than once"))
+					}
+					ParseMode::From => return Err(eyre!("Expected source file after 'from'")),
+					_ => {
+						states_before_from = !icon_states.is_empty();
 						current_mode = ParseMode::From;
-					} else {
-						return Err(eyre!("No icon states specified before 'from'"));
 					}
-				}
-				"to" => {
-					if from.is_some() {
-						current_mode = ParseMode::To;
-					} else {
-						return Err(eyre!("Source file not specified before 'to'"));
+				},
+				"to" => match current_mode {
+					ParseMode::WaitingTo => current_mode = ParseMode::To,
+					_ if to.is_some() => {
+						return Err(eyre!("Destination file specified more than once"))
 					}
-				}
+					ParseMode::From => return Err(eyre!("Expected source file after 'from'")),
+					_ => return Err(eyre!("Source file not specified before 'to'")),
+				},
 				value => match current_mode {
 					ParseMode::States => icon_states.push(value.to_string()),
-					ParseMode::From => {
-						from = Some(PathBuf::from(value));
+					ParseMode::From | ParseMode::WaitingTo => {
+						from.push(PathBuf::from(value));
 						current_mode = ParseMode::WaitingTo;
 					}
 					ParseMode::To => {
 						to = Some(PathBuf::from(value));
-						current_mode = ParseMode::Done;
-					}
-					ParseMode::WaitingTo => {
-						return Err(eyre!("Expected 'to' keyword"));
+						current_mode = ParseMode::TrailingStates;
 					}
-					ParseMode::Done => {
-						return Err(eyre!("Unexpected additional arguments"));
+					ParseMode::TrailingStates => {
+						if states_before_from {
+							return Err(eyre!(
+								"Icon states must be given either before 'from' or after the \
+								 destination file, not both"
+							));
+						}
+						icon_states.push(value.to_string())
 					}
 				},
 			}
 		}
 
-		match (from, to) {
-			(Some(from), Some(to)) => Ok(DmiCopyArgs {
-				from,
-				to,
-				icon_states,
-			}),
-			(Some(_), None) => Err(eyre!("Missing destination file")),
-			(None, Some(_)) => Err(eyre!("Missing source file")),
-			(None, None) => Err(eyre!("Missing both source and destination file")),
+		match (from.is_empty(), to) {
+			(false, Some(to)) => Ok((icon_states, from, to)),
+			(false, None) => Err(eyre!("Missing destination file")),
+			(true, Some(_)) => Err(eyre!("Missing source file")),
+			(true, None) => Err(eyre!("Missing both source and destination file")),
 		}
 	}
 }
 
-fn print_completions<G: Generator>(gen: G, cmd: &mut clap::Command) {
-	clap_complete::generate(gen, cmd, cmd.get_name().to_string(), &mut std::io::stdout());
+/// Parse the command line, treating a natural syntax copy whose first state
+/// is named like a subcommand, like `info from a.dmi to b.dmi`, as a copy
+/// rather than a run of that subcommand
+fn parse_cli(args: Vec<OsString>) -> Result<CliArgs, clap::Error> {
+	let mut cmd = CliArgs::command();
+	if is_natural_copy_of_command(&cmd, &args) {
+		// Rename the subcommands to names no argument can have, so the state
+		// names are left for the natural syntax.
+		let names = cmd
+			.get_subcommands()
+			.map(|subcommand| subcommand.get_name().to_string())
+			.collect::<Vec<_>>();
+		for name in names {
+			cmd = cmd.mut_subcommand(&name, |subcommand| subcommand.name(format!("\0{name}")));
+		}
+	}
+	CliArgs::from_arg_matches(&cmd.try_get_matches_from(args)?)
+}
+
+/// Whether the first positional argument names a subcommand, but is followed
+/// by the `from` and `to` keywords of the natural syntax
+fn is_natural_copy_of_command(cmd: &clap::Command, args: &[OsString]) -> bool {
+	let mut positional = args
+		.iter()
+		.skip(1)
+		.filter_map(|arg| arg.to_str())
+		.filter(|arg| !arg.starts_with('-'));
+	positional
+		.next()
+		.is_some_and(|first| cmd.find_subcommand(first).is_some())
+		&& positional
+			.skip_while(|&arg| arg != "from")
+			.any(|arg| arg == "to")
+}
+
+/// Whether the natural arguments are a single spec string, rather than the
+/// natural syntax
+fn is_spec(args: &[String]) -> bool {
+	matches!(args, [arg] if arg.contains('=') && arg.contains(';'))
+}
+
+/// Write a completion script for the whole command tree, including every
+/// subcommand's own arguments
+fn write_completions<G: Generator>(gen: G, out: &mut dyn std::io::Write) {
+	let mut cmd = CliArgs::command();
+	let name = cmd.get_name().to_string();
+	clap_complete::generate(gen, &mut cmd, name, out);
 }
 
 #[derive(Debug)]
@@ -193,7 +1919,7 @@ enum ParseMode {
 	From,
 	WaitingTo,
 	To,
-	Done,
+	TrailingStates,
 }
 
 #[cfg(test)]
@@ -205,19 +1931,10 @@ mod tests {
 		// Prepend the binary name as clap expects it
 		let args = std::iter::once("dmi-copy").chain(args.iter().copied());
 
-		let cli = CliArgs::try_parse_from(args).wrap_err("failed to parse cil args")?;
-
-		if !cli.natural_args.is_empty() {
-			DmiCopyArgs::parse_natural_syntax(&cli.natural_args)
-		} else if let (Some(from), Some(to), Some(states)) = (cli.from, cli.to, cli.states) {
-			Ok(DmiCopyArgs {
-				from,
-				to,
-				icon_states: states.into_iter().flatten().collect(),
-			})
-		} else {
-			Err(eyre!("Missing required arguments"))
-		}
+		let cli =
+			parse_cli(args.map(OsString::from).collect()).wrap_err("failed to parse cil args")?;
+
+		DmiCopyArgs::from_cli(cli)?.ok_or_else(|| eyre!("Missing required arguments"))
 	}
 
 	#[test]
@@ -233,10 +1950,45 @@ mod tests {
 		assert!(result.is_ok());
 		let args = result.unwrap();
 		assert_eq!(args.icon_states, vec!["state1", "state2"]);
-		assert_eq!(args.from, PathBuf::from("original.dmi"));
+		assert_eq!(args.from, [PathBuf::from("original.dmi")]);
+		assert_eq!(args.to, PathBuf::from("target.dmi"));
+	}
+
+	#[test]
+	fn test_natural_syntax_states_last() {
+		let result = parse_args(&[
+			"from",
+			"original.dmi",
+			"to",
+			"target.dmi",
+			"state1",
+			"state2",
+		]);
+		assert!(result.is_ok());
+		let args = result.unwrap();
+		assert_eq!(args.icon_states, vec!["state1", "state2"]);
+		assert_eq!(args.from, [PathBuf::from("original.dmi")]);
 		assert_eq!(args.to, PathBuf::from("target.dmi"));
 	}
 
+	#[test]
+	fn test_invalid_natural_syntax_ordering() {
+		// States on both sides of the files
+		assert!(parse_args(&["state1", "from", "a.dmi", "to", "b.dmi", "state2"]).is_err());
+
+		// Source specified twice
+		assert!(parse_args(&["state1", "from", "a.dmi", "from", "b.dmi", "to", "c.dmi"]).is_err());
+
+		// Destination specified twice
+		assert!(parse_args(&["from", "a.dmi", "to", "b.dmi", "to", "c.dmi", "state1"]).is_err());
+
+		// 'to' before 'from'
+		assert!(parse_args(&["to", "b.dmi", "from", "a.dmi", "state1"]).is_err());
+
+		// Missing source file after 'from'
+		assert!(parse_args(&["state1", "from", "to", "b.dmi"]).is_err());
+	}
+
 	#[test]
 	fn test_traditional_syntax() {
 		let result = parse_args(&[
@@ -250,7 +2002,7 @@ mod tests {
 		assert!(result.is_ok());
 		let args = result.unwrap();
 		assert_eq!(args.icon_states, vec!["state1", "state2"]);
-		assert_eq!(args.from, PathBuf::from("original.dmi"));
+		assert_eq!(args.from, [PathBuf::from("original.dmi")]);
 		assert_eq!(args.to, PathBuf::from("target.dmi"));
 	}
 
@@ -269,8 +2021,45 @@ mod tests {
 		assert!(result.is_ok());
 		let args = result.unwrap();
 		assert_eq!(args.icon_states, vec!["state1", "state2", "state3"]);
-		assert_eq!(args.from, PathBuf::from("original.dmi"));
+		assert_eq!(args.from, [PathBuf::from("original.dmi")]);
+		assert_eq!(args.to, PathBuf::from("target.dmi"));
+	}
+
+	#[test]
+	fn test_traditional_syntax_trailing_states() {
+		let args = parse_args(&[
+			"--from",
+			"original.dmi",
+			"--to",
+			"target.dmi",
+			"--state",
+			"state1",
+			"--",
+			"state2",
+			"!state3",
+			"from",
+		])
+		.unwrap();
+		assert_eq!(args.icon_states, ["state1", "state2", "!state3", "from"]);
+		assert_eq!(args.from, [PathBuf::from("original.dmi")]);
 		assert_eq!(args.to, PathBuf::from("target.dmi"));
+
+		let args =
+			parse_args(&["--from", "original.dmi", "--to", "target.dmi", "--", "a,b"]).unwrap();
+		assert_eq!(args.icon_states, vec!["a,b"]);
+
+		// States after -- need the flag form
+		assert!(parse_args(&["--", "state1"]).is_err());
+		assert!(parse_args(&[
+			"state1",
+			"from",
+			"original.dmi",
+			"to",
+			"target.dmi",
+			"--",
+			"state2"
+		])
+		.is_err());
 	}
 
 	#[test]
@@ -297,6 +2086,121 @@ mod tests {
 		assert!(parse_args(&["--from", "original.dmi", "--state", "state1"]).is_err());
 	}
 
+	#[test]
+	fn test_spec_string() {
+		let args = parse_args(&["states=walk, run;from=a.dmi,b.dmi;to=c.dmi;"]).unwrap();
+		assert_eq!(args.icon_states, ["walk", "run"]);
+		assert_eq!(args.from, [PathBuf::from("a.dmi"), PathBuf::from("b.dmi")]);
+		assert_eq!(args.to, PathBuf::from("c.dmi"));
+
+		let args = parse_args(&["--all", "from=a.dmi;to=c.dmi"]).unwrap();
+		assert!(args.all);
+
+		for (spec, error) in [
+			(
+				"states=walk;to=c.dmi",
+				"Missing source file in spec, use from=",
+			),
+			(
+				"states=walk;from=a.dmi;to=",
+				"Missing destination file in spec, use to=",
+			),
+			("states=walk;from=a.dmi;src=c.dmi", "Unknown spec key 'src'"),
+			(
+				"states=walk;from=a.dmi;to=c.dmi;to=d.dmi",
+				"Spec key 'to' given more than once",
+			),
+			("walk;from=a.dmi;to=c.dmi", "Malformed spec entry 'walk'"),
+		] {
+			let err = parse_args(&[spec]).unwrap_err().to_string();
+			assert!(err.starts_with(error), "{spec}: {err}");
+		}
+	}
+
+	#[test]
+	fn test_on_missing_source() {
+		let args = parse_args(&["walk", "from", "a.dmi", "to", "b.dmi"]).unwrap();
+		assert_eq!(args.on_missing_source, MissingSourcePolicy::Warn);
+		let args = parse_args(&[
+			"--on-missing-source",
+			"error",
+			"walk",
+			"from",
+			"a.dmi",
+			"to",
+			"b.dmi",
+		])
+		.unwrap();
+		assert_eq!(args.on_missing_source, MissingSourcePolicy::Error);
+		assert!(parse_args(&["--on-missing-source", "fail", "walk", "a.dmi", "b.dmi"]).is_err());
+	}
+
+	#[test]
+	fn test_states_named_like_subcommands() {
+		let result = parse_args(&["info", "sync", "from", "a.dmi", "to", "b.dmi"]).unwrap();
+		assert_eq!(result.icon_states, ["info", "sync"]);
+		assert_eq!(result.from, [PathBuf::from("a.dmi")]);
+		assert_eq!(result.to, PathBuf::from("b.dmi"));
+		let result = parse_args(&["--dry-run", "from", "a.dmi", "to", "b.dmi", "info"]).unwrap();
+		assert_eq!(result.icon_states, ["info"]);
+
+		// Without the keywords, it's still the subcommand.
+		let cli = parse_cli(["dmi-copy", "info", "a.dmi"].map(OsString::from).to_vec()).unwrap();
+		assert!(matches!(cli.command, Some(SubCommand::Info(_))));
+	}
+
+	#[test]
+	fn test_at_index_conflicts_with_canonical() {
+		let args = ["walk", "from", "a.dmi", "to", "b.dmi", "--at-index", "1"];
+		assert_eq!(parse_args(&args).unwrap().at_index, Some(1));
+		assert!(parse_args(&[&args[..], &["--canonical"]].concat()).is_err());
+	}
+
+	#[test]
+	fn test_state_exclusions() {
+		let result = parse_args(&[
+			"--from",
+			"original.dmi",
+			"--to",
+			"target.dmi",
+			"--state",
+			"walk,run",
+			"--state",
+			"!walk",
+		]);
+		assert!(result.is_ok());
+		assert_eq!(result.unwrap().icon_states, vec!["walk", "run", "!walk"]);
+
+		// Only exclusions
+		assert!(parse_args(&["!walk", "from", "original.dmi", "to", "target.dmi"]).is_err());
+	}
+
+	#[test]
+	fn test_parse_offset() {
+		assert_eq!(parse_offset("2,-1"), Ok((2, -1)));
+		assert_eq!(parse_offset(" -3 , 0 "), Ok((-3, 0)));
+		assert!(parse_offset("2").is_err());
+		assert!(parse_offset("a,1").is_err());
+	}
+
+	#[test]
+	fn test_parse_color() {
+		assert_eq!(parse_color("FF00ff"), Ok([255, 0, 255]));
+		assert_eq!(parse_color("#102030"), Ok([16, 32, 48]));
+		assert!(parse_color("FF00F").is_err());
+		assert!(parse_color("GG0000").is_err());
+		assert!(parse_color("+F00FF").is_err());
+	}
+
+	#[test]
+	fn test_parse_icon_size() {
+		assert_eq!(parse_icon_size("32x32"), Ok((32, 32)));
+		assert_eq!(parse_icon_size("64X48"), Ok((64, 48)));
+		assert!(parse_icon_size("32").is_err());
+		assert!(parse_icon_size("0x32").is_err());
+		assert!(parse_icon_size("axb").is_err());
+	}
+
 	#[test]
 	fn test_traditional_syntax_empty_states() {
 		let result = parse_args(&[
@@ -311,4 +2215,172 @@ mod tests {
 		let args = result.unwrap();
 		assert_eq!(args.icon_states, vec!["state1", "state2"]);
 	}
+
+	#[test]
+	fn test_from_default() {
+		let args = parse_args(&[
+			"--from",
+			"original.dmi",
+			"--to",
+			"target.dmi",
+			"--from-default",
+			"--as",
+			"promoted",
+		])
+		.unwrap();
+		assert_eq!(args.icon_states, vec![""]);
+		assert_eq!(args.default_as.as_deref(), Some("promoted"));
+
+		let args = parse_args(&[
+			"walk",
+			"from",
+			"original.dmi",
+			"to",
+			"target.dmi",
+			"--from-default",
+		])
+		.unwrap();
+		assert_eq!(args.icon_states, vec!["walk", ""]);
+		assert_eq!(args.default_as, None);
+
+		// --as only applies to the default state
+		assert!(parse_args(&[
+			"walk",
+			"from",
+			"original.dmi",
+			"to",
+			"target.dmi",
+			"--as",
+			"x"
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn test_all_states() {
+		let base = ["--from", "original.dmi", "--to", "target.dmi", "--all"];
+		let args = parse_args(&base).unwrap();
+		assert!(args.all);
+		assert!(args.icon_states.is_empty());
+		assert!(!args.include_default_state);
+
+		let args =
+			parse_args(&[&base[..], &["--state", "!walk", "--include-default-state"]].concat())
+				.unwrap();
+		assert_eq!(args.icon_states, vec!["!walk"]);
+		assert!(args.include_default_state);
+
+		assert!(parse_args(&[&base[..], &["--state", "walk"]].concat()).is_err());
+		assert!(parse_args(&[&base[..], &["--from-default"]].concat()).is_err());
+		assert!(parse_args(&[
+			"--from",
+			"a.dmi",
+			"--to",
+			"b.dmi",
+			"--include-default-state"
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn test_structure_filter_without_states() {
+		let args =
+			parse_args(&["from", "original.dmi", "to", "target.dmi", "--frames", "8"]).unwrap();
+		assert!(args.icon_states.is_empty());
+		assert_eq!(args.structure_filter.frames, Some(8));
+
+		let args = parse_args(&[
+			"!walk",
+			"from",
+			"original.dmi",
+			"to",
+			"target.dmi",
+			"--dirs",
+			"4",
+		]);
+		assert_eq!(args.unwrap().structure_filter.dirs, Some(4));
+	}
+
+	#[test]
+	fn test_multiple_sources() {
+		let args = parse_args(&[
+			"walk",
+			"from",
+			"base.dmi",
+			"override.dmi",
+			"to",
+			"target.dmi",
+		])
+		.unwrap();
+		assert_eq!(args.from, [
+			PathBuf::from("base.dmi"),
+			PathBuf::from("override.dmi")
+		]);
+		assert_eq!(args.to, PathBuf::from("target.dmi"));
+
+		let args = parse_args(&[
+			"--from",
+			"base.dmi",
+			"--from",
+			"override.dmi",
+			"--to",
+			"target.dmi",
+			"--state",
+			"walk",
+			"--source-priority",
+			"first",
+		])
+		.unwrap();
+		assert_eq!(args.from, [
+			PathBuf::from("base.dmi"),
+			PathBuf::from("override.dmi")
+		]);
+		assert_eq!(args.source_priority, SourcePriority::First);
+	}
+
+	#[test]
+	fn test_cli_definition() {
+		CliArgs::command().debug_assert();
+	}
+
+	#[test]
+	fn test_write_completions() {
+		for &shell in Shell::value_variants() {
+			let mut script = Vec::new();
+			write_completions(shell, &mut script);
+			let script = String::from_utf8(script).unwrap();
+			for subcommand in CliArgs::command().get_subcommands() {
+				assert!(
+					script.contains(subcommand.get_name()),
+					"{shell} completions are missing {}",
+					subcommand.get_name()
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn test_resolve_source() {
+		let source_dir = tempfile::tempdir().unwrap();
+		std::fs::write(source_dir.path().join("master.dmi"), b"").unwrap();
+		let dir = Some(source_dir.path());
+
+		assert_eq!(
+			resolve_source(PathBuf::from("master.dmi"), dir),
+			source_dir.path().join("master.dmi")
+		);
+		assert_eq!(
+			resolve_source(PathBuf::from("missing.dmi"), dir),
+			PathBuf::from("missing.dmi")
+		);
+		// Files in the current directory take precedence
+		assert_eq!(
+			resolve_source(PathBuf::from("Cargo.toml"), dir),
+			PathBuf::from("Cargo.toml")
+		);
+		assert_eq!(
+			resolve_source(PathBuf::from("master.dmi"), None),
+			PathBuf::from("master.dmi")
+		);
+	}
 }
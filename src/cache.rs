@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::checksum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{Hotspot, Icon, IconState, Looping};
+use image::{DynamicImage, GrayAlphaImage, GrayImage, RgbImage, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs, io::Write, num::NonZeroU32, path::Path};
+
+/// Identifies a cache entry, and the version of its format
+const MAGIC: &[u8; 8] = b"DMICACH1";
+
+/// Load a DMI from its contents, reusing a cached decode from `cache_dir` if
+/// there is one, and caching the decode otherwise.
+///
+/// Entries are named after the SHA-256 hash of the file's contents, and store
+/// every state with its images as raw, uncompressed pixels. Problems with the
+/// cache itself are never fatal, and just fall back to decoding the file.
+pub fn load(cache_dir: &Path, bytes: &[u8], decode: impl FnOnce() -> Result<Icon>) -> Result<Icon> {
+	let hash: [u8; 32] = Sha256::digest(bytes).into();
+	let path = cache_dir.join(format!("{}.bin", checksum::to_hex(&hash)));
+	if let Some(icon) = fs::read(&path)
+		.ok()
+		.and_then(|entry| decode_entry(&entry, &hash))
+	{
+		return Ok(icon);
+	}
+
+	let icon = decode()?;
+	if let Some(entry) = encode_entry(&icon, &hash) {
+		let _ = write_entry(cache_dir, &path, &entry);
+	}
+	Ok(icon)
+}
+
+/// Report a cache dir that exists but isn't a directory up front, rather
+/// than silently never caching anything
+pub fn check_dir(cache_dir: &Path) -> Result<()> {
+	if cache_dir.exists() && !cache_dir.is_dir() {
+		return Err(eyre!("{} is not a directory", cache_dir.display()));
+	}
+	Ok(())
+}
+
+/// Atomically write a cache entry, so a concurrent run never reads half of
+/// one
+fn write_entry(cache_dir: &Path, path: &Path, entry: &[u8]) -> Result<()> {
+	fs::create_dir_all(cache_dir).wrap_err("failed to create cache dir")?;
+	let mut file = tempfile::NamedTempFile::new_in(cache_dir)
+		.wrap_err("failed to create temporary cache entry")?;
+	file.write_all(entry)
+		.wrap_err("failed to write cache entry")?;
+	file.persist(path)
+		.wrap_err("failed to move cache entry into place")?;
+	Ok(())
+}
+
+/// Serialize an icon, returning `None` if it can't be cached exactly
+fn encode_entry(icon: &Icon, hash: &[u8; 32]) -> Option<Vec<u8>> {
+	// The version can't be set from outside the dmi crate, so only the
+	// default one round-trips.
+	if icon.version != Icon::default().version {
+		return None;
+	}
+	let mut out = Vec::new();
+	out.extend_from_slice(MAGIC);
+	out.extend_from_slice(hash);
+	put_u32(&mut out, icon.width);
+	put_u32(&mut out, icon.height);
+	put_u32(&mut out, icon.states.len() as u32);
+	for state in &icon.states {
+		put_str(&mut out, &state.name);
+		out.push(state.dirs);
+		put_u32(&mut out, state.frames);
+		match &state.delay {
+			Some(delays) => {
+				out.push(1);
+				put_u32(&mut out, delays.len() as u32);
+				for delay in delays {
+					out.extend_from_slice(&delay.to_le_bytes());
+				}
+			}
+			None => out.push(0),
+		}
+		put_u32(&mut out, match state.loop_flag {
+			Looping::Indefinitely => 0,
+			Looping::NTimes(times) => times.get(),
+		});
+		out.push(state.rewind as u8);
+		out.push(state.movement as u8);
+		match state.hotspot {
+			Some(hotspot) => {
+				out.push(1);
+				put_u32(&mut out, hotspot.x);
+				put_u32(&mut out, hotspot.y);
+			}
+			None => out.push(0),
+		}
+		match &state.unknown_settings {
+			Some(settings) => {
+				out.push(1);
+				put_u32(&mut out, settings.len() as u32);
+				for (key, value) in settings {
+					put_str(&mut out, key);
+					put_str(&mut out, value);
+				}
+			}
+			None => out.push(0),
+		}
+		put_u32(&mut out, state.images.len() as u32);
+		for image in &state.images {
+			// Images are compared by color type too, so it has to be kept.
+			let color = match image {
+				DynamicImage::ImageLuma8(_) => 0,
+				DynamicImage::ImageLumaA8(_) => 1,
+				DynamicImage::ImageRgb8(_) => 2,
+				DynamicImage::ImageRgba8(_) => 3,
+				_ => return None,
+			};
+			out.push(color);
+			put_u32(&mut out, image.width());
+			put_u32(&mut out, image.height());
+			put_bytes(&mut out, image.as_bytes());
+		}
+	}
+	Some(out)
+}
+
+/// Deserialize an icon, returning `None` if the entry is corrupt or for
+/// different contents
+fn decode_entry(entry: &[u8], hash: &[u8; 32]) -> Option<Icon> {
+	let mut reader = Reader(entry);
+	if reader.take(MAGIC.len())? != MAGIC || reader.take(hash.len())? != hash {
+		return None;
+	}
+	let width = reader.u32()?;
+	let height = reader.u32()?;
+	let mut states = Vec::new();
+	for _ in 0..reader.u32()? {
+		let name = reader.string()?;
+		let dirs = reader.u8()?;
+		let frames = reader.u32()?;
+		let delay = match reader.u8()? {
+			0 => None,
+			_ => Some(
+				(0..reader.u32()?)
+					.map(|_| Some(f32::from_le_bytes(reader.take(4)?.try_into().ok()?)))
+					.collect::<Option<Vec<_>>>()?,
+			),
+		};
+		let loop_flag = match NonZeroU32::new(reader.u32()?) {
+			Some(times) => Looping::NTimes(times),
+			None => Looping::Indefinitely,
+		};
+		let rewind = reader.u8()? != 0;
+		let movement = reader.u8()? != 0;
+		let hotspot = match reader.u8()? {
+			0 => None,
+			_ => Some(Hotspot {
+				x: reader.u32()?,
+				y: reader.u32()?,
+			}),
+		};
+		let unknown_settings = match reader.u8()? {
+			0 => None,
+			_ => Some(
+				(0..reader.u32()?)
+					.map(|_| Some((reader.string()?, reader.string()?)))
+					.collect::<Option<HashMap<_, _>>>()?,
+			),
+		};
+		let images = (0..reader.u32()?)
+			.map(|_| {
+				let color = reader.u8()?;
+				let (width, height) = (reader.u32()?, reader.u32()?);
+				let pixels = reader.bytes()?.to_vec();
+				Some(match color {
+					0 => DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, pixels)?),
+					1 => {
+						DynamicImage::ImageLumaA8(GrayAlphaImage::from_raw(width, height, pixels)?)
+					}
+					2 => DynamicImage::ImageRgb8(RgbImage::from_raw(width, height, pixels)?),
+					3 => DynamicImage::ImageRgba8(RgbaImage::from_raw(width, height, pixels)?),
+					_ => return None,
+				})
+			})
+			.collect::<Option<Vec<_>>>()?;
+		states.push(IconState {
+			name,
+			dirs,
+			frames,
+			images,
+			delay,
+			loop_flag,
+			rewind,
+			movement,
+			hotspot,
+			unknown_settings,
+		});
+	}
+	reader.0.is_empty().then_some(Icon {
+		width,
+		height,
+		states,
+		..Default::default()
+	})
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+	out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+	put_u32(out, bytes.len() as u32);
+	out.extend_from_slice(bytes);
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+	put_bytes(out, value.as_bytes());
+}
+
+/// Reads values back in the order they were written
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+		let (taken, rest) = self.0.split_at_checked(len)?;
+		self.0 = rest;
+		Some(taken)
+	}
+
+	fn u8(&mut self) -> Option<u8> {
+		Some(self.take(1)?[0])
+	}
+
+	fn u32(&mut self) -> Option<u32> {
+		Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+	}
+
+	fn bytes(&mut self) -> Option<&'a [u8]> {
+		let len = self.u32()? as usize;
+		self.take(len)
+	}
+
+	fn string(&mut self) -> Option<String> {
+		String::from_utf8(self.bytes()?.to_vec()).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::Rgba;
+
+	#[test]
+	fn test_entry_round_trip() {
+		let icon = Icon {
+			width: 2,
+			height: 2,
+			states: vec![
+				IconState {
+					name: "walk".to_string(),
+					dirs: 1,
+					frames: 2,
+					images: vec![
+						DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([1, 2, 3, 4]))),
+						DynamicImage::ImageRgb8(RgbImage::new(2, 2)),
+					],
+					delay: Some(vec![1.0, 2.5]),
+					loop_flag: Looping::new(3),
+					rewind: true,
+					hotspot: Some(Hotspot { x: 1, y: 0 }),
+					unknown_settings: Some(HashMap::from([("a".to_string(), "b".to_string())])),
+					..Default::default()
+				},
+				IconState::default(),
+			],
+			..Default::default()
+		};
+		let hash = [7; 32];
+		let entry = encode_entry(&icon, &hash).unwrap();
+		assert_eq!(decode_entry(&entry, &hash), Some(icon));
+		assert_eq!(decode_entry(&entry, &[0; 32]), None);
+		assert_eq!(decode_entry(&entry[..entry.len() - 1], &hash), None);
+	}
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: MPL-2.0
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::{
+	icon::{Icon, Looping},
+	RawDmi,
+};
+use std::io::Write;
+
+/// Metadata keys the dmi crate writes itself, in a fixed order, ahead of any
+/// unknown settings
+const KNOWN_KEYS: [&str; 7] = [
+	"dirs", "frames", "delay", "loop", "rewind", "movement", "hotspot",
+];
+
+/// Put an icon into a canonical form: states sorted by name, and animation
+/// metadata cleared from single-frame states, where it has no effect.
+pub fn canonicalize(icon: &mut Icon) {
+	icon.states.sort_by(|a, b| a.name.cmp(&b.name));
+	for state in &mut icon.states {
+		if state.frames <= 1 {
+			state.delay = None;
+			state.loop_flag = Looping::Indefinitely;
+			state.rewind = false;
+			state.movement = false;
+		}
+	}
+}
+
+/// Save an icon with its metadata in a deterministic order.
+///
+/// `Icon::save` writes each state's unknown settings in `HashMap` iteration
+/// order, which differs between runs, so the metadata chunk is rewritten with
+/// those settings sorted.
+pub fn save<W: Write>(icon: &Icon, writer: &mut W) -> Result<()> {
+	let mut bytes = Vec::new();
+	icon.save(&mut bytes).wrap_err("failed to save dmi")?;
+	let mut raw = RawDmi::load(&bytes[..]).wrap_err("failed to reload saved dmi")?;
+	let ztxt = raw
+		.chunk_ztxt
+		.as_ref()
+		.ok_or_else(|| eyre!("saved dmi has no metadata"))?;
+	let metadata = String::from_utf8(ztxt.data.decode().wrap_err("failed to decode metadata")?)
+		.wrap_err("metadata is not valid utf-8")?;
+	raw.chunk_ztxt = Some(
+		dmi::ztxt::create_ztxt_chunk(sort_unknown_settings(&metadata).as_bytes())
+			.wrap_err("failed to encode metadata")?,
+	);
+	raw.save(writer).wrap_err("failed to write dmi")?;
+	Ok(())
+}
+
+/// Sort the unknown settings of every state in DMI metadata, leaving
+/// everything else in place
+fn sort_unknown_settings(metadata: &str) -> String {
+	let mut output = String::with_capacity(metadata.len());
+	let mut unknown = Vec::new();
+	let mut in_state = false;
+	for line in metadata.lines() {
+		let key = line
+			.strip_prefix('\t')
+			.and_then(|line| line.split(" = ").next());
+		match key {
+			Some(key) if in_state && !KNOWN_KEYS.contains(&key) => unknown.push(line),
+			Some(_) => {
+				output.push_str(line);
+				output.push('\n');
+			}
+			None => {
+				// A line that isn't a setting ends the previous state's block
+				in_state = line.starts_with("state = ");
+				unknown.sort_unstable();
+				for line in unknown.drain(..) {
+					output.push_str(line);
+					output.push('\n');
+				}
+				output.push_str(line);
+				output.push('\n');
+			}
+		}
+	}
+	unknown.sort_unstable();
+	for line in unknown {
+		output.push_str(line);
+		output.push('\n');
+	}
+	output
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::IconState;
+	use image::{DynamicImage, RgbaImage};
+	use std::collections::HashMap;
+
+	#[test]
+	fn test_sort_unknown_settings() {
+		let metadata = "# BEGIN DMI\nversion = 4.0\n\twidth = 32\n\theight = 32\nstate = \
+		                \"a\"\n\tdirs = 1\n\tframes = 1\n\tzeta = 1\n\talpha = 2\nstate = \
+		                \"b\"\n\tdirs = 1\n\tframes = 1\n# END DMI\n";
+		assert_eq!(
+			sort_unknown_settings(metadata),
+			"# BEGIN DMI\nversion = 4.0\n\twidth = 32\n\theight = 32\nstate = \"a\"\n\tdirs = \
+			 1\n\tframes = 1\n\talpha = 2\n\tzeta = 1\nstate = \"b\"\n\tdirs = 1\n\tframes = 1\n# \
+			 END DMI\n"
+		);
+	}
+
+	#[test]
+	fn test_save_is_deterministic() {
+		// Each HashMap gets its own random hasher, so these two are very
+		// unlikely to iterate in the same order.
+		let icon = || {
+			let settings = (0..16)
+				.map(|i| (format!("key{i}"), i.to_string()))
+				.collect::<HashMap<_, _>>();
+			Icon {
+				width: 32,
+				height: 32,
+				states: vec![IconState {
+					images: vec![DynamicImage::ImageRgba8(RgbaImage::new(32, 32))],
+					unknown_settings: Some(settings),
+					..Default::default()
+				}],
+				..Default::default()
+			}
+		};
+		let (mut a, mut b) = (Vec::new(), Vec::new());
+		save(&icon(), &mut a).unwrap();
+		save(&icon(), &mut b).unwrap();
+		assert_eq!(a, b);
+	}
+}
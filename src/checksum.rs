@@ -0,0 +1,101 @@
+// SPDX-License-Identifier: MPL-2.0
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use sha2::{Digest, Sha256};
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+/// Format bytes as lowercase hex
+pub fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The SHA-256 hash of some data, as lowercase hex
+pub fn sha256_hex(data: &[u8]) -> String {
+	to_hex(&Sha256::digest(data))
+}
+
+/// Hash the current contents of each file, and write them to a manifest in
+/// the format `sha256sum` uses, so it can check the manifest too
+pub fn write_manifest(manifest: &Path, files: &[PathBuf]) -> Result<()> {
+	let mut contents = String::new();
+	for file in files {
+		let data = fs::read(file).wrap_err_with(|| format!("failed to read {}", file.display()))?;
+		contents += &format!("{}  {}\n", sha256_hex(&data), file.display());
+	}
+	fs::write(manifest, contents).wrap_err("failed to write checksum manifest")
+}
+
+/// Parse a manifest into the files it lists and their expected hashes
+fn parse_manifest(contents: &str) -> Result<Vec<(PathBuf, String)>> {
+	contents
+		.lines()
+		.filter(|line| !line.trim().is_empty())
+		.map(|line| {
+			let (hash, file) = line
+				.split_once(' ')
+				.ok_or_else(|| eyre!("invalid manifest line '{line}'"))?;
+			// sha256sum marks files hashed in binary mode with a `*`
+			let file = file.strip_prefix([' ', '*']).unwrap_or(file);
+			if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+				return Err(eyre!("invalid hash '{hash}'"));
+			}
+			Ok((PathBuf::from(file), hash.to_ascii_lowercase()))
+		})
+		.collect()
+}
+
+/// Check that every file in a manifest still has the hash it was written with
+pub fn verify_manifest(manifest: &Path) -> Result<ExitCode> {
+	let contents = fs::read_to_string(manifest)
+		.wrap_err_with(|| format!("failed to read {}", manifest.display()))?;
+	let entries = parse_manifest(&contents)
+		.wrap_err_with(|| format!("failed to parse {}", manifest.display()))?;
+	let mut failures = 0;
+	for (file, expected) in &entries {
+		match fs::read(file) {
+			Ok(data) if sha256_hex(&data) == *expected => println!("{}: OK", file.display()),
+			Ok(_) => {
+				println!("{}: FAILED", file.display());
+				failures += 1;
+			}
+			Err(err) => {
+				println!("{}: FAILED to read ({err})", file.display());
+				failures += 1;
+			}
+		}
+	}
+	if failures > 0 {
+		eprintln!("{failures} of {} file(s) did not match", entries.len());
+		Ok(ExitCode::FAILURE)
+	} else {
+		Ok(ExitCode::SUCCESS)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sha256_hex() {
+		assert_eq!(
+			sha256_hex(b"abc"),
+			"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+		);
+	}
+
+	#[test]
+	fn test_parse_manifest() {
+		let hash = sha256_hex(b"abc");
+		let entries = parse_manifest(&format!("{hash}  a.dmi\n{hash} *dir/b b.dmi\n\n")).unwrap();
+		assert_eq!(entries, [
+			(PathBuf::from("a.dmi"), hash.clone()),
+			(PathBuf::from("dir/b b.dmi"), hash.clone())
+		]);
+		assert!(parse_manifest("nothex  a.dmi").is_err());
+		assert!(parse_manifest(&hash).is_err());
+	}
+}
@@ -0,0 +1,125 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::ApplyPatchArgs, load_dmi, save_dmi, SaveOptions};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::Icon;
+use serde::Deserialize;
+use std::{
+	fs,
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+/// The parts of a report file that describe changes
+#[derive(Debug, Deserialize)]
+struct Patch {
+	files: Vec<PatchFile>,
+}
+
+/// The changes a report describes for a single target
+#[derive(Debug, Deserialize)]
+struct PatchFile {
+	target: PathBuf,
+	#[serde(default)]
+	added: Vec<String>,
+	#[serde(default)]
+	replaced: Vec<String>,
+}
+
+/// Apply the additions and replacements listed in a report to a target, with
+/// the states taken from a source
+pub fn run(args: ApplyPatchArgs) -> Result<ExitCode> {
+	let patch = fs::read_to_string(&args.patch)
+		.wrap_err_with(|| format!("failed to read {}", args.patch.display()))?;
+	let patch = serde_json::from_str::<Patch>(&patch)
+		.wrap_err_with(|| format!("failed to parse {}", args.patch.display()))?;
+	let changes = changes_for(&patch, &args.target)?;
+	let source =
+		load_dmi(&args.from).wrap_err_with(|| format!("failed to read {}", args.from.display()))?;
+	let mut target = load_dmi(&args.target)
+		.wrap_err_with(|| format!("failed to read {}", args.target.display()))?;
+
+	apply(changes, &source, &mut target)
+		.wrap_err_with(|| format!("failed to apply {}", args.patch.display()))?;
+	save_dmi(target, &args.target, &SaveOptions::default())
+		.wrap_err_with(|| format!("failed to save {}", args.target.display()))?;
+	println!(
+		"{} added, {} replaced",
+		changes.added.len(),
+		changes.replaced.len()
+	);
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Find the changes for a target in a patch, which may cover several. Even a
+/// patch for a single file is only applied to the target it was made for.
+fn changes_for<'a>(patch: &'a Patch, target: &Path) -> Result<&'a PatchFile> {
+	let canonical = fs::canonicalize(target).ok();
+	patch
+		.files
+		.iter()
+		.find(|file| {
+			file.target == target
+				|| canonical.is_some() && fs::canonicalize(&file.target).ok() == canonical
+		})
+		.ok_or_else(|| eyre!("patch has no changes for {}", target.display()))
+}
+
+/// Add and replace the listed states, failing without changing the target if
+/// it no longer matches what the patch expects
+fn apply(changes: &PatchFile, source: &Icon, target: &mut Icon) -> Result<()> {
+	let source_state = |name: &str| {
+		source
+			.states
+			.iter()
+			.find(|state| state.name == name)
+			.ok_or_else(|| eyre!("state '{name}' isn't in the source"))
+	};
+	let mut patched = target.states.clone();
+	for name in &changes.replaced {
+		let state = source_state(name)?;
+		let existing = patched
+			.iter_mut()
+			.find(|existing| &existing.name == name)
+			.ok_or_else(|| eyre!("state '{name}' should be replaced, but isn't in the target"))?;
+		*existing = state.clone();
+	}
+	for name in &changes.added {
+		if patched.iter().any(|existing| &existing.name == name) {
+			return Err(eyre!(
+				"state '{name}' should be added, but is already in the target"
+			));
+		}
+		patched.push(source_state(name)?.clone());
+	}
+	target.states = patched;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::commands::icon;
+
+	#[test]
+	fn test_apply() {
+		let patch = serde_json::from_str::<Patch>(
+			r#"{"files": [{"target": "b.dmi", "added": ["new"], "replaced": ["old"], "identical": []}]}"#,
+		)
+		.unwrap();
+		let changes = changes_for(&patch, Path::new("b.dmi")).unwrap();
+		assert!(changes_for(&patch, Path::new("a.dmi")).is_err());
+		let source = icon(&[("new", 1), ("old", 2), ("other", 3)]);
+
+		let mut target = icon(&[("old", 1), ("kept", 1)]);
+		apply(changes, &source, &mut target).unwrap();
+		assert_eq!(target, icon(&[("old", 2), ("kept", 1), ("new", 1)]));
+
+		// Applying it twice fails, and leaves the target alone
+		assert!(apply(changes, &source, &mut target).is_err());
+		assert_eq!(target, icon(&[("old", 2), ("kept", 1), ("new", 1)]));
+
+		let mut target = icon(&[("kept", 1)]);
+		assert!(apply(changes, &source, &mut target).is_err());
+		assert!(apply(changes, &icon(&[("old", 2)]), &mut icon(&[("old", 1)])).is_err());
+	}
+}
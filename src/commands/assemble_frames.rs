@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MPL-2.0
+use super::dump_frames::FrameSet;
+use crate::{
+	args::AssembleFramesArgs,
+	direction::Direction,
+	load_dmi, merge_into,
+	report::{CopyReport, LogFormat, Verbosity},
+	save_dmi, MergeOptions, SaveOptions,
+};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{Hotspot, IconState, Looping};
+use image::DynamicImage;
+use std::{fs, path::Path, process::ExitCode, slice};
+
+/// Rebuild a state from frames written by dump-frames, and add it to a
+/// target following the conflict policy
+pub fn run(args: AssembleFramesArgs) -> Result<ExitCode> {
+	let meta = fs::read_to_string(&args.meta)
+		.wrap_err_with(|| format!("failed to read {}", args.meta.display()))?;
+	let set = serde_json::from_str::<FrameSet>(&meta)
+		.wrap_err_with(|| format!("invalid frame metadata in {}", args.meta.display()))?;
+	let in_dir = match &args.in_dir {
+		Some(in_dir) => in_dir.as_path(),
+		None => args.meta.parent().unwrap_or(Path::new("")),
+	};
+	let mut to = load_dmi(&args.target)
+		.wrap_err_with(|| format!("failed to read {}", args.target.display()))?;
+	let state = assemble(&set, to.width, to.height, |file| {
+		let path = in_dir.join(file);
+		// Frames saved as RGB would never compare equal to the target's RGBA
+		// images otherwise.
+		image::open(&path)
+			.map(|image| DynamicImage::ImageRgba8(image.to_rgba8()))
+			.wrap_err_with(|| format!("failed to read frame {}", path.display()))
+	})?;
+
+	let options = MergeOptions {
+		on_conflict: args.on_conflict,
+		match_frames: args.match_frames,
+		..Default::default()
+	};
+	let mut report = CopyReport::new(
+		slice::from_ref(&args.meta),
+		&args.target,
+		Verbosity::Normal,
+		LogFormat::Text,
+	);
+	merge_into(&mut to, state, &options, &mut report)?;
+	if report.added.is_empty() && report.replaced.is_empty() {
+		return Ok(ExitCode::SUCCESS);
+	}
+	save_dmi(to, &args.target, &SaveOptions::default())
+		.wrap_err_with(|| format!("failed to save dmi to {}", args.target.display()))?;
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Build a state from its frame set, loading each image with `load`.
+/// Every image must be listed exactly once, and be `width` by `height`.
+fn assemble(
+	set: &FrameSet,
+	width: u32,
+	height: u32,
+	mut load: impl FnMut(&str) -> Result<DynamicImage>,
+) -> Result<IconState> {
+	if ![1, 4, 8].contains(&set.dirs) || set.frames == 0 {
+		return Err(eyre!(
+			"state '{}' has {} dir(s) and {} frame(s)",
+			set.name,
+			set.dirs,
+			set.frames
+		));
+	}
+	let mut images = vec![None; set.dirs as usize * set.frames as usize];
+	for entry in &set.images {
+		let dir = entry.dir.index();
+		if dir >= set.dirs as usize || entry.frame >= set.frames {
+			return Err(eyre!(
+				"{} is frame {} facing {}, but the state has {} dir(s) and {} frame(s)",
+				entry.file,
+				entry.frame,
+				entry.dir,
+				set.dirs,
+				set.frames
+			));
+		}
+		let slot = &mut images[entry.frame as usize * set.dirs as usize + dir];
+		if slot.is_some() {
+			return Err(eyre!(
+				"frame {} facing {} is listed more than once",
+				entry.frame,
+				entry.dir
+			));
+		}
+		let image = load(&entry.file)?;
+		if (image.width(), image.height()) != (width, height) {
+			return Err(eyre!(
+				"{} is {}x{}, but the target has {width}x{height} icons",
+				entry.file,
+				image.width(),
+				image.height()
+			));
+		}
+		*slot = Some(image);
+	}
+	let images = images
+		.into_iter()
+		.enumerate()
+		.map(|(index, image)| {
+			image.ok_or_else(|| {
+				eyre!(
+					"frame {} facing {} is missing",
+					index / set.dirs as usize,
+					Direction::value_variants()[index % set.dirs as usize]
+				)
+			})
+		})
+		.collect::<Result<Vec<_>>>()?;
+	Ok(IconState {
+		name: set.name.clone(),
+		dirs: set.dirs,
+		frames: set.frames,
+		images,
+		delay: set.delays.clone(),
+		loop_flag: set.loop_count.map_or(Looping::Indefinitely, Looping::new),
+		rewind: set.rewind,
+		movement: set.movement,
+		hotspot: set.hotspot.map(|[x, y]| Hotspot { x, y }),
+		unknown_settings: (!set.unknown_settings.is_empty()).then(|| {
+			set.unknown_settings
+				.iter()
+				.map(|(key, value)| (key.clone(), value.clone()))
+				.collect()
+		}),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::commands::dump_frames::FrameImage;
+	use image::{Rgba, RgbaImage};
+	use std::collections::BTreeMap;
+
+	#[test]
+	fn test_assemble() {
+		let image = |file: &str| FrameImage {
+			file: file.to_string(),
+			frame: file[1..].parse().unwrap(),
+			dir: if file.starts_with('s') {
+				Direction::South
+			} else {
+				Direction::North
+			},
+		};
+		let mut set = FrameSet {
+			name: "walk".to_string(),
+			dirs: 4,
+			frames: 1,
+			delays: None,
+			loop_count: Some(2),
+			rewind: false,
+			movement: false,
+			hotspot: None,
+			unknown_settings: BTreeMap::from([("\tcolor".to_string(), "red".to_string())]),
+			images: vec![image("n0"), image("s0")],
+		};
+		let load = |file: &str| {
+			let shade = if file.starts_with('s') { 1 } else { 2 };
+			Ok(DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				2,
+				2,
+				Rgba([shade, 0, 0, 255]),
+			)))
+		};
+		let err = assemble(&set, 2, 2, load).unwrap_err().to_string();
+		assert_eq!(err, "frame 0 facing east is missing");
+		set.dirs = 1;
+		assert!(assemble(&set, 2, 2, load).is_err());
+
+		set.dirs = 4;
+		set.images = vec![image("s0"), image("s0")];
+		assert!(assemble(&set, 2, 2, load)
+			.unwrap_err()
+			.to_string()
+			.contains("more than once"));
+
+		set.dirs = 1;
+		set.frames = 2;
+		set.images = vec![image("s1"), image("s0")];
+		let state = assemble(&set, 2, 2, load).unwrap();
+		assert_eq!(state.images.len(), 2);
+		assert_eq!(state.loop_flag, Looping::new(2));
+		let unknown_settings = state.unknown_settings.unwrap();
+		assert_eq!(unknown_settings["\tcolor"], "red");
+		assert!(assemble(&set, 4, 4, load).is_err());
+	}
+}
@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::AssertEqArgs, compare, load_dmi};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::{Icon, IconState};
+use std::{collections::BTreeMap, process::ExitCode};
+
+/// Check that two DMI files contain the same icon states, regardless of their
+/// order, printing a summary of any differences
+pub fn run(args: AssertEqArgs) -> Result<ExitCode> {
+	let actual = load_dmi(&args.actual)
+		.wrap_err_with(|| format!("failed to read {}", args.actual.display()))?;
+	let expected = load_dmi(&args.expected)
+		.wrap_err_with(|| format!("failed to read {}", args.expected.display()))?;
+
+	let differences = differences(&actual, &expected);
+	if differences.is_empty() {
+		println!(
+			"{} matches {}",
+			args.actual.display(),
+			args.expected.display()
+		);
+		return Ok(ExitCode::SUCCESS);
+	}
+
+	println!(
+		"{} does not match {}:",
+		args.actual.display(),
+		args.expected.display()
+	);
+	for difference in &differences {
+		println!("  {difference}");
+	}
+	Ok(ExitCode::FAILURE)
+}
+
+fn states_by_name(icon: &Icon) -> BTreeMap<&str, Vec<&IconState>> {
+	let mut states = BTreeMap::<&str, Vec<&IconState>>::new();
+	for state in &icon.states {
+		states.entry(state.name.as_str()).or_default().push(state);
+	}
+	states
+}
+
+/// Describe every difference between two icons, ignoring state order
+fn differences(actual: &Icon, expected: &Icon) -> Vec<String> {
+	let mut differences = Vec::new();
+	if (actual.width, actual.height) != (expected.width, expected.height) {
+		differences.push(format!(
+			"icon size differs: {}x{} != {}x{}",
+			actual.width, actual.height, expected.width, expected.height
+		));
+	}
+
+	let actual_states = states_by_name(actual);
+	let expected_states = states_by_name(expected);
+	for (name, expected) in &expected_states {
+		let Some(actual) = actual_states.get(name) else {
+			differences.push(format!("state '{name}' is missing"));
+			continue;
+		};
+		if actual.len() != expected.len() {
+			differences.push(format!(
+				"state '{name}' appears {} time(s), expected {}",
+				actual.len(),
+				expected.len()
+			));
+			continue;
+		}
+		for (actual, expected) in actual.iter().zip(expected) {
+			let fields = compare::differing_fields(actual, expected);
+			if !fields.is_empty() {
+				differences.push(format!("state '{name}' differs: {}", fields.join(", ")));
+			}
+		}
+	}
+	for name in actual_states.keys() {
+		if !expected_states.contains_key(name) {
+			differences.push(format!("state '{name}' is unexpected"));
+		}
+	}
+	differences
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::commands::icon;
+
+	#[test]
+	fn test_differences_ignores_order() {
+		let actual = icon(&[("a", 1), ("b", 1)]);
+		let expected = icon(&[("b", 1), ("a", 1)]);
+		assert!(differences(&actual, &expected).is_empty());
+	}
+
+	#[test]
+	fn test_differences_reports_changes() {
+		let actual = icon(&[("a", 1), ("b", 2), ("extra", 1)]);
+		let expected = icon(&[("a", 1), ("b", 1), ("missing", 1)]);
+		assert_eq!(differences(&actual, &expected), vec![
+			"state 'b' differs: frames",
+			"state 'missing' is missing",
+			"state 'extra' is unexpected",
+		]);
+	}
+}
@@ -0,0 +1,194 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{
+	args::BrowseArgs,
+	load_dmi, merge_into, render,
+	report::{CopyReport, LogFormat, Verbosity},
+	save_dmi, MergeOptions, SaveOptions,
+};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::Icon;
+use std::{
+	collections::BTreeSet,
+	io::{self, BufRead, IsTerminal, Write},
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+const HELP: &str = "\
+commands:
+  <number>         show that state
+  n, p             show the next or previous state
+  m                mark or unmark the shown state for copying
+  l                list the states
+  c [TARGET]       copy the marked states into TARGET, or --to
+  q                quit without copying
+  ?                show this help";
+
+/// What happened after a command
+enum Outcome {
+	Continue,
+	Quit,
+	Copied,
+}
+
+/// The state of an interactive browsing session
+struct Browser<'a> {
+	icon: &'a Icon,
+	cursor: usize,
+	marked: BTreeSet<usize>,
+	color: bool,
+	/// How marked states are merged into the target
+	options: MergeOptions<'a>,
+}
+
+/// Browse the states of a DMI file interactively, marking states to copy into
+/// a target
+pub fn run(args: BrowseArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	if icon.states.is_empty() {
+		return Err(eyre!("{} has no icon states", args.file.display()));
+	}
+	let mut browser = Browser {
+		icon: &icon,
+		cursor: 0,
+		marked: BTreeSet::new(),
+		color: io::stdout().is_terminal(),
+		options: MergeOptions {
+			on_conflict: args.on_conflict,
+			fail_on_dimension_change: args.fail_on_dimension_change,
+			match_frames: args.match_frames,
+			..Default::default()
+		},
+	};
+
+	println!("{HELP}");
+	browser.list();
+	let mut lines = io::stdin().lock().lines();
+	loop {
+		print!("> ");
+		io::stdout().flush()?;
+		let Some(line) = lines.next() else {
+			break;
+		};
+		// A bad command shouldn't end the session, so just report it.
+		match browser.command(line?.trim(), args.to.as_deref()) {
+			Ok(Outcome::Continue) => {}
+			Ok(Outcome::Quit) => break,
+			Ok(Outcome::Copied) => return Ok(ExitCode::SUCCESS),
+			Err(err) => println!("error: {err:#}"),
+		}
+	}
+	if !browser.marked.is_empty() {
+		println!(
+			"Quit without copying {} marked state(s)",
+			browser.marked.len()
+		);
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+impl Browser<'_> {
+	fn command(&mut self, line: &str, default_target: Option<&Path>) -> Result<Outcome> {
+		let (command, arg) = line
+			.split_once(char::is_whitespace)
+			.map_or((line, ""), |(command, arg)| (command, arg.trim()));
+		match command {
+			"" => {}
+			"n" => self.show(self.cursor + 1)?,
+			"p" => self.show(
+				self.cursor
+					.checked_sub(1)
+					.ok_or_else(|| eyre!("no previous state"))?,
+			)?,
+			"m" => {
+				if !self.marked.insert(self.cursor) {
+					self.marked.remove(&self.cursor);
+				}
+				println!("{} state(s) marked", self.marked.len());
+			}
+			"l" => self.list(),
+			"c" => {
+				let target = match arg {
+					"" => default_target
+						.map(Path::to_path_buf)
+						.ok_or_else(|| eyre!("no target given, and no --to"))?,
+					target => PathBuf::from(target),
+				};
+				self.copy(&target)?;
+				return Ok(Outcome::Copied);
+			}
+			"q" => return Ok(Outcome::Quit),
+			"?" => println!("{HELP}"),
+			number => match number.parse::<usize>() {
+				Ok(number) if number > 0 => self.show(number - 1)?,
+				_ => return Err(eyre!("unknown command '{line}', try ?")),
+			},
+		}
+		Ok(Outcome::Continue)
+	}
+
+	fn list(&self) {
+		for (index, state) in self.icon.states.iter().enumerate() {
+			let mark = if self.marked.contains(&index) {
+				'*'
+			} else {
+				' '
+			};
+			let cursor = if index == self.cursor { '>' } else { ' ' };
+			println!(
+				"{cursor}{mark}{:>3}. '{}' ({} dir(s), {} frame(s))",
+				index + 1,
+				state.name,
+				state.dirs,
+				state.frames
+			);
+		}
+	}
+
+	fn show(&mut self, index: usize) -> Result<()> {
+		let state = self
+			.icon
+			.states
+			.get(index)
+			.ok_or_else(|| eyre!("there are only {} states", self.icon.states.len()))?;
+		self.cursor = index;
+		let mark = if self.marked.contains(&index) {
+			" [marked]"
+		} else {
+			""
+		};
+		println!(
+			"{}. '{}' ({} dir(s), {} frame(s)){mark}",
+			index + 1,
+			state.name,
+			state.dirs,
+			state.frames
+		);
+		if let Some(image) = state.images.first() {
+			print!("{}", render::preview(image, self.color));
+		}
+		Ok(())
+	}
+
+	/// Add the marked states to a target, merging them into any with the same
+	/// names like a copy does
+	fn copy(&self, target: &Path) -> Result<()> {
+		if self.marked.is_empty() {
+			return Err(eyre!("no states are marked, use m to mark one"));
+		}
+		let mut to =
+			load_dmi(target).wrap_err_with(|| format!("failed to read {}", target.display()))?;
+		let mut report = CopyReport::new(&[], target, Verbosity::Normal, LogFormat::Text);
+		for &index in &self.marked {
+			let state = self.icon.states[index].clone();
+			merge_into(&mut to, state, &self.options, &mut report)?;
+		}
+		if !report.added.is_empty() || !report.replaced.is_empty() {
+			save_dmi(to, target, &SaveOptions::default())
+				.wrap_err_with(|| format!("failed to save {}", target.display()))?;
+		}
+		report.print_summary();
+		Ok(())
+	}
+}
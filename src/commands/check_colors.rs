@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::CheckColorsArgs, load_dmi};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::IconState;
+use std::{collections::HashSet, process::ExitCode};
+
+/// Report the states of a DMI file that use more distinct colors than
+/// `--max-colors`, worst first
+pub fn run(args: CheckColorsArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let mut over = icon
+		.states
+		.iter()
+		.map(|state| (state.name.as_str(), count_colors(state)))
+		.filter(|(_, colors)| *colors > args.max_colors)
+		.collect::<Vec<_>>();
+	for (name, colors) in &over {
+		println!("'{name}': {colors} colors");
+	}
+
+	if over.is_empty() {
+		println!(
+			"All {} state(s) use at most {} colors",
+			icon.states.len(),
+			args.max_colors
+		);
+		return Ok(ExitCode::SUCCESS);
+	}
+	over.sort_by(|(_, a), (_, b)| b.cmp(a));
+	println!(
+		"{} of {} state(s) use more than {} colors, worst: {}",
+		over.len(),
+		icon.states.len(),
+		args.max_colors,
+		over.iter()
+			.take(args.top)
+			.map(|(name, colors)| format!("'{name}' ({colors})"))
+			.collect::<Vec<_>>()
+			.join(", ")
+	);
+	Ok(ExitCode::FAILURE)
+}
+
+/// Count the distinct colors across every frame of a state. Fully transparent
+/// pixels all count as the same color, whatever their RGB values are.
+fn count_colors(state: &IconState) -> usize {
+	let mut colors = HashSet::new();
+	for image in &state.images {
+		for pixel in image.to_rgba8().pixels() {
+			colors.insert(if pixel[3] == 0 { [0; 4] } else { pixel.0 });
+		}
+	}
+	colors.len()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	#[test]
+	fn test_count_colors() {
+		let mut first = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+		first.put_pixel(0, 0, Rgba([1, 2, 3, 0]));
+		let mut second = RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]));
+		second.put_pixel(0, 0, Rgba([4, 5, 6, 0]));
+		second.put_pixel(1, 1, Rgba([0, 255, 0, 255]));
+		let state = IconState {
+			frames: 2,
+			images: vec![
+				DynamicImage::ImageRgba8(first),
+				DynamicImage::ImageRgba8(second),
+			],
+			..Default::default()
+		};
+		assert_eq!(count_colors(&state), 3);
+	}
+}
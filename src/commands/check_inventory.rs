@@ -0,0 +1,92 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::CheckInventoryArgs, load_dmi};
+use color_eyre::eyre::{Result, WrapErr};
+use std::{collections::BTreeSet, fs, process::ExitCode};
+
+/// Check that a DMI file has the states listed in its inventory, and no
+/// others unless `--subset` is given
+pub fn run(args: CheckInventoryArgs) -> Result<ExitCode> {
+	let inventory_path = args
+		.expect
+		.unwrap_or_else(|| args.file.with_extension("states"));
+	let inventory = fs::read_to_string(&inventory_path)
+		.wrap_err_with(|| format!("failed to read {}", inventory_path.display()))?;
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+
+	let expected = parse_inventory(&inventory);
+	let actual = icon
+		.states
+		.iter()
+		.map(|state| state.name.as_str())
+		.collect::<BTreeSet<_>>();
+	let missing = expected.difference(&actual).collect::<Vec<_>>();
+	let extra = if args.subset {
+		Vec::new()
+	} else {
+		actual.difference(&expected).collect()
+	};
+	for name in &missing {
+		println!("missing: '{name}'");
+	}
+	for name in &extra {
+		println!("extra: '{name}'");
+	}
+	Ok(if missing.is_empty() && extra.is_empty() {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::FAILURE
+	})
+}
+
+/// Parse the state names in an inventory file, one per line, skipping blank
+/// lines and `#` comments. The default state, which has no name, is written as
+/// `""`.
+fn parse_inventory(inventory: &str) -> BTreeSet<&str> {
+	inventory
+		.lines()
+		.filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+		.map(|line| match line.trim_end() {
+			"\"\"" => "",
+			name => name,
+		})
+		.collect()
+}
+
+/// Write the names of some states as an inventory file, sorted so that it
+/// diffs cleanly
+pub fn format_inventory<'a>(names: impl IntoIterator<Item = &'a str>) -> String {
+	names
+		.into_iter()
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.map(|name| match name {
+			"" => "\"\"\n".to_string(),
+			name => format!("{name}\n"),
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_inventory() {
+		let inventory = "# mob sprites\nwalk\n\nidle  \r\n\"\"\nfire burst\n";
+		assert_eq!(
+			parse_inventory(inventory),
+			BTreeSet::from(["walk", "idle", "", "fire burst"])
+		);
+	}
+
+	#[test]
+	fn test_format_inventory() {
+		let inventory = format_inventory(["walk", "", "idle", "walk"]);
+		assert_eq!(inventory, "\"\"\nidle\nwalk\n");
+		assert_eq!(
+			parse_inventory(&inventory),
+			BTreeSet::from(["walk", "idle", ""])
+		);
+	}
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::DumpFramesArgs, conflict, direction::Direction, load_dmi};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{IconState, Looping};
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::{BTreeMap, HashSet},
+	fs,
+	process::ExitCode,
+};
+
+/// Everything needed to reassemble a state from its dumped frames
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FrameSet {
+	pub name: String,
+	pub dirs: u8,
+	pub frames: u32,
+	pub delays: Option<Vec<f32>>,
+	/// How many times the animation plays, or `null` to loop forever
+	pub loop_count: Option<u32>,
+	pub rewind: bool,
+	pub movement: bool,
+	/// The hotspot's x and y position
+	pub hotspot: Option<[u32; 2]>,
+	/// Settings the dmi crate doesn't know about, kept as they are
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub unknown_settings: BTreeMap<String, String>,
+	/// The file of every image, in the order they're stored in the state
+	pub images: Vec<FrameImage>,
+}
+
+/// A single dumped image of a state
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FrameImage {
+	/// The image's file name, relative to the frame set's directory
+	pub file: String,
+	pub frame: u32,
+	pub dir: Direction,
+}
+
+/// Write every frame of the selected states to its own PNG, with a JSON file
+/// per state describing how they fit together
+pub fn run(args: DumpFramesArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let states = if args.states.is_empty() {
+		icon.states.iter().collect::<Vec<_>>()
+	} else {
+		args.states
+			.iter()
+			.map(|name| {
+				icon.states
+					.iter()
+					.find(|state| &state.name == name)
+					.ok_or_else(|| eyre!("state '{name}' not found in {}", args.file.display()))
+			})
+			.collect::<Result<_>>()?
+	};
+	fs::create_dir_all(&args.out_dir)
+		.wrap_err_with(|| format!("failed to create {}", args.out_dir.display()))?;
+
+	let mut written = 0;
+	for (state, stem) in states.iter().zip(file_stems(&states)) {
+		let set = frame_set(state, &stem);
+		for (image, frame) in state.images.iter().zip(&set.images) {
+			let path = args.out_dir.join(&frame.file);
+			image
+				.save_with_format(&path, image::ImageFormat::Png)
+				.wrap_err_with(|| format!("failed to write {}", path.display()))?;
+			written += 1;
+		}
+		let path = args.out_dir.join(format!("{stem}.json"));
+		let json = serde_json::to_string_pretty(&set).wrap_err("failed to serialize frames")?;
+		fs::write(&path, json).wrap_err_with(|| format!("failed to write {}", path.display()))?;
+	}
+	println!("Wrote {written} frame(s) to {}", args.out_dir.display());
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Describe a state's images and the files they're written to, which are
+/// named after `stem`
+fn frame_set(state: &IconState, stem: &str) -> FrameSet {
+	let images = (0..state.frames)
+		.flat_map(|frame| {
+			Direction::value_variants()
+				.iter()
+				.take(state.dirs as usize)
+				.map(move |&dir| (frame, dir))
+		})
+		.map(|(frame, dir)| FrameImage {
+			file: format!("{stem}_{dir}_{frame}.png"),
+			frame,
+			dir,
+		})
+		.collect();
+	FrameSet {
+		name: state.name.clone(),
+		dirs: state.dirs,
+		frames: state.frames,
+		delays: state.delay.clone(),
+		loop_count: match state.loop_flag {
+			Looping::Indefinitely => None,
+			Looping::NTimes(times) => Some(times.get()),
+		},
+		rewind: state.rewind,
+		movement: state.movement,
+		hotspot: state.hotspot.map(|hotspot| [hotspot.x, hotspot.y]),
+		unknown_settings: state
+			.unknown_settings
+			.iter()
+			.flatten()
+			.map(|(key, value)| (key.clone(), value.clone()))
+			.collect(),
+		images,
+	}
+}
+
+/// A file name for every state that's different from the others', even on
+/// case-insensitive file systems. A name that's taken gets a numeric suffix,
+/// as chosen by `unique_name`.
+fn file_stems(states: &[&IconState]) -> Vec<String> {
+	let mut taken = HashSet::new();
+	states
+		.iter()
+		.map(|state| {
+			let stem = file_stem(state);
+			let stem = if taken.contains(&stem.to_lowercase()) {
+				conflict::unique_name(&stem, |candidate| taken.contains(&candidate.to_lowercase()))
+			} else {
+				stem
+			};
+			taken.insert(stem.to_lowercase());
+			stem
+		})
+		.collect()
+}
+
+/// A file name safe version of a state's name. The default state, which has
+/// no name, is written as `default`.
+fn file_stem(state: &IconState) -> String {
+	if state.name.is_empty() {
+		return "default".to_string();
+	}
+	state
+		.name
+		.chars()
+		.map(|c| {
+			if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+				c
+			} else {
+				'_'
+			}
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_frame_set() {
+		let state = IconState {
+			name: "walk/fast".to_string(),
+			dirs: 4,
+			frames: 2,
+			delay: Some(vec![1.0, 2.0]),
+			..Default::default()
+		};
+		let set = frame_set(&state, &file_stem(&state));
+		let files = set
+			.images
+			.iter()
+			.map(|image| image.file.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(files, [
+			"walk_fast_south_0.png",
+			"walk_fast_north_0.png",
+			"walk_fast_east_0.png",
+			"walk_fast_west_0.png",
+			"walk_fast_south_1.png",
+			"walk_fast_north_1.png",
+			"walk_fast_east_1.png",
+			"walk_fast_west_1.png",
+		]);
+		assert_eq!(file_stem(&IconState::default()), "default");
+	}
+
+	#[test]
+	fn test_file_stems() {
+		let states = [
+			"walk fast",
+			"walk_fast",
+			"",
+			"default",
+			"Walk_Fast",
+			"walk_fast_1",
+		]
+		.map(|name| IconState {
+			name: name.to_string(),
+			..Default::default()
+		});
+		assert_eq!(file_stems(&states.iter().collect::<Vec<_>>()), [
+			"walk_fast",
+			"walk_fast_1",
+			"default",
+			"default_1",
+			"Walk_Fast_2",
+			"walk_fast_1_1",
+		]);
+	}
+}
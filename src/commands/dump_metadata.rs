@@ -0,0 +1,14 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::DumpMetadataArgs, png, read_dmi_bytes};
+use color_eyre::eyre::{Result, WrapErr};
+use std::process::ExitCode;
+
+/// Print a DMI's metadata text exactly as it's stored in the file
+pub fn run(args: DumpMetadataArgs) -> Result<ExitCode> {
+	let bytes = read_dmi_bytes(&args.file, false)
+		.wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let description = png::dmi_description(&bytes)
+		.wrap_err_with(|| format!("failed to read metadata of {}", args.file.display()))?;
+	print!("{description}");
+	Ok(ExitCode::SUCCESS)
+}
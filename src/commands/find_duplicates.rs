@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{
+	args::{FindDuplicatesArgs, OutputFormat},
+	compare, load_dmi, paths,
+};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::Icon;
+use serde::Serialize;
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+/// Where a state was found
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Location {
+	file: PathBuf,
+	name: String,
+}
+
+/// Report the states with the same content across every DMI file in a
+/// directory, whatever their names
+pub fn run(args: FindDuplicatesArgs) -> Result<ExitCode> {
+	let files = paths::expand_all([args.dir.join("**").join("*.dmi")])
+		.wrap_err_with(|| format!("failed to find DMI files in {}", args.dir.display()))?;
+	let mut icons = Vec::new();
+	for file in files {
+		// One broken file shouldn't stop a whole directory from being checked.
+		match load_dmi(&file) {
+			Ok(icon) => icons.push((file, icon)),
+			Err(err) => eprintln!("warning: skipped {}: {err:#}", file.display()),
+		}
+	}
+	let groups = duplicates(icons.iter().map(|(file, icon)| (file.as_path(), icon)));
+
+	match args.format {
+		OutputFormat::Text if groups.is_empty() => {
+			println!("No duplicate states in {} file(s)", icons.len());
+		}
+		OutputFormat::Text => {
+			for group in &groups {
+				println!("{} states with the same content:", group.len());
+				for location in group {
+					println!("  {}: '{}'", location.file.display(), location.name);
+				}
+			}
+			println!(
+				"{} group(s) of duplicate states in {} file(s)",
+				groups.len(),
+				icons.len()
+			);
+		}
+		OutputFormat::Json => println!(
+			"{}",
+			serde_json::to_string_pretty(&groups).wrap_err("failed to serialize duplicates")?
+		),
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Group the states of some files by their content, keeping just the groups
+/// with more than one state. Groups are in the order their first state was
+/// found.
+fn duplicates<'a>(files: impl IntoIterator<Item = (&'a Path, &'a Icon)>) -> Vec<Vec<Location>> {
+	let mut groups = Vec::<Vec<Location>>::new();
+	let mut by_hash = BTreeMap::new();
+	for (file, icon) in files {
+		for state in &icon.states {
+			let location = Location {
+				file: file.to_path_buf(),
+				name: state.name.clone(),
+			};
+			let index = *by_hash
+				.entry(compare::content_hash(state))
+				.or_insert_with(|| {
+					groups.push(Vec::new());
+					groups.len() - 1
+				});
+			groups[index].push(location);
+		}
+	}
+	groups.retain(|group| group.len() > 1);
+	groups
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::IconState;
+	use image::{DynamicImage, Rgba, RgbaImage};
+
+	#[test]
+	fn test_duplicates() {
+		let state = |name: &str, pixel: u8| IconState {
+			name: name.to_string(),
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				1,
+				1,
+				Rgba([pixel; 4]),
+			))],
+			..Default::default()
+		};
+		let icon = |states| Icon {
+			states,
+			..Default::default()
+		};
+		let a = icon(vec![state("walk", 1), state("idle", 2), state("walk2", 1)]);
+		let b = icon(vec![state("run", 3), state("idle", 2)]);
+		let location = |file: &str, name: &str| Location {
+			file: PathBuf::from(file),
+			name: name.to_string(),
+		};
+		assert_eq!(
+			duplicates([(Path::new("a.dmi"), &a), (Path::new("b.dmi"), &b)]),
+			[
+				vec![location("a.dmi", "walk"), location("a.dmi", "walk2")],
+				vec![location("a.dmi", "idle"), location("b.dmi", "idle")],
+			]
+		);
+	}
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{
+	args::{InfoArgs, OutputFormat},
+	load_dmi,
+};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::Icon;
+use serde::Serialize;
+use std::process::ExitCode;
+
+/// A summary of a single DMI file
+#[derive(Debug, Serialize)]
+struct Info {
+	width: u32,
+	height: u32,
+	version: Option<String>,
+	states: usize,
+	animated_states: usize,
+	frames: u64,
+}
+
+/// Print a summary of a single DMI file
+pub fn run(args: InfoArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let info = Info {
+		width: icon.width,
+		height: icon.height,
+		version: dmi_version(&icon),
+		states: icon.states.len(),
+		animated_states: icon.states.iter().filter(|state| state.frames > 1).count(),
+		frames: icon.states.iter().map(|state| state.frames as u64).sum(),
+	};
+
+	match args.format {
+		OutputFormat::Text => {
+			println!("{}", args.file.display());
+			println!("  dimensions: {}x{}", info.width, info.height);
+			if let Some(version) = &info.version {
+				println!("  version: {version}");
+			}
+			println!(
+				"  states: {} ({} animated)",
+				info.states, info.animated_states
+			);
+			println!("  frames: {}", info.frames);
+		}
+		OutputFormat::Json => println!(
+			"{}",
+			serde_json::to_string_pretty(&info).wrap_err("failed to serialize info")?
+		),
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Get the DMI format version of an icon.
+///
+/// The dmi crate doesn't expose the version string, only its `Debug`
+/// representation, so we have to pull it out of that.
+fn dmi_version(icon: &Icon) -> Option<String> {
+	let debug = format!("{:?}", icon.version);
+	let version = debug.strip_prefix("DmiVersion(\"")?.strip_suffix("\")")?;
+	Some(version.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::schema::{assert_matches_schema, SchemaKind};
+
+	#[test]
+	fn test_dmi_version() {
+		assert_eq!(dmi_version(&Icon::default()).as_deref(), Some("4.0"));
+	}
+
+	#[test]
+	fn test_info_matches_schema() {
+		let info = Info {
+			width: 32,
+			height: 32,
+			version: None,
+			states: 1,
+			animated_states: 0,
+			frames: 1,
+		};
+		let value = serde_json::to_value(info).unwrap();
+		assert_matches_schema(SchemaKind::Info, "", &value);
+	}
+}
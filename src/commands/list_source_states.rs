@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::load_dmi_with;
+use color_eyre::eyre::{Result, WrapErr};
+use serde::Serialize;
+use std::{
+	path::{Path, PathBuf},
+	process::ExitCode,
+};
+
+/// The metadata of a single state, as listed for tools choosing what to copy
+#[derive(Debug, Serialize)]
+struct StateListing<'a> {
+	source: &'a Path,
+	name: &'a str,
+	dirs: u8,
+	frames: u32,
+	delays: Option<&'a [f32]>,
+}
+
+/// Print every state in the source files as JSON
+pub fn run(from: &[PathBuf], gzip: bool) -> Result<ExitCode> {
+	let icons = from
+		.iter()
+		.map(|path| {
+			load_dmi_with(path, gzip)
+				.wrap_err_with(|| format!("failed to read input file {}", path.display()))
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let states = from
+		.iter()
+		.zip(&icons)
+		.flat_map(|(path, icon)| {
+			icon.states.iter().map(move |state| StateListing {
+				source: path,
+				name: &state.name,
+				dirs: state.dirs,
+				frames: state.frames,
+				delays: state.delay.as_deref(),
+			})
+		})
+		.collect::<Vec<_>>();
+	println!(
+		"{}",
+		serde_json::to_string_pretty(&states).wrap_err("failed to serialize states")?
+	);
+	Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::schema::{assert_matches_schema, SchemaKind};
+
+	#[test]
+	fn test_listing_matches_schema() {
+		let listing = StateListing {
+			source: Path::new("a.dmi"),
+			name: "walk",
+			dirs: 1,
+			frames: 2,
+			delays: Some(&[1.0, 2.0]),
+		};
+		let value = serde_json::to_value(listing).unwrap();
+		assert_matches_schema(SchemaKind::SourceStates, "/items", &value);
+	}
+}
@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: MPL-2.0
+pub mod apply_patch;
+pub mod assemble_frames;
+pub mod assert_eq;
+pub mod browse;
+pub mod check_colors;
+pub mod check_inventory;
+pub mod dump_frames;
+pub mod dump_metadata;
+pub mod find_duplicates;
+pub mod info;
+pub mod list_source_states;
+pub mod normalize;
+pub mod preview;
+pub mod probe;
+pub mod selftest;
+pub mod strip_hotspots;
+pub mod sync;
+pub mod thumbnail;
+pub mod validate;
+
+/// Build an icon for tests with a state of each name and frame count
+#[cfg(test)]
+fn icon(states: &[(&str, u32)]) -> dmi::icon::Icon {
+	dmi::icon::Icon {
+		width: 32,
+		height: 32,
+		states: states
+			.iter()
+			.map(|&(name, frames)| dmi::icon::IconState {
+				name: name.to_string(),
+				frames,
+				..Default::default()
+			})
+			.collect(),
+		..Default::default()
+	}
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::NormalizeArgs, parse_dmi, read_dmi_bytes, save_dmi, SaveOptions};
+use color_eyre::eyre::{Result, WrapErr};
+use std::{fs, process::ExitCode};
+
+/// Load a DMI file and save it again in place, without changing its states
+pub fn run(args: NormalizeArgs) -> Result<ExitCode> {
+	let before = fs::metadata(&args.file).map(|metadata| metadata.len()).ok();
+	let bytes = read_dmi_bytes(&args.file, false)
+		.wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let icon =
+		parse_dmi(&bytes).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	save_dmi(icon, &args.file, &SaveOptions {
+		canonical: args.canonical,
+		compression: args.compression,
+		chunks_from: args.preserve_chunks.then_some(&bytes[..]),
+		..Default::default()
+	})
+	.wrap_err_with(|| format!("failed to save {}", args.file.display()))?;
+	let after = fs::metadata(&args.file).map(|metadata| metadata.len()).ok();
+	match (before, after) {
+		(Some(before), Some(after)) if args.human => println!(
+			"Normalized {} ({} → {})",
+			args.file.display(),
+			human_size(before),
+			human_size(after)
+		),
+		(Some(before), Some(after)) => println!(
+			"Normalized {} ({before} → {after} bytes)",
+			args.file.display()
+		),
+		_ => println!("Normalized {}", args.file.display()),
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Format a number of bytes in the largest binary unit it fills, like
+/// `1.5 KiB`
+pub fn human_size(bytes: u64) -> String {
+	const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+	if bytes < 1024 {
+		return format!("{bytes} B");
+	}
+	let mut size = bytes as f64 / 1024.0;
+	let mut unit = 0;
+	while size >= 1024.0 && unit + 1 < UNITS.len() {
+		size /= 1024.0;
+		unit += 1;
+	}
+	format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_human_size() {
+		assert_eq!(human_size(0), "0 B");
+		assert_eq!(human_size(1023), "1023 B");
+		assert_eq!(human_size(1536), "1.5 KiB");
+		assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+		assert_eq!(human_size(u64::MAX), "16777216.0 TiB");
+	}
+}
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::PreviewArgs, load_dmi, render};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::Looping;
+use std::{
+	io::{self, IsTerminal, Write},
+	process::ExitCode,
+	thread,
+	time::Duration,
+};
+
+/// How long one tick of a state's delay lasts
+const TICK: Duration = Duration::from_millis(100);
+
+/// Show a state of a DMI file in the terminal
+pub fn run(args: PreviewArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let state = match &args.state {
+		Some(name) => icon
+			.states
+			.iter()
+			.find(|state| &state.name == name)
+			.ok_or_else(|| eyre!("{} has no state named '{name}'", args.file.display()))?,
+		None => icon
+			.states
+			.first()
+			.ok_or_else(|| eyre!("{} has no icon states", args.file.display()))?,
+	};
+	let dir = args.dir.index();
+	if dir >= state.dirs as usize {
+		return Err(eyre!(
+			"state '{}' has {} dir(s), so it has no {} dir",
+			state.name,
+			state.dirs,
+			args.dir
+		));
+	}
+	let color = io::stdout().is_terminal();
+	let frame = |index: usize| {
+		state
+			.images
+			.get(index * state.dirs as usize + dir)
+			.ok_or_else(|| eyre!("state '{}' is missing frame {index}", state.name))
+	};
+
+	if !args.animate || state.frames <= 1 {
+		print!("{}", render::preview(frame(0)?, color));
+		return Ok(ExitCode::SUCCESS);
+	}
+	// Redrawing in place needs cursor movement, which only a terminal does.
+	if !color {
+		return Err(eyre!("--animate needs stdout to be a terminal"));
+	}
+
+	let order = frame_order(state.frames as usize, state.rewind);
+	let mut stdout = io::stdout().lock();
+	let mut drawn_lines = 0;
+	let mut loops = 0;
+	loop {
+		for &index in &order {
+			if drawn_lines > 0 {
+				write!(stdout, "\x1b[{drawn_lines}A")?;
+			}
+			let drawn = render::preview(frame(index)?, color);
+			drawn_lines = drawn.lines().count();
+			write!(stdout, "{drawn}")?;
+			stdout.flush()?;
+			let ticks = state
+				.delay
+				.as_ref()
+				.and_then(|delays| delays.get(index))
+				.copied()
+				.unwrap_or(1.0);
+			thread::sleep(TICK.mul_f32(ticks.max(0.0)));
+		}
+		loops += 1;
+		if let Looping::NTimes(times) = state.loop_flag {
+			if loops >= times.get() {
+				break;
+			}
+		}
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+/// The order a state's frames are played in over one loop, which goes back
+/// through them if the state rewinds
+fn frame_order(frames: usize, rewind: bool) -> Vec<usize> {
+	let mut order: Vec<usize> = (0..frames).collect();
+	if rewind && frames > 2 {
+		order.extend((1..frames - 1).rev());
+	}
+	order
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_frame_order() {
+		assert_eq!(frame_order(3, false), [0, 1, 2]);
+		assert_eq!(frame_order(4, true), [0, 1, 2, 3, 2, 1]);
+		assert_eq!(frame_order(2, true), [0, 1]);
+	}
+}
@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::ProbeArgs, load_dmi};
+use color_eyre::eyre::{Result, WrapErr};
+use std::process::ExitCode;
+
+/// Check whether a DMI file has the given states, reporting the answer only
+/// through the exit code
+pub fn run(args: ProbeArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let mut found = args
+		.states
+		.iter()
+		.map(|name| icon.states.iter().any(|state| &state.name == name));
+	let present = if args.any_present {
+		found.any(|found| found)
+	} else {
+		found.all(|found| found)
+	};
+	Ok(if present {
+		ExitCode::SUCCESS
+	} else {
+		ExitCode::FAILURE
+	})
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::SelftestArgs, compare, load_dmi, write_dmi, SaveOptions};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::Icon;
+use std::process::ExitCode;
+
+/// Check that a DMI file survives being saved and loaded again unchanged,
+/// printing every state that diverged
+pub fn run(args: SelftestArgs) -> Result<ExitCode> {
+	let original =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let mut temp = tempfile::NamedTempFile::new().wrap_err("failed to create temporary file")?;
+	write_dmi(&original, temp.as_file_mut(), &SaveOptions::default())
+		.wrap_err("failed to save the dmi")?;
+	let reloaded = load_dmi(temp.path()).wrap_err("failed to reload the saved dmi")?;
+
+	let differences = differences(&original, &reloaded);
+	if differences.is_empty() {
+		println!(
+			"{} round-trips, all {} state(s) match",
+			args.file.display(),
+			original.states.len()
+		);
+		return Ok(ExitCode::SUCCESS);
+	}
+	println!("{} doesn't round-trip:", args.file.display());
+	for difference in &differences {
+		println!("  {difference}");
+	}
+	Ok(ExitCode::FAILURE)
+}
+
+/// Describe every difference between an icon and its reloaded copy, which
+/// should have the same states in the same order
+fn differences(original: &Icon, reloaded: &Icon) -> Vec<String> {
+	let mut differences = Vec::new();
+	if (original.width, original.height) != (reloaded.width, reloaded.height) {
+		differences.push(format!(
+			"icon size changed: {}x{} became {}x{}",
+			original.width, original.height, reloaded.width, reloaded.height
+		));
+	}
+	if original.states.len() != reloaded.states.len() {
+		differences.push(format!(
+			"{} state(s) became {}",
+			original.states.len(),
+			reloaded.states.len()
+		));
+	}
+	for (index, (original, reloaded)) in original.states.iter().zip(&reloaded.states).enumerate() {
+		if original.name != reloaded.name {
+			differences.push(format!(
+				"state {} was renamed from '{}' to '{}'",
+				index + 1,
+				original.name,
+				reloaded.name
+			));
+			continue;
+		}
+		let fields = compare::differing_fields(original, reloaded);
+		if !fields.is_empty() {
+			differences.push(format!(
+				"state '{}' changed: {}",
+				original.name,
+				fields.join(", ")
+			));
+		}
+	}
+	differences
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::commands::icon;
+
+	#[test]
+	fn test_differences() {
+		let original = icon(&[("a", 1), ("b", 1), ("c", 1)]);
+		assert!(differences(&original, &original).is_empty());
+		let reloaded = icon(&[("a", 2), ("c", 1)]);
+		assert_eq!(differences(&original, &reloaded), vec![
+			"3 state(s) became 2",
+			"state 'a' changed: frames",
+			"state 2 was renamed from 'b' to 'c'",
+		]);
+	}
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::StripHotspotsArgs, load_dmi, save_dmi, SaveOptions};
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::Icon;
+use std::process::ExitCode;
+
+/// Remove the hotspot from every state in a DMI file and save it in place
+pub fn run(args: StripHotspotsArgs) -> Result<ExitCode> {
+	let mut icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	let stripped = strip_hotspots(&mut icon);
+	if stripped > 0 {
+		save_dmi(icon, &args.file, &SaveOptions::default())
+			.wrap_err_with(|| format!("failed to save {}", args.file.display()))?;
+	}
+	println!(
+		"Removed hotspots from {stripped} state(s) in {}",
+		args.file.display()
+	);
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Remove every state's hotspot, returning how many states had one
+fn strip_hotspots(icon: &mut Icon) -> usize {
+	icon.states
+		.iter_mut()
+		.filter_map(|state| state.hotspot.take())
+		.count()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::{Hotspot, IconState};
+
+	#[test]
+	fn test_strip_hotspots() {
+		let state = |name: &str, hotspot: Option<Hotspot>| IconState {
+			name: name.to_string(),
+			hotspot,
+			..Default::default()
+		};
+		let mut icon = Icon {
+			states: vec![
+				state("a", Some(Hotspot { x: 1, y: 2 })),
+				state("b", None),
+				state("c", Some(Hotspot { x: 3, y: 4 })),
+			],
+			..Default::default()
+		};
+		assert_eq!(strip_hotspots(&mut icon), 2);
+		assert!(icon.states.iter().all(|state| state.hotspot.is_none()));
+		assert_eq!(strip_hotspots(&mut icon), 0);
+	}
+}
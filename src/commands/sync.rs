@@ -0,0 +1,270 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{
+	args::{FrameMatching, SyncArgs, SyncPreference},
+	checksum, compare,
+	conflict::ConflictPolicy,
+	load_dmi, merge_into, prepare_save,
+	report::{CopyReport, LogFormat, Verbosity},
+	MergeOptions, SaveOptions,
+};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{Icon, IconState};
+use std::{collections::BTreeMap, fs, path::Path, process::ExitCode};
+
+/// The content hash of every state as of the last sync, by name
+type Base = BTreeMap<String, String>;
+
+/// Which way a state is copied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+	AToB,
+	BToA,
+}
+
+/// A single state to copy from one file to the other
+#[derive(Debug, PartialEq, Eq)]
+struct Move<'a> {
+	name: &'a str,
+	flow: Flow,
+	/// Whether the state replaces a different one, rather than being added
+	replaces: bool,
+}
+
+/// Copy states between two DMI files until they have the same states
+pub fn run(args: SyncArgs) -> Result<ExitCode> {
+	let a = load_dmi(&args.a).wrap_err_with(|| format!("failed to read {}", args.a.display()))?;
+	let b = load_dmi(&args.b).wrap_err_with(|| format!("failed to read {}", args.b.display()))?;
+	let a_wins = match args.prefer {
+		Some(SyncPreference::A) => Some(true),
+		Some(SyncPreference::B) => Some(false),
+		Some(SyncPreference::Newer) => Some(modified(&args.a)? >= modified(&args.b)?),
+		None => None,
+	};
+	let base = match &args.base {
+		Some(path) if path.exists() => {
+			let base = fs::read_to_string(path)
+				.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+			serde_json::from_str::<Base>(&base)
+				.wrap_err_with(|| format!("invalid sync base {}", path.display()))?
+		}
+		_ => Base::new(),
+	};
+
+	let moves = plan(&a, &b, &base, a_wins)?;
+	for step in &moves {
+		let (from, to) = match step.flow {
+			Flow::AToB => (&args.a, &args.b),
+			Flow::BToA => (&args.b, &args.a),
+		};
+		let verb = if step.replaces { "replaced" } else { "added" };
+		println!(
+			"State '{}': {} → {} ({verb})",
+			step.name,
+			from.display(),
+			to.display()
+		);
+	}
+	let count = |flow| moves.iter().filter(|step| step.flow == flow).count();
+	let (to_b, to_a) = (count(Flow::AToB), count(Flow::BToA));
+	println!(
+		"{to_b} state(s) synced to {}, {to_a} to {}",
+		args.b.display(),
+		args.a.display()
+	);
+	if args.dry_run {
+		println!("dry run, nothing was written");
+		return Ok(ExitCode::SUCCESS);
+	}
+
+	let (new_a, new_b) = apply(&a, &b, &moves)?;
+	let new_base = base_of(&new_a);
+	// Both files are written out before either is replaced, so a failure to
+	// write one leaves both untouched.
+	let mut pending = Vec::new();
+	for (icon, path, count) in [(new_a, &args.a, to_a), (new_b, &args.b, to_b)] {
+		if count > 0 {
+			let save = prepare_save(icon, path, &SaveOptions::default())
+				.wrap_err_with(|| format!("failed to save {}", path.display()))?;
+			pending.push((save, path));
+		}
+	}
+	for (save, path) in pending {
+		save.commit()
+			.wrap_err_with(|| format!("failed to save {}", path.display()))?;
+	}
+	if let Some(path) = &args.base {
+		let json = serde_json::to_string_pretty(&new_base).wrap_err("failed to serialize base")?;
+		fs::write(path, json + "\n")
+			.wrap_err_with(|| format!("failed to write {}", path.display()))?;
+	}
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Record the content of every state, to tell which file changed a state the
+/// next time they're synced
+fn base_of(icon: &Icon) -> Base {
+	icon.states
+		.iter()
+		.map(|state| {
+			let hash = checksum::to_hex(&compare::content_hash(state));
+			(state.name.clone(), hash)
+		})
+		.collect()
+}
+
+fn modified(path: &Path) -> Result<std::time::SystemTime> {
+	fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.wrap_err_with(|| format!("failed to read modification time of {}", path.display()))
+}
+
+fn find<'a>(icon: &'a Icon, name: &str) -> Option<&'a IconState> {
+	icon.states.iter().find(|state| state.name == name)
+}
+
+/// Work out which states go which way. A state in both files that differs
+/// goes from the file that changed it, if it matches the `base` in the other.
+/// If both changed it, or it isn't in the base, it goes from A to B if
+/// `a_wins`, the other way if not, and is an error if it's `None`.
+fn plan<'a>(a: &'a Icon, b: &'a Icon, base: &Base, a_wins: Option<bool>) -> Result<Vec<Move<'a>>> {
+	let unchanged = |state: &IconState| {
+		base.get(&state.name)
+			.is_some_and(|hash| *hash == checksum::to_hex(&compare::content_hash(state)))
+	};
+	let mut moves = Vec::new();
+	let mut conflicts = Vec::new();
+	for state in &a.states {
+		if moves.iter().any(|step: &Move| step.name == state.name) {
+			continue;
+		}
+		match find(b, &state.name) {
+			None => moves.push(Move {
+				name: &state.name,
+				flow: Flow::AToB,
+				replaces: false,
+			}),
+			Some(other) if other == state => {}
+			Some(other) if unchanged(other) => moves.push(Move {
+				name: &state.name,
+				flow: Flow::AToB,
+				replaces: true,
+			}),
+			Some(_) if unchanged(state) => moves.push(Move {
+				name: &state.name,
+				flow: Flow::BToA,
+				replaces: true,
+			}),
+			Some(_) => match a_wins {
+				Some(a_wins) => moves.push(Move {
+					name: &state.name,
+					flow: if a_wins { Flow::AToB } else { Flow::BToA },
+					replaces: true,
+				}),
+				None => conflicts.push(format!("'{}'", state.name)),
+			},
+		}
+	}
+	for state in &b.states {
+		if find(a, &state.name).is_none() && !moves.iter().any(|step| step.name == state.name) {
+			moves.push(Move {
+				name: &state.name,
+				flow: Flow::BToA,
+				replaces: false,
+			});
+		}
+	}
+	if !conflicts.is_empty() {
+		return Err(eyre!(
+			"state(s) {} differ between the files, use --prefer to pick a side, or --base to tell \
+			 which file changed them",
+			conflicts.join(", ")
+		));
+	}
+	Ok(moves)
+}
+
+/// Copy the planned states, returning the new contents of both files
+fn apply(a: &Icon, b: &Icon, moves: &[Move]) -> Result<(Icon, Icon)> {
+	let (mut new_a, mut new_b) = (a.clone(), b.clone());
+	// Which side wins has already been decided, so states are replaced as
+	// they are.
+	let options = MergeOptions {
+		on_conflict: ConflictPolicy::Always,
+		match_frames: Some(FrameMatching::Replace),
+		..Default::default()
+	};
+	// The moves were already printed by the plan.
+	let mut report = CopyReport::new(&[], Path::new(""), Verbosity::Quiet, LogFormat::Text);
+	for step in moves {
+		let (from, to) = match step.flow {
+			Flow::AToB => (a, &mut new_b),
+			Flow::BToA => (b, &mut new_a),
+		};
+		let state = find(from, step.name)
+			.expect("planned states exist in their source")
+			.clone();
+		merge_into(to, state, &options, &mut report)?;
+	}
+	Ok((new_a, new_b))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::commands::icon;
+
+	#[test]
+	fn test_sync() {
+		let a = icon(&[("shared", 1), ("only_a", 1), ("changed", 1)]);
+		let b = icon(&[("only_b", 1), ("shared", 1), ("changed", 2)]);
+		assert!(plan(&a, &b, &Base::new(), None).is_err());
+
+		let moves = plan(&a, &b, &Base::new(), Some(false)).unwrap();
+		assert_eq!(moves, [
+			Move {
+				name: "only_a",
+				flow: Flow::AToB,
+				replaces: false
+			},
+			Move {
+				name: "changed",
+				flow: Flow::BToA,
+				replaces: true
+			},
+			Move {
+				name: "only_b",
+				flow: Flow::BToA,
+				replaces: false
+			},
+		]);
+		let (new_a, new_b) = apply(&a, &b, &moves).unwrap();
+		assert_eq!(
+			new_a,
+			icon(&[("shared", 1), ("only_a", 1), ("changed", 2), ("only_b", 1)])
+		);
+		assert_eq!(
+			new_b,
+			icon(&[("only_b", 1), ("shared", 1), ("changed", 2), ("only_a", 1)])
+		);
+		assert_eq!(plan(&new_a, &new_b, &Base::new(), None).unwrap(), []);
+	}
+
+	#[test]
+	fn test_sync_with_base() {
+		let base = base_of(&icon(&[("walk", 1), ("run", 1), ("idle", 1)]));
+		let a = icon(&[("walk", 2), ("run", 1), ("idle", 3)]);
+		let b = icon(&[("walk", 1), ("run", 2), ("idle", 4)]);
+		// idle changed in both, so it still needs a preference
+		assert!(plan(&a, &b, &base, None).is_err());
+		let moves = plan(&a, &b, &base, Some(false)).unwrap();
+		let flows = moves
+			.iter()
+			.map(|step| (step.name, step.flow))
+			.collect::<Vec<_>>();
+		assert_eq!(flows, [
+			("walk", Flow::AToB),
+			("run", Flow::BToA),
+			("idle", Flow::BToA)
+		]);
+	}
+}
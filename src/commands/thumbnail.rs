@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::ThumbnailArgs, load_dmi};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use font8x8::{UnicodeFonts, BASIC_FONTS};
+use image::{imageops, ImageFormat, Rgba, RgbaImage};
+use std::process::ExitCode;
+
+/// The size of a glyph in the label font, in pixels
+const GLYPH_SIZE: u32 = 8;
+/// Labels longer than this many characters are truncated
+const MAX_LABEL_CHARS: usize = 16;
+/// The gap around each cell, in pixels
+const PADDING: u32 = 4;
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const TEXT: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Render the first south-facing frame of every state in a DMI into a
+/// labeled grid
+pub fn run(args: ThumbnailArgs) -> Result<ExitCode> {
+	let icon =
+		load_dmi(&args.file).wrap_err_with(|| format!("failed to read {}", args.file.display()))?;
+	if icon.states.is_empty() {
+		return Err(eyre!("{} has no icon states", args.file.display()));
+	}
+
+	let labels = icon
+		.states
+		.iter()
+		.map(|state| label(&state.name))
+		.collect::<Vec<_>>();
+	let (label_chars, label_height) = if args.no_labels {
+		(0, 0)
+	} else {
+		let longest = labels.iter().map(|label| label.chars().count()).max();
+		(longest.unwrap_or(0), GLYPH_SIZE + PADDING)
+	};
+	let cell_width = icon.width.max(label_chars as u32 * GLYPH_SIZE) + PADDING;
+	let cell_height = icon.height + label_height + PADDING;
+
+	let count = icon.states.len() as u32;
+	let columns = args
+		.columns
+		.unwrap_or_else(|| (count as f64).sqrt().ceil() as u32)
+		.min(count);
+	let rows = count.div_ceil(columns);
+	let mut sheet = RgbaImage::from_pixel(
+		columns * cell_width + PADDING,
+		rows * cell_height + PADDING,
+		BACKGROUND,
+	);
+
+	for (index, (state, label)) in icon.states.iter().zip(&labels).enumerate() {
+		let index = index as u32;
+		let x = PADDING + (index % columns) * cell_width;
+		let y = PADDING + (index / columns) * cell_height;
+		if let Some(image) = state.images.first() {
+			let offset = (cell_width - PADDING - icon.width) / 2;
+			imageops::overlay(&mut sheet, &image.to_rgba8(), (x + offset).into(), y.into());
+		}
+		if !args.no_labels {
+			let width = label.chars().count() as u32 * GLYPH_SIZE;
+			let offset = (cell_width - PADDING - width) / 2;
+			draw_text(&mut sheet, label, x + offset, y + icon.height + PADDING);
+		}
+	}
+
+	sheet
+		.save_with_format(&args.output, ImageFormat::Png)
+		.wrap_err_with(|| format!("failed to save {}", args.output.display()))?;
+	Ok(ExitCode::SUCCESS)
+}
+
+/// The label drawn under a state, truncated to fit
+fn label(name: &str) -> String {
+	let name = if name.is_empty() { "(default)" } else { name };
+	if name.chars().count() > MAX_LABEL_CHARS {
+		let mut label = name.chars().take(MAX_LABEL_CHARS - 1).collect::<String>();
+		label.push('~');
+		label
+	} else {
+		name.to_string()
+	}
+}
+
+/// Draw text with the top-left corner at `(x, y)`, replacing characters the
+/// font lacks with `?`
+fn draw_text(image: &mut RgbaImage, text: &str, x: u32, y: u32) {
+	for (index, c) in text.chars().enumerate() {
+		let glyph = BASIC_FONTS
+			.get(c)
+			.or_else(|| BASIC_FONTS.get('?'))
+			.unwrap_or_default();
+		let glyph_x = x + index as u32 * GLYPH_SIZE;
+		for (row, bits) in glyph.iter().enumerate() {
+			for column in 0..GLYPH_SIZE {
+				// The least significant bit is the leftmost pixel
+				if bits & (1 << column) != 0 {
+					let (px, py) = (glyph_x + column, y + row as u32);
+					if px < image.width() && py < image.height() {
+						image.put_pixel(px, py, TEXT);
+					}
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_label() {
+		assert_eq!(label("walk"), "walk");
+		assert_eq!(label(""), "(default)");
+		assert_eq!(label("a_very_long_state_name"), "a_very_long_sta~");
+	}
+}
@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{args::ValidateArgs, parse_dmi, png, read_dmi_bytes, validate};
+use color_eyre::eyre::Result;
+use dmi::icon::Icon;
+use std::{path::Path, process::ExitCode};
+
+/// Check that DMI files load and can be saved again, and with `--strict-png`
+/// that their PNG structure is sound, printing the problems with each file
+pub fn run(args: ValidateArgs) -> Result<ExitCode> {
+	let mut invalid = 0;
+	for file in &args.files {
+		let problems = problems(file, &args);
+		if problems.is_empty() {
+			println!("{}: OK", file.display());
+			continue;
+		}
+		invalid += 1;
+		println!("{}:", file.display());
+		for problem in &problems {
+			println!("  {problem}");
+		}
+	}
+	if invalid == 0 {
+		return Ok(ExitCode::SUCCESS);
+	}
+	println!("{invalid} of {} file(s) are invalid", args.files.len());
+	Ok(ExitCode::FAILURE)
+}
+
+/// List everything wrong with a single file
+fn problems(file: &Path, args: &ValidateArgs) -> Vec<String> {
+	let bytes = match read_dmi_bytes(file, false) {
+		Ok(bytes) => bytes,
+		Err(err) => return vec![format!("{err:#}")],
+	};
+	let mut problems = if args.strict_png {
+		png::structure_problems(&bytes)
+	} else {
+		Vec::new()
+	};
+	match parse_dmi(&bytes) {
+		Ok(icon) => {
+			if let Err(err) = validate::check(&icon) {
+				problems.push(format!("{err:#}"));
+			}
+			if let Some(max) = args.max_total_frames {
+				problems.extend(frame_budget_problem(&icon, max));
+			}
+		}
+		Err(err) => problems.push(format!("{err:#}")),
+	}
+	problems
+}
+
+/// Describe how far over a budget the total frames of an icon's states are,
+/// if they are
+fn frame_budget_problem(icon: &Icon, max: u64) -> Option<String> {
+	let total = icon
+		.states
+		.iter()
+		.map(|state| state.frames as u64)
+		.sum::<u64>();
+	(total > max).then(|| format!("has {total} frames in total, more than the budget of {max}"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::IconState;
+
+	#[test]
+	fn test_frame_budget_problem() {
+		let state = |frames| IconState {
+			frames,
+			..Default::default()
+		};
+		let icon = Icon {
+			states: vec![state(4), state(2), state(1)],
+			..Default::default()
+		};
+		assert_eq!(frame_budget_problem(&icon, 7), None);
+		assert_eq!(
+			frame_budget_problem(&icon, 6).as_deref(),
+			Some("has 7 frames in total, more than the budget of 6")
+		);
+	}
+}
@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{Icon, IconState};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt};
+
+/// A metadata field that can be left out when deciding whether two states
+/// are identical
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum IgnorableField {
+	/// Frame delays
+	Delays,
+	/// The loop flag
+	Loop,
+	/// The rewind flag
+	Rewind,
+	/// The movement flag
+	Movement,
+	/// Hotspots
+	Hotspots,
+	/// Settings the dmi crate doesn't know about
+	UnknownSettings,
+}
+
+impl IgnorableField {
+	/// The name of the field in a [`FieldComparison`]
+	fn field(self) -> &'static str {
+		match self {
+			Self::Delays => "delay",
+			Self::Loop => "loop",
+			Self::Rewind => "rewind",
+			Self::Movement => "movement",
+			Self::Hotspots => "hotspot",
+			Self::UnknownSettings => "unknown settings",
+		}
+	}
+}
+
+/// The result of comparing one field of two icon states
+#[derive(Debug)]
+pub struct FieldComparison {
+	/// The name of the field
+	pub field: &'static str,
+	/// Whether the field is the same in both states
+	pub equal: bool,
+	/// A summary of the first state's value
+	pub old: String,
+	/// A summary of the second state's value
+	pub new: String,
+	/// How the field is compared, if it's more than a plain equality check
+	pub method: Option<&'static str>,
+}
+
+impl fmt::Display for FieldComparison {
+	/// Formats the change in the field, like `frames: 4 → 6`
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.old == self.new {
+			// The summaries don't capture every difference, like in pixels.
+			write!(f, "{}: changed", self.field)
+		} else {
+			write!(f, "{}: {} → {}", self.field, self.old, self.new)
+		}
+	}
+}
+
+/// Compare every field (other than the name) of two icon states, which is
+/// exactly what deciding whether they're identical checks
+pub fn compare_fields(a: &IconState, b: &IconState) -> Vec<FieldComparison> {
+	fn field<T: PartialEq>(
+		field: &'static str,
+		a: &T,
+		b: &T,
+		summary: impl Fn(&T) -> String,
+	) -> FieldComparison {
+		FieldComparison {
+			field,
+			equal: a == b,
+			old: summary(a),
+			new: summary(b),
+			method: None,
+		}
+	}
+	let optional = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+	vec![
+		field("dirs", &a.dirs, &b.dirs, u8::to_string),
+		field("frames", &a.frames, &b.frames, u32::to_string),
+		FieldComparison {
+			method: Some("compared by size, color type, and every pixel"),
+			..field("images", &a.images, &b.images, |images| {
+				format!("{} image(s)", images.len())
+			})
+		},
+		field("delay", &a.delay, &b.delay, |delay| {
+			optional(delay.as_ref().map(|delay| format!("{delay:?}")))
+		}),
+		field("loop", &a.loop_flag, &b.loop_flag, |loop_flag| {
+			format!("{loop_flag:?}")
+		}),
+		field("rewind", &a.rewind, &b.rewind, bool::to_string),
+		field("movement", &a.movement, &b.movement, bool::to_string),
+		field("hotspot", &a.hotspot, &b.hotspot, |hotspot| {
+			optional(hotspot.map(|hotspot| format!("{},{}", hotspot.x, hotspot.y)))
+		}),
+		field(
+			"unknown settings",
+			&a.unknown_settings,
+			&b.unknown_settings,
+			|settings| {
+				format!(
+					"{} setting(s)",
+					settings.as_ref().map_or(0, |settings| settings.len())
+				)
+			},
+		),
+	]
+}
+
+/// Whether two icon states are identical in every field (other than the
+/// name) that isn't ignored
+pub fn equal_ignoring(a: &IconState, b: &IconState, ignored: &[IgnorableField]) -> bool {
+	if ignored.is_empty() {
+		return a == b;
+	}
+	compare_fields(a, b)
+		.iter()
+		.all(|comparison| comparison.equal || is_ignored(comparison.field, ignored))
+}
+
+/// Whether two icon states have the same content, whatever their names
+pub fn same_content(a: &IconState, b: &IconState) -> bool {
+	// Cheap checks first, since this is run against every state in the
+	// target, and the images last. These are the same fields compare_fields
+	// checks, without summarizing them.
+	a.dirs == b.dirs
+		&& a.frames == b.frames
+		&& a.loop_flag == b.loop_flag
+		&& a.rewind == b.rewind
+		&& a.movement == b.movement
+		&& a.hotspot == b.hotspot
+		&& a.delay == b.delay
+		&& a.unknown_settings == b.unknown_settings
+		&& a.images == b.images
+}
+
+/// How many bytes a state takes up when saved on its own, as a rough measure
+/// of how much it adds to a file
+pub fn encoded_size(state: &IconState) -> Result<usize> {
+	let image = state
+		.images
+		.first()
+		.ok_or_else(|| eyre!("state '{}' has no images", state.name))?;
+	let icon = Icon {
+		width: image.width(),
+		height: image.height(),
+		states: vec![state.clone()],
+		..Default::default()
+	};
+	let mut bytes = Vec::new();
+	icon.save(&mut bytes)
+		.wrap_err_with(|| format!("failed to encode state '{}'", state.name))?;
+	Ok(bytes.len())
+}
+
+/// Hash every field (other than the name) of an icon state, so that states
+/// with the same content, as decided by [`same_content`], hash the same
+pub fn content_hash(state: &IconState) -> [u8; 32] {
+	let mut hasher = Sha256::new();
+	// Each field is length-prefixed, so no two states hash the same input.
+	let mut field = |bytes: &[u8]| {
+		hasher.update((bytes.len() as u64).to_le_bytes());
+		hasher.update(bytes);
+	};
+	field(&[state.dirs]);
+	field(&state.frames.to_le_bytes());
+	for image in &state.images {
+		field(format!("{:?}", image.color()).as_bytes());
+		field(&image.width().to_le_bytes());
+		field(&image.height().to_le_bytes());
+		field(image.as_bytes());
+	}
+	let delay = state.delay.as_ref().map(|delay| {
+		delay
+			.iter()
+			.flat_map(|delay| delay.to_le_bytes())
+			.collect::<Vec<_>>()
+	});
+	field(format!("{delay:?}").as_bytes());
+	field(format!("{:?}", state.loop_flag).as_bytes());
+	field(&[state.rewind as u8, state.movement as u8]);
+	field(format!("{:?}", state.hotspot.map(|hotspot| (hotspot.x, hotspot.y))).as_bytes());
+	// Settings are sorted, since a HashMap's order isn't stable.
+	let settings = state
+		.unknown_settings
+		.as_ref()
+		.map(|settings| settings.iter().collect::<BTreeMap<_, _>>());
+	field(format!("{settings:?}").as_bytes());
+	hasher.finalize().into()
+}
+
+/// Whether a field of a [`FieldComparison`] is ignored
+pub fn is_ignored(field: &str, ignored: &[IgnorableField]) -> bool {
+	ignored.iter().any(|ignored| ignored.field() == field)
+}
+
+/// List the fields (other than the name) in which two icon states differ
+pub fn differing_fields(a: &IconState, b: &IconState) -> Vec<&'static str> {
+	compare_fields(a, b)
+		.into_iter()
+		.filter(|comparison| !comparison.equal)
+		.map(|comparison| comparison.field)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{DynamicImage, Rgba, RgbaImage};
+	use std::collections::HashMap;
+
+	#[test]
+	fn test_encoded_size() {
+		let state = |image| IconState {
+			name: "walk".to_string(),
+			images: vec![DynamicImage::ImageRgba8(image)],
+			..Default::default()
+		};
+		let solid = state(RgbaImage::from_pixel(32, 32, Rgba([255, 0, 0, 255])));
+		let noisy = state(RgbaImage::from_fn(32, 32, |x, y| {
+			Rgba([
+				((x * 37) ^ (y * 91)) as u8,
+				(x * y) as u8,
+				(x + y * 7) as u8,
+				255,
+			])
+		}));
+		assert!(encoded_size(&solid).unwrap() < encoded_size(&noisy).unwrap());
+		assert!(encoded_size(&IconState::default()).is_err());
+	}
+
+	#[test]
+	fn test_content_hash() {
+		let state = |name: &str, pixel: u8| IconState {
+			name: name.to_string(),
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				2,
+				2,
+				Rgba([pixel; 4]),
+			))],
+			unknown_settings: Some(HashMap::from([
+				("\ta".to_string(), "1".to_string()),
+				("\tb".to_string(), "2".to_string()),
+			])),
+			..Default::default()
+		};
+		assert!(same_content(&state("walk", 1), &state("run", 1)));
+		assert_eq!(
+			content_hash(&state("walk", 1)),
+			content_hash(&state("run", 1))
+		);
+		assert_ne!(
+			content_hash(&state("walk", 1)),
+			content_hash(&state("walk", 2))
+		);
+		let rewound = IconState {
+			rewind: true,
+			..state("walk", 1)
+		};
+		assert_ne!(content_hash(&rewound), content_hash(&state("walk", 1)));
+	}
+
+	#[test]
+	fn test_field_changes() {
+		let old = IconState {
+			frames: 4,
+			..Default::default()
+		};
+		let new = IconState {
+			frames: 6,
+			delay: Some(vec![1.0; 6]),
+			..Default::default()
+		};
+		let changes = compare_fields(&old, &new)
+			.into_iter()
+			.filter(|comparison| !comparison.equal)
+			.map(|comparison| comparison.to_string())
+			.collect::<Vec<_>>();
+		assert_eq!(changes, [
+			"frames: 4 → 6",
+			"delay: none → [1.0, 1.0, 1.0, 1.0, 1.0, 1.0]"
+		]);
+	}
+
+	#[test]
+	fn test_same_content() {
+		let a = IconState {
+			name: "a".to_string(),
+			frames: 2,
+			..Default::default()
+		};
+		let b = IconState {
+			name: "b".to_string(),
+			..a.clone()
+		};
+		assert!(same_content(&a, &b));
+		let c = IconState {
+			rewind: true,
+			..b.clone()
+		};
+		assert!(!same_content(&a, &c));
+
+		// Every field compare_fields checks counts, and nothing else does
+		let changed = [
+			IconState {
+				dirs: 4,
+				..b.clone()
+			},
+			IconState {
+				frames: 3,
+				..b.clone()
+			},
+			IconState {
+				delay: Some(vec![1.0, 2.0]),
+				..b.clone()
+			},
+			IconState {
+				loop_flag: dmi::icon::Looping::new(2),
+				..b.clone()
+			},
+			IconState {
+				movement: true,
+				..b.clone()
+			},
+			IconState {
+				hotspot: Some(dmi::icon::Hotspot { x: 1, y: 1 }),
+				..b.clone()
+			},
+			IconState {
+				unknown_settings: Some(HashMap::new()),
+				..b.clone()
+			},
+			IconState {
+				images: vec![DynamicImage::ImageRgba8(RgbaImage::new(1, 1))],
+				..b.clone()
+			},
+		];
+		for state in &changed {
+			let equal = compare_fields(&a, state)
+				.iter()
+				.all(|comparison| comparison.equal);
+			assert_eq!(same_content(&a, state), equal, "{state:?}");
+			assert!(!equal);
+		}
+	}
+
+	#[test]
+	fn test_equal_ignoring() {
+		let old = IconState {
+			hotspot: Some(dmi::icon::Hotspot { x: 1, y: 2 }),
+			..Default::default()
+		};
+		let new = IconState {
+			delay: Some(vec![2.0]),
+			..Default::default()
+		};
+		assert!(!equal_ignoring(&old, &new, &[]));
+		assert!(!equal_ignoring(&old, &new, &[IgnorableField::Hotspots]));
+		assert!(equal_ignoring(&old, &new, &[
+			IgnorableField::Hotspots,
+			IgnorableField::Delays
+		]));
+		let new = IconState {
+			frames: 2,
+			..old.clone()
+		};
+		assert!(!equal_ignoring(&old, &new, &[IgnorableField::Hotspots]));
+	}
+}
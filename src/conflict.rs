@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+
+/// What to do when a copied state already exists in the target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ConflictPolicy {
+	/// Only add states the target doesn't have, leaving existing ones alone
+	MissingOnly,
+	/// Replace existing states whose content differs from the source's
+	#[default]
+	Differs,
+	/// Replace existing states, even if they're identical
+	Always,
+	/// Fail if an existing state's content differs from the source's
+	Never,
+	/// Add the source's state under the first free name of name_1, name_2,
+	/// and so on, if its content differs
+	Rename,
+	/// Keep whichever of the source's and target's states encodes to fewer
+	/// bytes, if their content differs
+	Smaller,
+}
+
+/// What happens to a single state that already exists in the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictAction {
+	/// Leave the target's state as it is, without comparing it
+	Skip,
+	/// Leave the target's state as it is, since it's identical
+	Identical,
+	/// Replace the target's state with the source's
+	Replace,
+	/// Stop the copy
+	Fail,
+	/// Keep the target's state, and add the source's under a new name
+	Rename,
+	/// Keep whichever state encodes to fewer bytes
+	KeepSmaller,
+}
+
+impl ConflictPolicy {
+	/// Whether the states need to be compared to decide what to do
+	pub fn compares(self) -> bool {
+		self != Self::MissingOnly
+	}
+
+	/// Decide what to do with a state that exists in both files
+	pub fn action(self, identical: bool) -> ConflictAction {
+		match (self, identical) {
+			(Self::MissingOnly, _) => ConflictAction::Skip,
+			(Self::Always, _) => ConflictAction::Replace,
+			(Self::Differs | Self::Never | Self::Rename | Self::Smaller, true) => {
+				ConflictAction::Identical
+			}
+			(Self::Differs, false) => ConflictAction::Replace,
+			(Self::Never, false) => ConflictAction::Fail,
+			(Self::Rename, false) => ConflictAction::Rename,
+			(Self::Smaller, false) => ConflictAction::KeepSmaller,
+		}
+	}
+}
+
+/// Pick a new name for a state that conflicts with one named `name`: the
+/// first of `name_1`, `name_2`, and so on that `taken` says is free. This only
+/// depends on which names are taken, so the same files always get the same
+/// names.
+pub fn unique_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+	(1..)
+		.map(|suffix| format!("{name}_{suffix}"))
+		.find(|candidate| !taken(candidate))
+		.expect("some suffix is free")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_conflict_actions() {
+		use ConflictAction::*;
+		let cases = [
+			(ConflictPolicy::MissingOnly, [Skip, Skip]),
+			(ConflictPolicy::Differs, [Identical, Replace]),
+			(ConflictPolicy::Always, [Replace, Replace]),
+			(ConflictPolicy::Never, [Identical, Fail]),
+			(ConflictPolicy::Rename, [Identical, Rename]),
+			(ConflictPolicy::Smaller, [Identical, KeepSmaller]),
+		];
+		for (policy, [identical, differs]) in cases {
+			assert_eq!(policy.action(true), identical, "{policy:?}");
+			assert_eq!(policy.action(false), differs, "{policy:?}");
+		}
+	}
+
+	#[test]
+	fn test_unique_name() {
+		let taken = ["walk", "walk_1", "walk_3", "idle_1"];
+		let taken = |name: &str| taken.contains(&name);
+		assert_eq!(unique_name("walk", taken), "walk_2");
+		assert_eq!(unique_name("walk", taken), "walk_2");
+		assert_eq!(unique_name("idle", taken), "idle_2");
+		assert_eq!(unique_name("run", taken), "run_1");
+		assert_eq!(unique_name("", taken), "_1");
+	}
+}
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A direction an icon state can face, declared in the order directions are
+/// stored within each frame of a DMI
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+	South,
+	North,
+	East,
+	West,
+	Southeast,
+	Southwest,
+	Northeast,
+	Northwest,
+}
+
+impl Direction {
+	/// The position of this direction's image within each frame of a state
+	pub fn index(self) -> usize {
+		self as usize
+	}
+}
+
+impl fmt::Display for Direction {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let value = self.to_possible_value().expect("no directions are skipped");
+		f.write_str(value.get_name())
+	}
+}
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+use dmi::icon::IconState;
+
+/// Whose hotspot a replaced state ends up with.
+///
+/// There's no composite conflict mode to merge hotspots in, so this applies to
+/// plain replacements, where the source's hotspot would otherwise always win.
+/// There's no union either, since the dmi crate keeps a single hotspot per
+/// state rather than one per frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum HotspotStrategy {
+	/// The source state's hotspot, or none if it has none
+	#[default]
+	Source,
+	/// The target state's hotspot, or none if it has none
+	Target,
+	/// No hotspot at all
+	None,
+}
+
+impl HotspotStrategy {
+	/// Set the hotspot of a state that replaces `target`
+	pub fn apply(self, state: &mut IconState, target: &IconState) {
+		match self {
+			Self::Source => {}
+			Self::Target => state.hotspot = target.hotspot,
+			Self::None => state.hotspot = None,
+		}
+	}
+}
+
+/// A `hotspot = x,y,frame` line from a DMI's metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHotspot {
+	pub x: u32,
+	pub y: u32,
+	pub frame: u32,
+}
+
+/// Read the hotspot lines of every state in a DMI's metadata, in the order of
+/// the states. Lines that can't be read are left out.
+pub fn read(description: &str) -> Vec<Vec<FrameHotspot>> {
+	let mut states = Vec::<Vec<FrameHotspot>>::new();
+	for line in description.lines() {
+		let Some((key, value)) = line.split_once('=') else {
+			continue;
+		};
+		match (key.trim(), states.last_mut()) {
+			("state", _) => states.push(Vec::new()),
+			("hotspot", Some(hotspots)) => {
+				let numbers = value
+					.split(',')
+					.map(|number| number.trim().parse::<u32>().ok())
+					.collect::<Option<Vec<_>>>();
+				if let Some(&[x, y, frame]) = numbers.as_deref() {
+					hotspots.push(FrameHotspot { x, y, frame });
+				}
+			}
+			_ => {}
+		}
+	}
+	states
+}
+
+/// List the ways a state's hotspots can't be copied intact. The dmi crate
+/// only keeps one hotspot per state, and saves it for the first frame, so
+/// hotspots for any other frame are lost.
+pub fn problems(
+	state: &IconState,
+	hotspots: &[FrameHotspot],
+	width: u32,
+	height: u32,
+) -> Vec<String> {
+	let mut problems = Vec::new();
+	if let Some(extra) = hotspots.iter().find(|hotspot| hotspot.frame > state.frames) {
+		problems.push(format!(
+			"has a hotspot for frame {}, but only {} frame(s)",
+			extra.frame, state.frames
+		));
+	}
+	if hotspots.len() > 1 || hotspots.iter().any(|hotspot| hotspot.frame != 1) {
+		let frames = hotspots
+			.iter()
+			.map(|hotspot| hotspot.frame.to_string())
+			.collect::<Vec<_>>()
+			.join(", ");
+		problems.push(format!(
+			"has hotspots for frame(s) {frames}, but only a single hotspot for the first frame \
+			 can be copied"
+		));
+	}
+	if let Some(hotspot) = state.hotspot {
+		if hotspot.x > width || hotspot.y > height {
+			problems.push(format!(
+				"has a hotspot at {},{}, outside its {width}x{height} icons",
+				hotspot.x, hotspot.y
+			));
+		}
+	}
+	problems
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::Hotspot;
+
+	#[test]
+	fn test_hotspot_strategy() {
+		let with = |x| IconState {
+			hotspot: Some(Hotspot { x, y: 0 }),
+			..Default::default()
+		};
+		let cases = [
+			(HotspotStrategy::Source, Some(Hotspot { x: 1, y: 0 })),
+			(HotspotStrategy::Target, Some(Hotspot { x: 2, y: 0 })),
+			(HotspotStrategy::None, None),
+		];
+		for (strategy, expected) in cases {
+			let mut state = with(1);
+			strategy.apply(&mut state, &with(2));
+			assert_eq!(state.hotspot, expected, "{strategy:?}");
+		}
+	}
+
+	#[test]
+	fn test_read() {
+		let description = [
+			"# BEGIN DMI",
+			"version = 4.0",
+			"state = \"a\"",
+			"\tdirs = 1",
+			"\thotspot = 1,2,1",
+			"\thotspot = 3,4,2",
+			"state = \"b\"",
+			"\thotspot = x,2,1",
+			"# END DMI",
+		]
+		.join("\n");
+		let hotspot = |x, y, frame| FrameHotspot { x, y, frame };
+		assert_eq!(read(&description), [
+			vec![hotspot(1, 2, 1), hotspot(3, 4, 2)],
+			vec![]
+		]);
+	}
+
+	#[test]
+	fn test_problems() {
+		let state = IconState {
+			frames: 2,
+			hotspot: Some(Hotspot { x: 3, y: 4 }),
+			..Default::default()
+		};
+		let hotspot = |frame| FrameHotspot { x: 3, y: 4, frame };
+		assert!(problems(&state, &[hotspot(1)], 32, 32).is_empty());
+		let per_frame = problems(&state, &[hotspot(1), hotspot(2)], 32, 32);
+		assert_eq!(per_frame.len(), 1);
+		assert!(per_frame[0].starts_with("has hotspots for frame(s) 1, 2"));
+		assert_eq!(problems(&state, &[hotspot(3)], 32, 32).len(), 2);
+		let outside = problems(&state, &[hotspot(1)], 2, 2);
+		assert_eq!(outside, ["has a hotspot at 3,4, outside its 2x2 icons"]);
+	}
+}
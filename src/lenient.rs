@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::png;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::{Hotspot, Icon, IconState, Looping};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::collections::HashMap;
+
+/// Load as many states of a damaged DMI as possible, returning the icon and a
+/// description of every problem that was skipped over.
+///
+/// The metadata is parsed one state at a time, so a state with a bad setting
+/// is dropped on its own. Once a state's dirs or frames can't be read, there's
+/// no way to know where the next state's images start, so it and every state
+/// after it are dropped. The image itself and the icon size do have to be
+/// readable, since nothing can be recovered without them.
+pub fn load(bytes: &[u8]) -> Result<(Icon, Vec<String>)> {
+	let description = png::dmi_description(bytes)?;
+	let mut header = HashMap::new();
+	let mut blocks = Vec::<(&str, Vec<(&str, &str)>)>::new();
+	let mut problems = Vec::new();
+	for line in description.lines() {
+		if line.trim().is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let Some((key, value)) = line.split_once('=') else {
+			problems.push(format!("skipped metadata line '{line}'"));
+			continue;
+		};
+		let (key, value) = (key.trim(), value.trim());
+		match blocks.last_mut() {
+			_ if key == "state" => blocks.push((value, Vec::new())),
+			Some((_, settings)) => settings.push((key, value)),
+			None => {
+				header.insert(key, value);
+			}
+		}
+	}
+	let size = |key: &str| {
+		header
+			.get(key)
+			.and_then(|value| value.parse::<u32>().ok())
+			.filter(|&value| value > 0)
+			.ok_or_else(|| eyre!("the icon {key} is missing or invalid"))
+	};
+	let (width, height) = (size("width")?, size("height")?);
+	let sheet = image::load_from_memory_with_format(bytes, ImageFormat::Png)
+		.wrap_err("the image data is unreadable")?;
+	let columns = sheet.width() / width;
+	let cells = columns * (sheet.height() / height);
+
+	let mut states = Vec::new();
+	let mut index = 0u32;
+	for (number, (name, settings)) in blocks.iter().enumerate() {
+		let name = name
+			.strip_prefix('"')
+			.and_then(|name| name.strip_suffix('"'))
+			.unwrap_or(name);
+		let count = |key: &str| {
+			settings
+				.iter()
+				.find(|(setting, _)| *setting == key)
+				.and_then(|(_, value)| value.parse::<u32>().ok())
+		};
+		let (Some(dirs), Some(frames)) = (count("dirs"), count("frames")) else {
+			problems.push(format!(
+				"state '{name}' has unreadable dirs or frames, so it and the {} state(s) after it \
+				 were skipped",
+				blocks.len() - number - 1
+			));
+			break;
+		};
+		// Counts this large can't be real, and just as with unreadable ones,
+		// there's no telling where the next state starts.
+		let Some((images, end)) = dirs
+			.checked_mul(frames)
+			.and_then(|images| Some((images, index.checked_add(images)?)))
+		else {
+			problems.push(format!(
+				"state '{name}' has {dirs} dirs and {frames} frames, too many images to be real, \
+				 so it and the {} state(s) after it were skipped",
+				blocks.len() - number - 1
+			));
+			break;
+		};
+		let first = index;
+		index = end;
+		if index > cells {
+			problems.push(format!(
+				"state '{name}' needs {images} image(s), but the sheet ends before them"
+			));
+			continue;
+		}
+		let state = parse_state(name, dirs, frames, settings).map(|state| IconState {
+			images: (first..index)
+				.map(|cell| {
+					let (x, y) = ((cell % columns) * width, (cell / columns) * height);
+					DynamicImage::from(sheet.view(x, y, width, height).to_image())
+				})
+				.collect(),
+			..state
+		});
+		match state {
+			Ok(state) => states.push(state),
+			Err(err) => problems.push(format!("state '{name}' was skipped, {err}")),
+		}
+	}
+
+	let icon = Icon {
+		width,
+		height,
+		states,
+		..Default::default()
+	};
+	Ok((icon, problems))
+}
+
+/// Parse the settings of a single state, without its images
+fn parse_state(name: &str, dirs: u32, frames: u32, settings: &[(&str, &str)]) -> Result<IconState> {
+	let mut state = IconState {
+		name: name.to_string(),
+		dirs: u8::try_from(dirs).map_err(|_| eyre!("it has {dirs} dirs"))?,
+		frames,
+		..Default::default()
+	};
+	let flag = |value: &str| {
+		value
+			.parse::<u8>()
+			.map(|value| value != 0)
+			.map_err(|_| eyre!("it has an invalid flag '{value}'"))
+	};
+	for &(key, value) in settings {
+		match key {
+			"dirs" | "frames" => {}
+			"delay" => {
+				state.delay = Some(
+					value
+						.split(',')
+						.map(|delay| delay.trim().parse::<f32>())
+						.collect::<Result<_, _>>()
+						.map_err(|_| eyre!("it has an invalid delay '{value}'"))?,
+				)
+			}
+			"loop" => {
+				state.loop_flag = Looping::new(
+					value
+						.parse()
+						.map_err(|_| eyre!("it has an invalid loop '{value}'"))?,
+				)
+			}
+			"rewind" => state.rewind = flag(value)?,
+			"movement" => state.movement = flag(value)?,
+			"hotspot" => {
+				let coordinates = value
+					.split(',')
+					.map(|coordinate| coordinate.trim().parse::<u32>())
+					.collect::<Result<Vec<_>, _>>();
+				let [x, y, _] = coordinates.as_deref().unwrap_or_default() else {
+					return Err(eyre!("it has an invalid hotspot '{value}'"));
+				};
+				state.hotspot = Some(Hotspot { x: *x, y: *y });
+			}
+			// The dmi crate keeps the indentation in unknown settings' names.
+			_ => {
+				state
+					.unknown_settings
+					.get_or_insert_with(HashMap::new)
+					.insert(format!("\t{key}"), value.to_string());
+			}
+		}
+	}
+	Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::RawDmi;
+	use image::{Rgba, RgbaImage};
+
+	/// Save an icon, then replace its metadata with a damaged version
+	fn damaged(icon: &Icon, damage: impl Fn(String) -> String) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		icon.save(&mut bytes).unwrap();
+		let metadata = damage(png::dmi_description(&bytes).unwrap());
+		let mut raw = RawDmi::load(&bytes[..]).unwrap();
+		raw.chunk_ztxt = Some(dmi::ztxt::create_ztxt_chunk(metadata.as_bytes()).unwrap());
+		let mut bytes = Vec::new();
+		raw.save(&mut bytes).unwrap();
+		bytes
+	}
+
+	#[test]
+	fn test_load() {
+		let state = |name: &str, shade: u8| IconState {
+			name: name.to_string(),
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+				2,
+				2,
+				Rgba([shade, 0, 0, 255]),
+			))],
+			..Default::default()
+		};
+		let icon = Icon {
+			width: 2,
+			height: 2,
+			states: vec![state("a", 1), state("b", 2), state("c", 3), state("d", 4)],
+			..Default::default()
+		};
+
+		let (loaded, problems) = load(&damaged(&icon, |metadata| metadata)).unwrap();
+		assert_eq!(loaded.states, icon.states);
+		assert!(problems.is_empty());
+
+		let bytes = damaged(&icon, |metadata| {
+			metadata.replacen("\tframes = 1\n", "\tframes = 1\n\tmovement = yes\n", 2)
+		});
+		assert!(Icon::load(&bytes[..]).is_err());
+		let (loaded, problems) = load(&bytes).unwrap();
+		let names = loaded.states.iter().map(|state| state.name.as_str());
+		assert_eq!(names.collect::<Vec<_>>(), ["c", "d"]);
+		assert_eq!(loaded.states[0], icon.states[2]);
+		assert_eq!(problems.len(), 2);
+
+		let bytes = damaged(&icon, |metadata| {
+			metadata.replace("state = \"c\"\n\tdirs = 1", "state = \"c\"\n\tdirs = ?")
+		});
+		let (loaded, problems) = load(&bytes).unwrap();
+		assert_eq!(loaded.states, icon.states[..2]);
+		assert_eq!(problems, ["state 'c' has unreadable dirs or frames, so \
+		                       it and the 1 state(s) after it were skipped"]);
+
+		let bytes = damaged(&icon, |metadata| {
+			metadata.replace(
+				"state = \"c\"\n\tdirs = 1\n\tframes = 1",
+				"state = \"c\"\n\tdirs = 65536\n\tframes = 65536",
+			)
+		});
+		let (loaded, problems) = load(&bytes).unwrap();
+		assert_eq!(loaded.states, icon.states[..2]);
+		assert_eq!(problems, ["state 'c' has 65536 dirs and 65536 frames, \
+		                       too many images to be real, so it and the 1 \
+		                       state(s) after it were skipped"]);
+
+		let bytes = damaged(&icon, |metadata| {
+			metadata
+				.replace("state = \"a\"\n\tdirs = 1", "state = \"a\"\n\tdirs = 255")
+				.replace(
+					"state = \"b\"\n\tdirs = 1\n\tframes = 1",
+					"state = \"b\"\n\tdirs = 1\n\tframes = 4294967295",
+				)
+		});
+		let (_, problems) = load(&bytes).unwrap();
+		assert!(
+			problems
+				.iter()
+				.any(|problem| problem.contains("too many images")),
+			"{problems:?}"
+		);
+	}
+}
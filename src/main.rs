@@ -8,82 +8,1425 @@
 )]
 
 mod args;
+mod cache;
+mod canonical;
+mod checksum;
+mod commands;
+mod compare;
+mod conflict;
+mod direction;
+mod hotspot;
+mod lenient;
+mod paths;
+mod png;
+mod remote;
+mod rename;
+mod render;
+mod report;
+mod schema;
+mod select;
+mod transform;
+mod validate;
 
-use color_eyre::eyre::{Result, WrapErr};
-use dmi::icon::Icon;
+use args::{
+	Command, DmiCopyArgs, FrameMatching, MissingSourcePolicy, OutputFormat, PngCompression,
+};
+use color_eyre::eyre::{eyre, Report, Result, WrapErr};
+use compare::IgnorableField;
+use conflict::{ConflictAction, ConflictPolicy};
+use direction::Direction;
+use dmi::icon::{Icon, IconState};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hotspot::HotspotStrategy;
+use rename::RenameMap;
+use report::{CopyReport, LogFormat};
+use select::{normalize_name, StateMatcher};
 use std::{
+	collections::{HashMap, HashSet},
 	fs::File,
-	io::{BufReader, BufWriter},
-	path::Path,
+	io::{BufReader, BufWriter, Read, Write},
+	path::{Path, PathBuf},
+	process::ExitCode,
+	time::Duration,
 };
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
 	color_eyre::install()?;
-	let args = args::DmiCopyArgs::parse().wrap_err("failed to parse arguments")?;
-	let from = load_dmi(&args.from)
-		.wrap_err_with(|| format!("failed to read input file {}", args.from.display()))?;
-	let mut to = load_dmi(&args.to)
-		.wrap_err_with(|| format!("failed to read output file {}", args.from.display()))?;
+	match Command::parse().wrap_err("failed to parse arguments")? {
+		Command::Copy(args) => copy(*args),
+		Command::AssertEq(args) => commands::assert_eq::run(args),
+		Command::Info(args) => commands::info::run(args),
+		Command::Thumbnail(args) => commands::thumbnail::run(args),
+		Command::StripHotspots(args) => commands::strip_hotspots::run(args),
+		Command::Probe(args) => commands::probe::run(args),
+		Command::DumpMetadata(args) => commands::dump_metadata::run(args),
+		Command::Normalize(args) => commands::normalize::run(args),
+		Command::CheckInventory(args) => commands::check_inventory::run(args),
+		Command::CheckColors(args) => commands::check_colors::run(args),
+		Command::FindDuplicates(args) => commands::find_duplicates::run(args),
+		Command::ApplyPatch(args) => commands::apply_patch::run(args),
+		Command::Sync(args) => commands::sync::run(args),
+		Command::Browse(args) => commands::browse::run(args),
+		Command::Preview(args) => commands::preview::run(args),
+		Command::Selftest(args) => commands::selftest::run(args),
+		Command::Validate(args) => commands::validate::run(args),
+		Command::DumpFrames(args) => commands::dump_frames::run(args),
+		Command::AssembleFrames(args) => commands::assemble_frames::run(args),
+		Command::VerifyChecksums(manifest) => checksum::verify_manifest(&manifest),
+		Command::ListSourceStates { from, gzip } => commands::list_source_states::run(&from, gzip),
+	}
+}
 
-	let states_to_insert = from
-		.states
+fn copy(args: DmiCopyArgs) -> Result<ExitCode> {
+	if args.log_format == LogFormat::Tracing {
+		// An embedding tool may already have installed its own subscriber.
+		let _ = tracing_subscriber::fmt().try_init();
+	}
+	let mut report = CopyReport::new(&args.from, &args.to, args.verbosity, args.log_format);
+
+	// Fail before doing any work if we won't be able to write the result.
+	if let Err(err) = check_writable(&args.to) {
+		let err = err.wrap_err(format!("can't write to {}", args.to.display()));
+		if args.dry_run {
+			report.warn(format_args!("{err:#}"));
+		} else {
+			return Err(err);
+		}
+	}
+
+	if let Some(cache_dir) = &args.cache_dir {
+		cache::check_dir(cache_dir).wrap_err("invalid cache dir")?;
+	}
+	// Problems are kept with the index of their source, so that only the
+	// problems of the state that's actually copied are reported.
+	let mut hotspot_problems = Vec::new();
+	let sources = args
+		.from
+		.iter()
+		.enumerate()
+		.map(|(source, path)| {
+			let mut problems = Vec::new();
+			let icon = load_source(path, &args, &mut report, &mut problems)
+				.wrap_err_with(|| format!("failed to read input file {}", path.display()))?;
+			hotspot_problems.extend(
+				problems
+					.into_iter()
+					.map(|(name, problem)| (source, name, problem)),
+			);
+			Ok(icon)
+		})
+		.collect::<Result<Vec<_>>>()?;
+	let mut to = load_dmi_with(&args.to, args.gzip)
+		.wrap_err_with(|| format!("failed to read output file {}", args.to.display()))?;
+
+	if args.strict_dimensions {
+		let files = args.from.iter().zip(&sources).chain([(&args.to, &to)]);
+		validate::check_dimensions(
+			files.map(|(path, icon)| (path.as_path(), icon)),
+			args.icon_size,
+		)?;
+	} else if let Some((width, height)) = args.icon_size {
+		let files = args.from.iter().zip(&sources).chain([(&args.to, &to)]);
+		for (path, icon) in files {
+			if (icon.width, icon.height) != (width, height) {
+				return Err(eyre!(
+					"{} has {}x{} icons, not {width}x{height}",
+					path.display(),
+					icon.width,
+					icon.height
+				));
+			}
+		}
+	} else {
+		for (path, from) in args.from.iter().zip(&sources) {
+			if (from.width, from.height) != (to.width, to.height) {
+				report.warn(format_args!(
+					"{} has {}x{} icons, but {} has {}x{} icons",
+					path.display(),
+					from.width,
+					from.height,
+					args.to.display(),
+					to.width,
+					to.height
+				));
+			}
+		}
+	}
+
+	let matcher =
+		StateMatcher::with_mode(&args.icon_states, args.match_mode, &args.normalize_names)?;
+	let (merged_states, shadowed) = select::merge_sources(&sources, args.source_priority);
+	// States are protected by the source they came from, so a newer source
+	// still replaces its own states. Renames have to carry the names along.
+	let mut protected = HashSet::new();
+	if args.no_clobber_newer {
+		let mut newer = Vec::with_capacity(args.from.len());
+		for source in &args.from {
+			newer.push(
+				is_newer_than(&args.to, source).wrap_err("failed to compare modification times")?,
+			);
+		}
+		for state in &merged_states {
+			let source = shadowed
+				.iter()
+				.find(|shadowed| shadowed.name == state.name)
+				.map(|shadowed| shadowed.winner)
+				.or_else(|| {
+					sources
+						.iter()
+						.position(|icon| icon.states.iter().any(|other| other.name == state.name))
+				});
+			if source.is_some_and(|source| newer[source]) {
+				protected.insert(state.name.clone());
+			}
+		}
+	}
+	hotspot_problems.retain(|(source, name, _)| {
+		!shadowed
+			.iter()
+			.any(|shadowed| shadowed.source == *source && shadowed.name == *name)
+	});
+	for shadowed in shadowed {
+		if matcher.matches(&shadowed.name) {
+			report.shadowed(
+				&shadowed.name,
+				&args.from[shadowed.source],
+				&args.from[shadowed.winner],
+			);
+		}
+	}
+	let from = Icon {
+		states: merged_states,
+		..sources
+			.into_iter()
+			.next()
+			.expect("at least one source is required")
+	};
+
+	let mut missing = Vec::new();
+	for pattern in matcher.inclusions() {
+		let name = pattern.text();
+		if !from
+			.states
+			.iter()
+			.any(|state| matcher.pattern_matches(pattern, &state.name))
+		{
+			match args.on_missing_source {
+				MissingSourcePolicy::Error => missing.push(format!("'{name}'")),
+				MissingSourcePolicy::Warn => report.unmatched(name, false),
+				MissingSourcePolicy::Ignore => report.unmatched(name, true),
+			}
+		} else if matcher.is_excluded(name) {
+			report.skipped(name, "excluded");
+		}
+	}
+
+	if !missing.is_empty() {
+		return Err(eyre!(
+			"state(s) not found in source: {}",
+			missing.join(", ")
+		));
+	}
+
+	let mut selected_states = Vec::new();
+	for state in &from.states {
+		if !matcher.matches(&state.name) {
+			continue;
+		}
+		if args.all && state.name.is_empty() {
+			if !args.include_default_state {
+				report.skipped(
+					"",
+					"--all leaves the default state out without --include-default-state",
+				);
+				continue;
+			}
+			report.info(format_args!("Copying the source's default state"));
+		}
+		if args.structure_filter.matches(state) {
+			selected_states.push(state.clone());
+		} else if matcher.is_requested(&state.name) {
+			report.skipped(&state.name, "doesn't match the frame and dir filters");
+		}
+	}
+	// Checked before any state is renamed, since problems are found by name.
+	for state in &mut selected_states {
+		for (_, _, problem) in hotspot_problems
+			.iter()
+			.filter(|(_, name, _)| *name == state.name)
+		{
+			if args.strip_invalid_hotspots && state.hotspot.is_some() {
+				state.hotspot = None;
+				report.info(format_args!(
+					"State '{}': removed its hotspot, since it {problem}",
+					state.name
+				));
+			} else if !args.strip_invalid_hotspots {
+				report.warn(format_args!("state '{}' {problem}", state.name));
+			}
+		}
+	}
+	if let Some(new_name) = &args.default_as {
+		if selected_states.iter().any(|state| &state.name == new_name) {
+			return Err(eyre!(
+				"can't copy the default state as '{new_name}', a state with that name is already \
+				 being copied"
+			));
+		}
+		if let Some(state) = selected_states
+			.iter_mut()
+			.find(|state| state.name.is_empty())
+		{
+			report.info(format_args!("Default state renamed to '{new_name}'"));
+			rename_protected(&mut protected, &[(String::new(), new_name.clone())]);
+			state.name.clone_from(new_name);
+		}
+	}
+	for (icon, side) in [(&from, "source"), (&to, "target")] {
+		for (name, count) in duplicate_names(icon, &selected_states) {
+			report.warn(format_args!(
+				"state '{name}' appears {count} times in the {side}"
+			));
+		}
+	}
+
+	if let Some(filter) = args.animation_filter {
+		selected_states.retain(|state| {
+			let matches = filter.matches(state);
+			if !matches {
+				report.skipped(&state.name, filter.skip_reason());
+			}
+			matches
+		});
+	}
+
+	// Checked against the source's frame counts, before --max-frames-keep cuts
+	// them down.
+	if let Some(max_frames) = args.max_frames {
+		if let Some(state) = selected_states
+			.iter()
+			.find(|state| state.frames > max_frames)
+		{
+			return Err(eyre!(
+				"state '{}' has {} frames, more than the limit of {max_frames}",
+				state.name,
+				state.frames
+			));
+		}
+	}
+
+	if let Some(max) = args.max_frames_keep {
+		for state in &mut selected_states {
+			let old_frames = state.frames;
+			if transform::limit_frames(state, max, args.sample_frames) {
+				report.info(format_args!(
+					"State '{}': kept {} of {old_frames} frames",
+					state.name, state.frames
+				));
+			}
+		}
+	}
+
+	if let Some(max_copies) = args.max_copies {
+		if selected_states.len() > max_copies {
+			const SHOWN: usize = 10;
+			let mut names = selected_states
+				.iter()
+				.take(SHOWN)
+				.map(|state| format!("'{}'", state.name))
+				.collect::<Vec<_>>()
+				.join(", ");
+			if selected_states.len() > SHOWN {
+				names += &format!(", and {} more", selected_states.len() - SHOWN);
+			}
+			return Err(eyre!(
+				"{} states were selected, more than the limit of {max_copies}: {names}",
+				selected_states.len()
+			));
+		}
+	}
+
+	if let Some(cmd) = &args.transform_cmd {
+		selected_states =
+			try_each_state(selected_states, args.skip_errors, &mut report, |state| {
+				transform::external_command(state, cmd)
+					.wrap_err_with(|| format!("failed to transform state '{}'", state.name))
+			})?;
+	}
+
+	if let Some(key) = args.mask_color {
+		for state in &mut selected_states {
+			let masked = transform::mask_color(state, key, args.mask_tolerance);
+			if masked > 0 {
+				report.info(format_args!(
+					"State '{}': made {masked} pixel(s) of the mask color transparent",
+					state.name
+				));
+			}
+		}
+	}
+
+	if let Some((dx, dy)) = args.offset {
+		for state in &mut selected_states {
+			transform::offset_frames(state, dx, dy, args.offset_mode);
+		}
+	}
+
+	if let Some(threshold) = args.alpha_threshold {
+		for state in &mut selected_states {
+			let cleared = transform::clear_faint_pixels(state, threshold);
+			if cleared > 0 {
+				report.info(format_args!(
+					"State '{}': cleared {cleared} pixel(s) with alpha below {threshold}",
+					state.name
+				));
+			}
+		}
+	}
+
+	if let Some(tick_ms) = args.normalize_delays {
+		// Delays are measured in ticks, which are a tenth of a second.
+		let base = tick_ms / 100.0;
+		for state in &mut selected_states {
+			let old_delays = state.delay.clone();
+			if transform::normalize_delays(state, base) {
+				report.info(format_args!(
+					"State '{}': delays normalized from {:?} to {:?}",
+					state.name,
+					old_delays.unwrap_or_default(),
+					state.delay.as_deref().unwrap_or_default()
+				));
+			}
+		}
+	}
+
+	if let Some(delay) = args.frame_delay {
+		for state in &mut selected_states {
+			if transform::set_delay(state, delay) {
+				report.info(format_args!(
+					"State '{}': every frame's delay set to {delay}",
+					state.name
+				));
+			}
+		}
+	}
+
+	if args.dedupe_frames {
+		for state in &mut selected_states {
+			let removed = transform::dedupe_frames(state);
+			if removed > 0 {
+				report.info(format_args!(
+					"State '{}': merged {removed} duplicate frame(s)",
+					state.name
+				));
+			}
+		}
+	}
+
+	if let Some(dirs) = args.set_dirs {
+		selected_states =
+			try_each_state(selected_states, args.skip_errors, &mut report, |state| {
+				transform::set_dirs(state, dirs, args.keep_dir)
+					.wrap_err_with(|| format!("failed to reshape state '{}'", state.name))
+			})?;
+	}
+
+	if let Some(path) = &args.rename_map {
+		let map = RenameMap::load(path)?;
+		for old_name in map.old_names() {
+			if !from.states.iter().any(|state| state.name == old_name) {
+				report.warn(format_args!(
+					"rename map entry '{old_name}' doesn't match any state in the source"
+				));
+			}
+		}
+		let renamed = rename::rename_states(&mut selected_states, |name| map.apply(name))
+			.wrap_err("can't apply the rename map")?;
+		rename_protected(&mut protected, &renamed);
+		for (old_name, new_name) in renamed {
+			report.info(format_args!("State '{old_name}' renamed to '{new_name}'"));
+		}
+	}
+	if let Some(rename) = &args.rename_with_regex {
+		let renamed = rename::rename_states(&mut selected_states, |name| rename.apply(name))?;
+		rename_protected(&mut protected, &renamed);
+		for (old_name, new_name) in renamed {
+			report.info(format_args!("State '{old_name}' renamed to '{new_name}'"));
+		}
+	}
+
+	// States whose names normalize the same way would both replace the same
+	// target state, and only the last one would be kept.
+	let mut normalized = HashMap::<String, &str>::new();
+	for state in &selected_states {
+		let key = normalize_name(&state.name, &args.normalize_names);
+		match normalized.insert(key, &state.name) {
+			Some(other) if other != state.name => {
+				return Err(eyre!(
+					"states '{other}' and '{}' have the same name once normalized, so they would \
+					 both be copied to the same state",
+					state.name
+				));
+			}
+			_ => {}
+		}
+	}
+
+	// Renamed states mustn't take the name of a state that's copied later.
+	let source_names = selected_states
 		.iter()
-		.filter(|state| args.icon_states.contains(&state.name))
-		.cloned()
-		.filter_map(|new_state| {
-			let name = new_state.name.as_str();
-			match to
-				.states
-				.iter_mut()
-				.find(|existing_state| existing_state.name == name)
+		.map(|state| state.name.clone())
+		.collect::<Vec<_>>();
+	let mut states_to_insert = Vec::new();
+	for mut new_state in selected_states {
+		let protect_target = protected.contains(&new_state.name);
+		let key = normalize_name(&new_state.name, &args.normalize_names);
+		let existing_state = to.states.iter_mut().find(|existing_state| {
+			normalize_name(&existing_state.name, &args.normalize_names) == key
+		});
+		// States matched through name normalization keep the target's name.
+		if let Some(existing_state) = &existing_state {
+			if existing_state.name != new_state.name {
+				report.info(format_args!(
+					"State '{}' matched '{}' in the target",
+					new_state.name, existing_state.name
+				));
+				new_state.name.clone_from(&existing_state.name);
+			}
+		}
+		let name = new_state.name.clone();
+		match existing_state {
+			Some(existing_state) => {
+				let result = merge_state(
+					existing_state,
+					new_state,
+					&MergeOptions {
+						protect_target,
+						..MergeOptions::from(&args)
+					},
+					&mut report,
+				);
+				match result {
+					Ok(Some(mut renamed)) => {
+						let taken = |candidate: &str| {
+							let key = normalize_name(candidate, &args.normalize_names);
+							to.states
+								.iter()
+								.chain(&states_to_insert)
+								.map(|state| &state.name)
+								.chain(&source_names)
+								.any(|name| normalize_name(name, &args.normalize_names) == key)
+						};
+						renamed.name = conflict::unique_name(&name, taken);
+						report.info(format_args!(
+							"State '{name}' renamed to '{}', the target already has a different \
+							 '{name}'",
+							renamed.name
+						));
+						states_to_insert.push(renamed);
+					}
+					Ok(None) => {}
+					Err(err) => {
+						if !args.skip_errors {
+							return Err(err);
+						}
+						report.failed(&name, format_args!("{err:#}"));
+					}
+				}
+			}
+			None => {
+				if args.skip_if_content_exists {
+					let existing = to
+						.states
+						.iter()
+						.chain(&states_to_insert)
+						.find(|state| compare::same_content(state, &new_state));
+					if let Some(existing) = existing {
+						report.info(format_args!(
+							"State '{name}' skipped, its content is already in the target as '{}'",
+							existing.name
+						));
+						report.skipped(
+							&name,
+							&format!("content already in target as '{}'", existing.name),
+						);
+						continue;
+					}
+				}
+				states_to_insert.push(new_state);
+			}
+		}
+	}
+
+	for new_state in &states_to_insert {
+		report.added(&new_state.name);
+	}
+	let index = args
+		.at_index
+		.map_or(to.states.len(), |index| index.min(to.states.len()));
+	to.states.splice(index..index, states_to_insert);
+
+	let save_options = SaveOptions {
+		temp_dir: args.temp_dir.as_deref(),
+		gzip: args.gzip,
+		canonical: args.canonical,
+		retries: args.retries,
+		follow_symlinks: args.follow_symlinks,
+		..Default::default()
+	};
+	let changed = !report.added.is_empty() || !report.replaced.is_empty();
+	if let (Some(states_file), false) = (&args.emit_states_file, args.dry_run) {
+		let inventory = commands::check_inventory::format_inventory(
+			to.states.iter().map(|state| state.name.as_str()),
+		);
+		std::fs::write(states_file, inventory)
+			.wrap_err_with(|| format!("failed to write states to {}", states_file.display()))?;
+	}
+	// The patch is built now, but only written once the target is, so a
+	// failed save doesn't leave a patch for changes that never happened.
+	let patch = (args.emit_patch.is_some() && changed).then(|| Icon {
+		width: to.width,
+		height: to.height,
+		states: to
+			.states
+			.iter()
+			.filter(|state| {
+				report.added.contains(&state.name) || report.replaced.contains(&state.name)
+			})
+			.cloned()
+			.collect(),
+		..Default::default()
+	});
+
+	// Re-encoding can change the file's bytes even when no state changed, so
+	// an unchanged target isn't written at all.
+	if !changed {
+		report.info(format_args!(
+			"Nothing changed, {} was left untouched",
+			args.to.display()
+		));
+	} else if !args.dry_run {
+		prepare_save(to, &args.to, &save_options)
+			.and_then(|save| save.commit_with(|message| report.warn(message)))
+			.wrap_err_with(|| format!("failed to save dmi to {}", args.to.display()))?;
+	}
+
+	if let (Some(patch_path), false) = (&args.emit_patch, args.dry_run) {
+		if let Some(patch) = patch {
+			let patch_options = SaveOptions {
+				gzip: false,
+				..save_options
+			};
+			prepare_save(patch, patch_path, &patch_options)
+				.and_then(|save| save.commit_with(|message| report.warn(message)))
+				.wrap_err_with(|| format!("failed to save patch to {}", patch_path.display()))?;
+		} else {
+			// A DMI can't be saved without any states, so there's no empty
+			// patch to write.
+			report.info(format_args!(
+				"Nothing changed, so no patch was written to {}",
+				patch_path.display()
+			));
+		}
+	}
+
+	if let Some(report_file) = &args.report_file {
+		report::write_report_file(report_file, std::slice::from_ref(&report))
+			.wrap_err_with(|| format!("failed to write report to {}", report_file.display()))?;
+	}
+	if let (Some(manifest), false) = (&args.write_checksums, args.dry_run) {
+		checksum::write_manifest(manifest, std::slice::from_ref(&args.to))
+			.wrap_err_with(|| format!("failed to write checksums to {}", manifest.display()))?;
+	}
+	if let (Some(append_log), false) = (&args.append_log, args.dry_run) {
+		report::append_log(append_log, &report)
+			.wrap_err_with(|| format!("failed to append to log {}", append_log.display()))?;
+	}
+
+	report.print_summary();
+	match args.list_unmatched {
+		Some(OutputFormat::Text) if report.unmatched.is_empty() => {
+			println!("Unmatched states: none");
+		}
+		Some(OutputFormat::Text) => {
+			println!("Unmatched states:");
+			for name in &report.unmatched {
+				println!("  '{name}'");
+			}
+		}
+		Some(OutputFormat::Json) => println!(
+			"{}",
+			serde_json::to_string(&report.unmatched).wrap_err("failed to serialize states")?
+		),
+		None => {}
+	}
+	if args.dry_run {
+		report.info("dry run, nothing was written");
+	} else {
+		report.info("done!");
+	}
+
+	if !report.failed.is_empty() {
+		return Err(eyre!("{} state(s) failed to copy", report.failed.len()));
+	}
+	if args.warnings_as_errors && !report.warnings.is_empty() {
+		return Err(eyre!(
+			"{} warning(s) treated as errors",
+			report.warnings.len()
+		));
+	}
+
+	Ok(ExitCode::SUCCESS)
+}
+
+/// Options for how a copied state is merged into one the target already has
+#[derive(Debug, Default)]
+struct MergeOptions<'a> {
+	/// What to do with a state that already exists in the target
+	on_conflict: ConflictPolicy,
+	/// How to handle replacing a state with a different frame count
+	match_frames: Option<FrameMatching>,
+	/// The only direction to replace, if not all of them
+	dir: Option<Direction>,
+	/// Whose hotspot a replaced state ends up with
+	hotspot_strategy: HotspotStrategy,
+	/// Whether replacements keep the animation metadata they replace
+	keep_target_metadata: bool,
+	/// Whether to fail instead of replacing a state with one of a different
+	/// size
+	fail_on_dimension_change: bool,
+	/// Fields that don't count when deciding if states are identical
+	compare_ignoring: &'a [IgnorableField],
+	/// Whether to explain why identical states are identical
+	explain_identical: bool,
+	/// Whether to list the fields that changed in replaced states
+	explain_diff: bool,
+	/// Whether to print sizes in KiB and MiB, rather than bytes
+	human: bool,
+	/// Whether to leave existing states alone, since the target is newer than
+	/// the sources
+	protect_target: bool,
+}
+
+impl<'a> From<&'a DmiCopyArgs> for MergeOptions<'a> {
+	fn from(args: &'a DmiCopyArgs) -> Self {
+		Self {
+			on_conflict: args.on_conflict,
+			match_frames: args.match_frames,
+			dir: args.dir,
+			hotspot_strategy: args.hotspot_strategy,
+			keep_target_metadata: args.keep_target_metadata,
+			fail_on_dimension_change: args.fail_on_dimension_change,
+			compare_ignoring: &args.compare_ignoring,
+			explain_identical: args.explain_identical,
+			explain_diff: args.explain_diff,
+			human: args.human,
+			protect_target: false,
+		}
+	}
+}
+
+/// Add a state to an icon, or merge it into the one with the same name. A
+/// state the conflict policy renames is added under its new name.
+fn merge_into(
+	icon: &mut Icon,
+	state: IconState,
+	options: &MergeOptions,
+	report: &mut CopyReport,
+) -> Result<()> {
+	let name = state.name.clone();
+	let Some(existing) = icon
+		.states
+		.iter_mut()
+		.find(|existing| existing.name == name)
+	else {
+		report.added(&name);
+		icon.states.push(state);
+		return Ok(());
+	};
+	if let Some(mut renamed) = merge_state(existing, state, options, report)? {
+		let taken = |candidate: &str| icon.states.iter().any(|state| state.name == candidate);
+		renamed.name = conflict::unique_name(&name, taken);
+		report.info(format_args!(
+			"State '{name}' renamed to '{}', the target already has a different '{name}'",
+			renamed.name
+		));
+		report.added(&renamed.name);
+		icon.states.push(renamed);
+	}
+	Ok(())
+}
+
+/// Merge a copied state into the one it replaces in the target, following
+/// the conflict options. Returns the copied state if it should be added under
+/// a new name instead.
+fn merge_state(
+	existing_state: &mut IconState,
+	mut new_state: IconState,
+	args: &MergeOptions,
+	report: &mut CopyReport,
+) -> Result<Option<IconState>> {
+	let name = new_state.name.clone();
+	if !args.on_conflict.compares() {
+		report.skipped(&name, "already in target");
+		return Ok(None);
+	}
+	// Checked before the frames are matched up, which can fail, for a state
+	// that won't be replaced anyway.
+	if args.protect_target
+		&& matches!(
+			args.on_conflict.action(false),
+			ConflictAction::Replace | ConflictAction::KeepSmaller
+		) && !compare::equal_ignoring(existing_state, &new_state, args.compare_ignoring)
+	{
+		report.warn(format_args!(
+			"not replacing state '{name}', the target is newer than the source"
+		));
+		report.skipped(&name, "target is newer than the source");
+		return Ok(None);
+	}
+	if new_state.frames != existing_state.frames {
+		match args.match_frames {
+			Some(FrameMatching::Error) => {
+				return Err(eyre!(
+					"state '{name}' has {} frame(s) in the source, but {} in the target",
+					new_state.frames,
+					existing_state.frames
+				));
+			}
+			Some(FrameMatching::Pad) => {
+				transform::pad_frames(&mut new_state, existing_state.frames)
+					.wrap_err_with(|| format!("failed to pad frames of state '{name}'"))?;
+			}
+			Some(FrameMatching::Replace) => {}
+			// Frames are replaced by position, so a different count usually
+			// means they don't line up, and the animation silently changes.
+			None if matches!(
+				args.on_conflict.action(false),
+				ConflictAction::Replace | ConflictAction::KeepSmaller
+			) =>
 			{
-				Some(existing_state) => {
-					if *existing_state == new_state {
-						println!("State '{name}' identical in both files");
-					} else {
-						println!("State '{name}' replaced");
-						*existing_state = new_state;
+				return Err(eyre!(
+					"state '{name}' has {} frame(s) in the source, but {} in the target, so its \
+					 frames may not line up; use --match-frames=pad or --match-frames=replace to \
+					 replace it anyway",
+					new_state.frames,
+					existing_state.frames
+				));
+			}
+			None => {}
+		}
+	}
+	if let Some(dir) = args.dir {
+		new_state = transform::splice_direction(existing_state, &new_state, dir)
+			.wrap_err_with(|| format!("failed to copy {dir} dir of state '{name}'"))?;
+	}
+	args.hotspot_strategy.apply(&mut new_state, existing_state);
+	if args.keep_target_metadata {
+		transform::keep_animation_metadata(&mut new_state, existing_state)
+			.wrap_err_with(|| format!("failed to keep the metadata of state '{name}'"))?;
+	}
+	let identical = compare::equal_ignoring(existing_state, &new_state, args.compare_ignoring);
+	let mut action = args.on_conflict.action(identical);
+	// Size only decides which side wins, and a smaller source is replaced
+	// like any other.
+	if action == ConflictAction::KeepSmaller {
+		let source_size = compare::encoded_size(&new_state)?;
+		let target_size = compare::encoded_size(existing_state)?;
+		let size = |bytes: usize| {
+			if args.human {
+				commands::normalize::human_size(bytes as u64)
+			} else {
+				format!("{bytes} bytes")
+			}
+		};
+		// Ties keep the target, since there's nothing to gain by changing it.
+		if source_size >= target_size {
+			report.info(format_args!(
+				"State '{name}' kept, the target is no larger ({}, source {})",
+				size(target_size),
+				size(source_size)
+			));
+			report.skipped(&name, "target is no larger than the source");
+			return Ok(None);
+		}
+		report.info(format_args!(
+			"State '{name}': the source is smaller ({}, target {})",
+			size(source_size),
+			size(target_size)
+		));
+		action = ConflictAction::Replace;
+	}
+	match action {
+		ConflictAction::Skip => report.skipped(&name, "already in target"),
+		ConflictAction::Identical => {
+			report.identical(&name);
+			if args.explain_identical {
+				for comparison in compare::compare_fields(existing_state, &new_state) {
+					if !comparison.equal {
+						report.info(format_args!("  {comparison} (ignored)"));
+						continue;
+					}
+					match comparison.method {
+						Some(method) => report.info(format_args!(
+							"  {}: {}, {method} (matches)",
+							comparison.field, comparison.old
+						)),
+						None => report.info(format_args!(
+							"  {}: {} (matches)",
+							comparison.field, comparison.old
+						)),
 					}
-					None
 				}
-				None => Some(new_state),
 			}
-		})
-		.collect::<Vec<_>>();
+		}
+		ConflictAction::Replace if args.protect_target => {
+			report.warn(format_args!(
+				"not replacing state '{name}', the target is newer than the source"
+			));
+			report.skipped(&name, "target is newer than the source");
+		}
+		ConflictAction::Replace => {
+			if args.fail_on_dimension_change {
+				validate::check_same_size(existing_state, &new_state)?;
+			}
+			report.replaced(&name);
+			if args.explain_diff {
+				let changes = compare::compare_fields(existing_state, &new_state)
+					.into_iter()
+					.filter(|comparison| !comparison.equal)
+					.map(|comparison| comparison.to_string())
+					.collect::<Vec<_>>();
+				if !changes.is_empty() {
+					report.info(format_args!("  {}", changes.join(", ")));
+				}
+			}
+			*existing_state = new_state;
+		}
+		ConflictAction::Fail => {
+			return Err(eyre!(
+				"state '{name}' already exists in the target with different content"
+			));
+		}
+		ConflictAction::Rename => return Ok(Some(new_state)),
+		ConflictAction::KeepSmaller => unreachable!("the smaller state was already picked"),
+	}
+	Ok(None)
+}
 
-	to.states.reserve(states_to_insert.len());
-	for new_state in states_to_insert {
-		println!("State '{}' added", new_state.name);
-		to.states.push(new_state);
+/// Apply a fallible operation to each state. With `skip_errors`, states it
+/// fails on are recorded in the report and dropped, rather than failing the
+/// whole copy.
+fn try_each_state(
+	states: Vec<IconState>,
+	skip_errors: bool,
+	report: &mut CopyReport,
+	mut operation: impl FnMut(&mut IconState) -> Result<()>,
+) -> Result<Vec<IconState>> {
+	let mut kept = Vec::with_capacity(states.len());
+	for mut state in states {
+		match operation(&mut state) {
+			Ok(()) => kept.push(state),
+			Err(err) if skip_errors => report.failed(&state.name, format_args!("{err:#}")),
+			Err(err) => return Err(err),
+		}
 	}
+	Ok(kept)
+}
 
-	save_dmi(to, &args.to)
-		.wrap_err_with(|| format!("failed to save dmi to {}", args.to.display()))?;
+/// Find the names of the given states that appear more than once in an icon,
+/// along with how many times they appear
+fn duplicate_names<'a>(icon: &Icon, states: &'a [IconState]) -> Vec<(&'a str, usize)> {
+	let mut duplicates = Vec::new();
+	for state in states {
+		let name = state.name.as_str();
+		let count = icon
+			.states
+			.iter()
+			.filter(|state| state.name == name)
+			.count();
+		if count > 1 && !duplicates.iter().any(|(duplicate, _)| *duplicate == name) {
+			duplicates.push((name, count));
+		}
+	}
+	duplicates
+}
 
-	println!("done!");
+/// Options for how a DMI file is written
+#[derive(Debug, Default)]
+struct SaveOptions<'a> {
+	/// The directory to write the temporary output file to
+	temp_dir: Option<&'a Path>,
+	/// Whether to gzip the file, even if its extension doesn't call for it
+	gzip: bool,
+	/// Whether to write the file in a canonical, reproducible form
+	canonical: bool,
+	/// How many times to retry moving the file into place after a transient
+	/// failure
+	retries: u32,
+	/// Whether to write to the file a symlinked target points to, rather than
+	/// replacing the symlink
+	follow_symlinks: bool,
+	/// How hard to compress the image data
+	compression: PngCompression,
+	/// A PNG file whose ancillary chunks should be carried over into the
+	/// saved file
+	chunks_from: Option<&'a [u8]>,
+}
 
+/// Check that an existing file can be written to
+fn check_writable(path: &Path) -> Result<()> {
+	if let Some(parent) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+		if !parent.is_dir() {
+			return Err(eyre!("directory {} does not exist", parent.display()));
+		}
+	}
+	let metadata = std::fs::metadata(path).wrap_err("failed to read file metadata")?;
+	if metadata.permissions().readonly() {
+		return Err(eyre!("file is read-only"));
+	}
+	// This also catches files that are locked by another process.
+	std::fs::OpenOptions::new()
+		.write(true)
+		.open(path)
+		.wrap_err("file can't be opened for writing")?;
 	Ok(())
 }
 
+/// Whether a file was modified more recently than a source
+fn is_newer_than(path: &Path, source: &Path) -> Result<bool> {
+	// There's no modification time to compare against for a download.
+	if remote::is_url(source) {
+		return Ok(false);
+	}
+	let modified = |path: &Path| {
+		std::fs::metadata(path)
+			.and_then(|metadata| metadata.modified())
+			.wrap_err_with(|| format!("failed to read modification time of {}", path.display()))
+	};
+	Ok(modified(path)? > modified(source)?)
+}
+
+/// Move the protection of renamed states over to their new names
+fn rename_protected(protected: &mut HashSet<String>, renamed: &[(String, String)]) {
+	let moved = renamed
+		.iter()
+		.filter(|(old_name, _)| protected.contains(old_name))
+		.map(|(_, new_name)| new_name.clone())
+		.collect::<Vec<_>>();
+	for (old_name, _) in renamed {
+		protected.remove(old_name);
+	}
+	protected.extend(moved);
+}
+
+/// Whether a path should be treated as a gzip-compressed DMI
+fn is_gzip_path(path: &Path) -> bool {
+	path.extension().is_some_and(|extension| extension == "gz")
+}
+
 fn load_dmi(path: &Path) -> Result<Icon> {
-	let file = File::open(path)
-		.map(BufReader::new)
-		.wrap_err("failed to open file for reading")?;
-	Icon::load(file).wrap_err("failed to load dmi")
+	load_dmi_with(path, false)
+}
+
+/// Load a DMI file, decompressing it like [`read_dmi_bytes`] does
+fn load_dmi_with(path: &Path, gzip: bool) -> Result<Icon> {
+	parse_dmi(&read_dmi_bytes(path, gzip)?)
+}
+
+/// Load a source DMI file, downloading it if it's a URL, and through the
+/// decode cache if one is configured. With `--lenient`, a source that fails to
+/// load is recovered as far as possible instead. The name and problem of each
+/// state whose hotspots can't be copied intact are added to
+/// `hotspot_problems`.
+fn load_source(
+	path: &Path,
+	args: &DmiCopyArgs,
+	report: &mut CopyReport,
+	hotspot_problems: &mut Vec<(String, String)>,
+) -> Result<Icon> {
+	let bytes = if remote::is_url(path) {
+		if !args.allow_remote {
+			return Err(eyre!("sources can only be URLs with --allow-remote"));
+		}
+		let bytes = remote::fetch(&path.to_string_lossy(), args.timeout)
+			.wrap_err("failed to download source")?;
+		if args.gzip || is_gzip_path(path) {
+			let mut decompressed = Vec::new();
+			GzDecoder::new(bytes.as_slice())
+				.read_to_end(&mut decompressed)
+				.wrap_err("failed to decompress gzipped dmi")?;
+			decompressed
+		} else {
+			bytes
+		}
+	} else {
+		read_dmi_bytes(path, args.gzip)?
+	};
+	let icon = match &args.cache_dir {
+		Some(cache_dir) => cache::load(cache_dir, &bytes, || parse_dmi(&bytes)),
+		None => parse_dmi(&bytes),
+	};
+	// The dmi crate drops all but one hotspot per state, so they're checked in
+	// the metadata itself.
+	if let (Ok(icon), Ok(description)) = (&icon, png::dmi_description(&bytes)) {
+		let hotspots = hotspot::read(&description);
+		if hotspots.len() == icon.states.len() {
+			for (state, hotspots) in icon.states.iter().zip(&hotspots) {
+				for problem in hotspot::problems(state, hotspots, icon.width, icon.height) {
+					hotspot_problems.push((state.name.clone(), problem));
+				}
+			}
+		}
+	}
+	match icon {
+		Err(err) if args.lenient => {
+			let (icon, problems) = lenient::load(&bytes)
+				.wrap_err_with(|| format!("{err:#}, and the file couldn't be recovered"))?;
+			report.warn(format_args!(
+				"{} is damaged ({err:#}), recovered {} state(s)",
+				path.display(),
+				icon.states.len()
+			));
+			for problem in problems {
+				report.warn(format_args!("{}: {problem}", path.display()));
+			}
+			Ok(icon)
+		}
+		icon => icon,
+	}
 }
 
-fn save_dmi(dmi: Icon, path: &Path) -> Result<()> {
+/// Parse the contents of a DMI file
+fn parse_dmi(bytes: &[u8]) -> Result<Icon> {
+	Icon::load(bytes).map_err(|err| {
+		// The dmi crate's errors for common mistakes are cryptic, so explain
+		// them instead.
+		if !png::is_png(bytes) {
+			eyre!("file is not a PNG, and so can't be a DMI")
+		} else if !png::has_dmi_description(bytes) {
+			eyre!("file is a PNG but not a DMI — missing zTXt description")
+		} else {
+			Report::new(err).wrap_err("failed to load dmi")
+		}
+	})
+}
+
+/// Read the bytes of a DMI file, decompressing it if `gzip` is set or its
+/// extension is `.gz`
+fn read_dmi_bytes(path: &Path, gzip: bool) -> Result<Vec<u8>> {
+	let mut file = File::open(path).wrap_err("failed to open file for reading")?;
+	let mut bytes = Vec::new();
+	if gzip || is_gzip_path(path) {
+		GzDecoder::new(BufReader::new(file))
+			.read_to_end(&mut bytes)
+			.wrap_err("failed to decompress gzipped dmi")?;
+	} else {
+		file.read_to_end(&mut bytes)
+			.wrap_err("failed to read file")?;
+	}
+	Ok(bytes)
+}
+
+/// Write a DMI, in canonical form if requested
+fn write_dmi<W: Write>(dmi: &Icon, writer: &mut W, options: &SaveOptions) -> Result<()> {
+	if options.compression != PngCompression::Default || options.chunks_from.is_some() {
+		let mut bytes = Vec::new();
+		write_dmi(dmi, &mut bytes, &SaveOptions {
+			compression: PngCompression::Default,
+			chunks_from: None,
+			..*options
+		})?;
+		if options.compression != PngCompression::Default {
+			bytes = png::recompress(&bytes, options.compression)?;
+		}
+		if let Some(original) = options.chunks_from {
+			bytes = png::copy_ancillary_chunks(original, &bytes)?;
+		}
+		// Check the result still loads before it replaces anything.
+		Icon::load(&bytes[..]).wrap_err("re-encoded dmi doesn't load")?;
+		return writer.write_all(&bytes).wrap_err("failed to write dmi");
+	}
+	if options.canonical {
+		canonical::save(dmi, writer)
+	} else {
+		dmi.save(writer).wrap_err("failed to save dmi")?;
+		Ok(())
+	}
+}
+
+fn save_dmi(dmi: Icon, path: &Path, options: &SaveOptions) -> Result<()> {
+	prepare_save(dmi, path, options)?.commit()
+}
+
+/// A DMI that's been written to a temporary file, waiting to be moved over
+/// its target
+struct PendingSave {
+	temp_path: tempfile::TempPath,
+	path: PathBuf,
+	retries: u32,
+}
+
+/// Write a DMI to a temporary file next to its target, without touching the
+/// target itself. Several files can be prepared before committing any, so
+/// that a failure to write one doesn't leave the others half-changed.
+fn prepare_save(mut dmi: Icon, path: &Path, options: &SaveOptions) -> Result<PendingSave> {
+	if options.canonical {
+		canonical::canonicalize(&mut dmi);
+	}
+	// The encoder's own errors don't say which state was at fault.
+	validate::check(&dmi).wrap_err("refusing to save an invalid dmi")?;
+
+	// Renaming over a symlink replaces the link itself, so to write through it
+	// the final file has to be targeted directly.
+	let resolved;
+	let path = if options.follow_symlinks {
+		resolved = resolve_symlinks(path)
+			.wrap_err_with(|| format!("failed to resolve symlink {}", path.display()))?;
+		resolved.as_path()
+	} else {
+		path
+	};
+
 	// For the sake of user safety, we do an "atomic write" by writing to a
-	// tempfile, and then copying said tempfile to the target path.
-	let mut file = tempfile::Builder::new()
-		.suffix(".dmi")
-		.tempfile()
+	// tempfile, and then renaming said tempfile over the target path. The
+	// tempfile goes next to the target by default, so that the rename can't
+	// cross filesystems, and is hidden so it doesn't look like a real DMI
+	// while it's being written.
+	let mut builder = tempfile::Builder::new();
+	let prefix = format!(
+		".{}.",
+		path.file_name().unwrap_or_default().to_string_lossy()
+	);
+	builder.prefix(&prefix).suffix(".tmp");
+	let file = match options.temp_dir {
+		Some(temp_dir) => builder.tempfile_in(temp_dir),
+		None => {
+			let target_dir = path
+				.parent()
+				.filter(|dir| !dir.as_os_str().is_empty())
+				.unwrap_or(Path::new("."));
+			// If we can't write next to the target, fall back to the system
+			// temp dir, which respects TMPDIR.
+			builder
+				.tempfile_in(target_dir)
+				.or_else(|_| builder.tempfile())
+		}
+	};
+	let mut file = file
 		.map(BufWriter::new)
 		.wrap_err("failed to create temporary output file")?;
-	dmi.save(&mut file).wrap_err("failed to save dmi")?;
+	if options.gzip || is_gzip_path(path) {
+		let mut encoder = GzEncoder::new(file, Compression::default());
+		write_dmi(&dmi, &mut encoder, options)?;
+		file = encoder.finish().wrap_err("failed to finish gzip stream")?;
+	} else {
+		write_dmi(&dmi, &mut file, options)?;
+	}
 	let file = file
 		.into_inner()
 		.wrap_err("failed to finish writing buffer to file")?;
-	std::fs::copy(file.path(), path).wrap_err("failed to copy temp file to target")?;
-	Ok(())
+	file.as_file()
+		.sync_all()
+		.wrap_err("failed to flush temporary output file")?;
+	// Keep the target's permissions, rather than the tempfile's restrictive
+	// ones.
+	if let Ok(metadata) = std::fs::metadata(path) {
+		file.as_file()
+			.set_permissions(metadata.permissions())
+			.wrap_err("failed to copy permissions to temporary output file")?;
+	}
+	Ok(PendingSave {
+		temp_path: file.into_temp_path(),
+		path: path.to_path_buf(),
+		retries: options.retries,
+	})
+}
+
+impl PendingSave {
+	/// Move the temporary file over the target, printing a warning for each
+	/// retry
+	fn commit(self) -> Result<()> {
+		self.commit_with(|message| eprintln!("warning: {message}"))
+	}
+
+	/// Move the temporary file over the target, passing a message for each
+	/// retry to `warn`
+	fn commit_with(self, mut warn: impl FnMut(String)) -> Result<()> {
+		let mut attempt = 0;
+		loop {
+			match replace_with(&self.temp_path, &self.path) {
+				Ok(true) => {
+					// The tempfile has already been moved, so don't delete it.
+					let _ = self.temp_path.keep();
+					return Ok(());
+				}
+				Ok(false) => return Ok(()),
+				Err(err) if attempt < self.retries && is_transient(&err) => {
+					attempt += 1;
+					let delay = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+					warn(format!(
+						"failed to write {}: {err}, retrying in {}ms (attempt {attempt} of {})",
+						self.path.display(),
+						delay.as_millis(),
+						self.retries
+					));
+					std::thread::sleep(delay);
+				}
+				Err(err) => return Err(err).wrap_err("failed to copy temp file to target"),
+			}
+		}
+	}
+}
+
+/// How long to wait before the first retry of a failed write, doubling with
+/// each further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(100);
+
+/// How many symlinks to follow before assuming there's a loop, matching
+/// Linux's limit
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// Follow a chain of symlinks to the path they end at, which doesn't have to
+/// exist yet
+fn resolve_symlinks(path: &Path) -> Result<PathBuf> {
+	let mut path = path.to_path_buf();
+	// One more check than the limit, to see whether the last link followed
+	// was the end of the chain.
+	for _ in 0..=MAX_SYMLINK_DEPTH {
+		if !path.is_symlink() {
+			return Ok(path);
+		}
+		let link = std::fs::read_link(&path).wrap_err("failed to read symlink")?;
+		path = match path.parent() {
+			Some(parent) => parent.join(link),
+			None => link,
+		};
+	}
+	Err(eyre!("too many levels of symlinks"))
+}
+
+/// Move a finished tempfile over the target, returning whether it was
+/// renamed rather than copied
+fn replace_with(temp_path: &Path, path: &Path) -> std::io::Result<bool> {
+	match std::fs::rename(temp_path, path) {
+		Ok(()) => Ok(true),
+		// Renaming fails across filesystems, so fall back to copying.
+		Err(_) => {
+			// Copying would write through a symlink, unlike renaming, so
+			// remove it first to replace it the same way.
+			if path.is_symlink() {
+				std::fs::remove_file(path)?;
+			}
+			std::fs::copy(temp_path, path).map(|_| false)
+		}
+	}
+}
+
+/// Whether an IO error is likely to go away if the operation is retried,
+/// as is common on network filesystems
+fn is_transient(err: &std::io::Error) -> bool {
+	use std::io::ErrorKind;
+	matches!(
+		err.kind(),
+		ErrorKind::Interrupted
+			| ErrorKind::WouldBlock
+			| ErrorKind::TimedOut
+			| ErrorKind::ResourceBusy
+			| ErrorKind::StaleNetworkFileHandle
+			| ErrorKind::ConnectionReset
+			| ErrorKind::ConnectionAborted
+			| ErrorKind::NotConnected
+			| ErrorKind::BrokenPipe
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::io::{Error, ErrorKind};
+
+	#[test]
+	fn test_is_transient() {
+		for kind in [
+			ErrorKind::Interrupted,
+			ErrorKind::TimedOut,
+			ErrorKind::ResourceBusy,
+			ErrorKind::StaleNetworkFileHandle,
+		] {
+			assert!(is_transient(&Error::from(kind)), "{kind:?}");
+		}
+		for kind in [
+			ErrorKind::NotFound,
+			ErrorKind::PermissionDenied,
+			ErrorKind::StorageFull,
+		] {
+			assert!(!is_transient(&Error::from(kind)), "{kind:?}");
+		}
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_resolve_symlinks_chain() {
+		use std::os::unix::fs::symlink;
+
+		let dir = tempfile::tempdir().unwrap();
+		let target = dir.path().join("target.dmi");
+		symlink("target.dmi", dir.path().join("first.dmi")).unwrap();
+		symlink(dir.path().join("first.dmi"), dir.path().join("second.dmi")).unwrap();
+		// The end of the chain doesn't have to exist yet.
+		assert_eq!(
+			resolve_symlinks(&dir.path().join("second.dmi")).unwrap(),
+			target
+		);
+		std::fs::write(&target, b"").unwrap();
+		assert_eq!(
+			resolve_symlinks(&dir.path().join("second.dmi")).unwrap(),
+			target
+		);
+		assert_eq!(resolve_symlinks(&target).unwrap(), target);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_resolve_symlinks_depth_limit() {
+		use std::os::unix::fs::symlink;
+
+		let dir = tempfile::tempdir().unwrap();
+		let link = |index: usize| dir.path().join(format!("{index}.dmi"));
+		for index in 0..MAX_SYMLINK_DEPTH {
+			symlink(link(index + 1), link(index)).unwrap();
+		}
+		// Exactly the limit is still followed, one more is not.
+		assert_eq!(resolve_symlinks(&link(0)).unwrap(), link(MAX_SYMLINK_DEPTH));
+		symlink(link(MAX_SYMLINK_DEPTH + 1), link(MAX_SYMLINK_DEPTH)).unwrap();
+		assert!(resolve_symlinks(&link(0)).is_err());
+
+		// A loop never ends, so it hits the limit too.
+		symlink("loop.dmi", dir.path().join("loop.dmi")).unwrap();
+		assert!(resolve_symlinks(&dir.path().join("loop.dmi")).is_err());
+	}
+
+	#[test]
+	fn test_is_newer_than() {
+		let dir = tempfile::tempdir().unwrap();
+		let now = std::time::SystemTime::now();
+		let touch = |name: &str, age: u64| {
+			let path = dir.path().join(name);
+			let file = std::fs::File::create(&path).unwrap();
+			file.set_modified(now - Duration::from_secs(age)).unwrap();
+			path
+		};
+		let old = touch("old.dmi", 300);
+		let target = touch("target.dmi", 200);
+		let new = touch("new.dmi", 100);
+
+		assert!(is_newer_than(&target, &old).unwrap());
+		assert!(!is_newer_than(&target, &new).unwrap());
+		// The same modification time isn't newer.
+		assert!(!is_newer_than(&target, &target).unwrap());
+		// A download has no modification time, so it's never older.
+		assert!(!is_newer_than(&target, Path::new("http://example.com/icon.dmi")).unwrap());
+		assert!(is_newer_than(&target, &dir.path().join("missing.dmi")).is_err());
+	}
 }
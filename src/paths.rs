@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::{remote, select::glob_to_regex};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use regex::Regex;
+use std::{
+	fs,
+	path::{Component, Path, PathBuf},
+};
+
+/// Whether a path has glob wildcards to expand
+pub fn is_pattern(path: &Path) -> bool {
+	!remote::is_url(path)
+		&& path
+			.to_str()
+			.is_some_and(|path| path.contains(['*', '?', '[']))
+}
+
+/// Expand each path that's a glob pattern into the files it matches, in
+/// order. A pattern that matches nothing is an error, unless a file with that
+/// literal name exists.
+pub fn expand_all(paths: impl IntoIterator<Item = PathBuf>) -> Result<Vec<PathBuf>> {
+	let mut expanded = Vec::new();
+	for path in paths {
+		if !is_pattern(&path) || path.exists() {
+			expanded.push(path);
+			continue;
+		}
+		let matches = expand(&path)?;
+		if matches.is_empty() {
+			return Err(eyre!("'{}' doesn't match any files", path.display()));
+		}
+		expanded.extend(matches);
+	}
+	Ok(expanded)
+}
+
+/// Expand a glob pattern into the files it matches, sorted by path.
+///
+/// `*`, `?`, and `[...]` match within a single path component, and a `**`
+/// component matches any number of directories, including none. Hidden files
+/// only match wildcards when the pattern component starts with a `.` too.
+fn expand(pattern: &Path) -> Result<Vec<PathBuf>> {
+	let mut bases = vec![PathBuf::new()];
+	for component in pattern.components() {
+		let part = match component {
+			Component::Normal(part) => part.to_string_lossy(),
+			other => {
+				for base in &mut bases {
+					base.push(other);
+				}
+				continue;
+			}
+		};
+		if part == "**" {
+			let mut dirs = Vec::new();
+			for base in &bases {
+				collect_dirs(base, &mut dirs);
+			}
+			bases = dirs;
+		} else if is_pattern(Path::new(part.as_ref())) {
+			let regex = Regex::new(&glob_to_regex(&part))
+				.wrap_err_with(|| format!("invalid pattern '{}'", pattern.display()))?;
+			bases = bases
+				.iter()
+				.flat_map(|base| matching_entries(base, &regex, part.starts_with('.')))
+				.collect();
+		} else {
+			for base in &mut bases {
+				base.push(part.as_ref());
+			}
+		}
+	}
+	bases.retain(|path| path.is_file());
+	bases.sort();
+	bases.dedup();
+	Ok(bases)
+}
+
+/// Read a directory, treating the empty path as the current directory, and
+/// skipping anything that can't be read
+fn entries(dir: &Path) -> Vec<(String, PathBuf)> {
+	let read = if dir.as_os_str().is_empty() {
+		fs::read_dir(".")
+	} else {
+		fs::read_dir(dir)
+	};
+	read.into_iter()
+		.flatten()
+		.flatten()
+		.filter_map(|entry| {
+			let name = entry.file_name().into_string().ok()?;
+			let path = dir.join(&name);
+			Some((name, path))
+		})
+		.collect()
+}
+
+/// The entries of a directory whose names match a pattern
+fn matching_entries(dir: &Path, regex: &Regex, hidden: bool) -> Vec<PathBuf> {
+	entries(dir)
+		.into_iter()
+		.filter(|(name, _)| (hidden || !name.starts_with('.')) && regex.is_match(name))
+		.map(|(_, path)| path)
+		.collect()
+}
+
+/// Add a directory and every visible directory under it. Symlinks aren't
+/// followed, since one pointing back up the tree would never end.
+fn collect_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) {
+	dirs.push(dir.to_path_buf());
+	for (name, path) in entries(dir) {
+		let is_dir = fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_dir());
+		if !name.starts_with('.') && is_dir {
+			collect_dirs(&path, dirs);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_expand() {
+		let dir = tempfile::tempdir().unwrap();
+		for file in [
+			"a.dmi",
+			"b.dmi",
+			"notes.txt",
+			"mobs/c.dmi",
+			"mobs/big/d.dmi",
+			".hidden/e.dmi",
+		] {
+			let path = dir.path().join(file);
+			fs::create_dir_all(path.parent().unwrap()).unwrap();
+			fs::write(path, "").unwrap();
+		}
+		let expand = |pattern: &str| {
+			expand(&dir.path().join(pattern))
+				.unwrap()
+				.into_iter()
+				.map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf())
+				.collect::<Vec<_>>()
+		};
+		let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+		assert_eq!(expand("*.dmi"), paths(&["a.dmi", "b.dmi"]));
+		assert_eq!(expand("[a]?dmi"), paths(&["a.dmi"]));
+		assert_eq!(expand("*/c.dmi"), paths(&["mobs/c.dmi"]));
+		assert_eq!(
+			expand("**/*.dmi"),
+			paths(&["a.dmi", "b.dmi", "mobs/big/d.dmi", "mobs/c.dmi"])
+		);
+		assert_eq!(expand(".*/*.dmi"), paths(&[".hidden/e.dmi"]));
+		assert!(expand("*.png").is_empty());
+
+		assert!(expand_all([dir.path().join("*.png")]).is_err());
+		assert_eq!(
+			expand_all([PathBuf::from("plain.dmi")]).unwrap(),
+			paths(&["plain.dmi"])
+		);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_expand_symlink_cycle() {
+		let dir = tempfile::tempdir().unwrap();
+		fs::create_dir(dir.path().join("mobs")).unwrap();
+		fs::write(dir.path().join("mobs/c.dmi"), "").unwrap();
+		std::os::unix::fs::symlink(dir.path(), dir.path().join("mobs/loop")).unwrap();
+		assert_eq!(expand(&dir.path().join("**/*.dmi")).unwrap(), [dir
+			.path()
+			.join("mobs/c.dmi")]);
+	}
+}
@@ -0,0 +1,424 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::args::PngCompression;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::RawDmi;
+use flate2::read::ZlibDecoder;
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use std::io::Read;
+
+/// The eight bytes every PNG file starts with
+const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// A chunk of a PNG file
+pub struct Chunk<'a> {
+	pub kind: [u8; 4],
+	pub data: &'a [u8],
+}
+
+/// Whether the data starts with the PNG signature
+pub fn is_png(bytes: &[u8]) -> bool {
+	bytes.starts_with(SIGNATURE)
+}
+
+/// Iterate over the chunks of a PNG file, stopping early at the first
+/// truncated chunk
+pub fn chunks(bytes: &[u8]) -> impl Iterator<Item = Chunk<'_>> {
+	let mut rest = bytes.strip_prefix(SIGNATURE).unwrap_or_default();
+	std::iter::from_fn(move || {
+		let length = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?) as usize;
+		let kind = rest.get(4..8)?.try_into().ok()?;
+		let data = rest.get(8..8 + length)?;
+		// Skip the CRC
+		rest = rest.get(12 + length..)?;
+		Some(Chunk { kind, data })
+	})
+}
+
+/// List the ways a PNG file's chunk structure breaks the PNG specification,
+/// which decoders are free to overlook: bad CRCs, truncated or misnamed
+/// chunks, unknown critical chunks, chunks out of order, and data after the
+/// end
+pub fn structure_problems(bytes: &[u8]) -> Vec<String> {
+	let Some(mut rest) = bytes.strip_prefix(SIGNATURE) else {
+		return vec!["doesn't start with the PNG signature".to_string()];
+	};
+	let mut problems = Vec::new();
+	let mut kinds = Vec::<[u8; 4]>::new();
+	while !rest.is_empty() {
+		let index = kinds.len();
+		let (Some(length), Some(kind)) = (rest.get(..4), rest.get(4..8)) else {
+			problems.push(format!("chunk {index} is truncated"));
+			break;
+		};
+		let length = u32::from_be_bytes(length.try_into().expect("4 bytes")) as usize;
+		let kind: [u8; 4] = kind.try_into().expect("4 bytes");
+		let name = String::from_utf8_lossy(&kind);
+		let Some(crc) = rest.get(8 + length..12 + length) else {
+			problems.push(format!(
+				"chunk {index} ({name}) is {length} bytes long, but the file ends first"
+			));
+			break;
+		};
+		let mut expected = flate2::Crc::new();
+		expected.update(&rest[4..8 + length]);
+		if expected.sum().to_be_bytes() != crc {
+			problems.push(format!("chunk {index} ({name}) has a bad CRC"));
+		}
+		if !kind.iter().all(u8::is_ascii_alphabetic) {
+			problems.push(format!("chunk {index} has an invalid type {kind:?}"));
+		} else if !is_ancillary(&kind) && !matches!(&kind, b"IHDR" | b"PLTE" | b"IDAT" | b"IEND") {
+			problems.push(format!(
+				"chunk {index} ({name}) is an unknown critical chunk"
+			));
+		}
+		kinds.push(kind);
+		rest = &rest[12 + length..];
+	}
+
+	if kinds.first() != Some(b"IHDR") {
+		problems.push("the first chunk isn't IHDR".to_string());
+	}
+	for unique in [b"IHDR", b"PLTE", b"IEND"] {
+		let count = kinds.iter().filter(|kind| *kind == unique).count();
+		if count > 1 {
+			let name = String::from_utf8_lossy(unique);
+			problems.push(format!("there are {count} {name} chunks"));
+		}
+	}
+	match kinds.iter().position(|kind| kind == b"IEND") {
+		None => problems.push("there's no IEND chunk".to_string()),
+		Some(end) if end + 1 != kinds.len() => {
+			problems.push(format!("{} chunk(s) follow IEND", kinds.len() - end - 1));
+		}
+		Some(_) => {}
+	}
+	let data = kinds
+		.iter()
+		.enumerate()
+		.filter(|(_, kind)| *kind == b"IDAT")
+		.map(|(index, _)| index)
+		.collect::<Vec<_>>();
+	if data.is_empty() {
+		problems.push("there's no IDAT chunk".to_string());
+	} else if data.last().unwrap() - data[0] + 1 != data.len() {
+		problems.push("the IDAT chunks aren't consecutive".to_string());
+	}
+	let descriptions = chunks(bytes)
+		.filter(|chunk| &chunk.kind == b"zTXt" && chunk.data.starts_with(DESCRIPTION_KEYWORD))
+		.count();
+	if descriptions > 1 {
+		problems.push(format!("there are {descriptions} DMI descriptions"));
+	}
+	problems
+}
+
+/// The keyword of the zTXt chunk that holds a DMI's metadata
+const DESCRIPTION_KEYWORD: &[u8] = b"Description\0";
+
+/// Find the compressed contents of the zTXt chunk that holds a DMI's
+/// metadata
+fn dmi_description_chunk(bytes: &[u8]) -> Option<&[u8]> {
+	chunks(bytes)
+		.filter(|chunk| &chunk.kind == b"zTXt")
+		.find_map(|chunk| chunk.data.strip_prefix(DESCRIPTION_KEYWORD))
+}
+
+/// Whether a PNG file has the zTXt chunk that holds a DMI's metadata
+pub fn has_dmi_description(bytes: &[u8]) -> bool {
+	dmi_description_chunk(bytes).is_some()
+}
+
+/// Decompress the raw metadata text of a DMI, without parsing it
+pub fn dmi_description(bytes: &[u8]) -> Result<String> {
+	let chunk = dmi_description_chunk(bytes).ok_or_else(|| eyre!("missing zTXt description"))?;
+	// The first byte is the compression method, and zlib is the only one
+	let (&method, compressed) = chunk
+		.split_first()
+		.ok_or_else(|| eyre!("zTXt description is empty"))?;
+	if method != 0 {
+		return Err(eyre!("unknown zTXt compression method {method}"));
+	}
+	let mut text = String::new();
+	ZlibDecoder::new(compressed)
+		.read_to_string(&mut text)
+		.wrap_err("failed to decompress zTXt description")?;
+	Ok(text)
+}
+
+/// Re-encode the image data of a DMI with a different compression level,
+/// keeping its metadata
+pub fn recompress(bytes: &[u8], compression: PngCompression) -> Result<Vec<u8>> {
+	let image = image::load_from_memory(bytes).wrap_err("failed to decode png")?;
+	let compression = match compression {
+		PngCompression::Fast => CompressionType::Fast,
+		PngCompression::Default => CompressionType::Default,
+		PngCompression::Best => CompressionType::Best,
+	};
+	let mut png = Vec::new();
+	image
+		.write_with_encoder(PngEncoder::new_with_quality(
+			&mut png,
+			compression,
+			FilterType::Adaptive,
+		))
+		.wrap_err("failed to encode png")?;
+	let original = RawDmi::load(bytes).wrap_err("failed to load dmi")?;
+	let mut raw = RawDmi::load(&png[..]).wrap_err("failed to reload encoded png")?;
+	raw.chunk_ztxt = original.chunk_ztxt;
+	let mut output = Vec::new();
+	raw.save(&mut output).wrap_err("failed to write dmi")?;
+	Ok(output)
+}
+
+/// Whether a chunk is ancillary, rather than critical to decoding the image
+fn is_ancillary(kind: &[u8; 4]) -> bool {
+	kind[0].is_ascii_lowercase()
+}
+
+/// Whether an ancillary chunk still means the same thing after the image is
+/// re-encoded as RGBA. Chunks like `tRNS`, `bKGD`, or `sBIT` are laid out
+/// differently for each color type, so they can't be carried over, and text
+/// chunks holding a DMI description would conflict with the new one.
+fn is_portable(kind: &[u8; 4], data: &[u8]) -> bool {
+	match kind {
+		b"tEXt" | b"iTXt" | b"zTXt" => !data.starts_with(b"Description\0"),
+		b"pHYs" | b"tIME" | b"gAMA" | b"cHRM" | b"sRGB" | b"iCCP" => true,
+		_ => false,
+	}
+}
+
+/// Copy the ancillary chunks of an original PNG that don't depend on its
+/// color type, like `tEXt`, `pHYs`, or `gAMA`, into a re-encoded DMI that
+/// doesn't already have chunks of that kind. The DMI description is always
+/// the re-encoded one's.
+pub fn copy_ancillary_chunks(original: &[u8], encoded: &[u8]) -> Result<Vec<u8>> {
+	let original = RawDmi::load(original).wrap_err("failed to load original dmi")?;
+	let mut raw = RawDmi::load(encoded).wrap_err("failed to reload encoded dmi")?;
+	let mut chunks = raw.other_chunks.take().unwrap_or_default();
+	let existing = chunks
+		.iter()
+		.map(|chunk| chunk.chunk_type)
+		.collect::<Vec<_>>();
+	chunks.extend(original.other_chunks.into_iter().flatten().filter(|chunk| {
+		is_portable(&chunk.chunk_type, &chunk.data) && !existing.contains(&chunk.chunk_type)
+	}));
+	raw.other_chunks = (!chunks.is_empty()).then_some(chunks);
+	let mut output = Vec::new();
+	raw.save(&mut output).wrap_err("failed to write dmi")?;
+	Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::{Icon, IconState};
+	use image::{DynamicImage, ImageFormat, RgbaImage};
+	use std::io::Cursor;
+
+	#[test]
+	fn test_has_dmi_description() {
+		let image = DynamicImage::ImageRgba8(RgbaImage::new(32, 32));
+		let mut png = Cursor::new(Vec::new());
+		image.write_to(&mut png, ImageFormat::Png).unwrap();
+		let png = png.into_inner();
+		assert!(is_png(&png));
+		assert!(!has_dmi_description(&png));
+
+		let icon = Icon {
+			width: 32,
+			height: 32,
+			states: vec![IconState {
+				name: "state".to_string(),
+				dirs: 1,
+				frames: 1,
+				images: vec![image],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let mut dmi = Vec::new();
+		icon.save(&mut dmi).unwrap();
+		assert!(has_dmi_description(&dmi));
+		let description = dmi_description(&dmi).unwrap();
+		assert!(description.starts_with("# BEGIN DMI\n"));
+		assert!(description.contains("state = \"state\"\n"));
+		assert!(dmi_description(&png).is_err());
+
+		assert!(!is_png(b"not a png"));
+	}
+
+	#[test]
+	fn test_copy_ancillary_chunks() {
+		let icon = Icon {
+			width: 32,
+			height: 32,
+			states: vec![IconState {
+				name: "state".to_string(),
+				dirs: 1,
+				frames: 1,
+				images: vec![DynamicImage::ImageRgba8(RgbaImage::new(32, 32))],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let mut dmi = Vec::new();
+		icon.save(&mut dmi).unwrap();
+
+		// Insert a tEXt chunk right after IHDR, which is 8 + 25 bytes in
+		let mut text = Vec::new();
+		let data = b"Software\0test";
+		text.extend_from_slice(&(data.len() as u32).to_be_bytes());
+		text.extend_from_slice(b"tEXt");
+		text.extend_from_slice(data);
+		let mut crc = flate2::Crc::new();
+		crc.update(&text[4..]);
+		text.extend_from_slice(&crc.sum().to_be_bytes());
+		let mut original = dmi.clone();
+		original.splice(33..33, text);
+
+		let has_text =
+			|bytes: &[u8]| chunks(bytes).any(|chunk| &chunk.kind == b"tEXt" && chunk.data == data);
+		assert!(has_text(&original));
+		assert!(!has_text(&dmi));
+		let copied = copy_ancillary_chunks(&original, &dmi).unwrap();
+		assert!(has_text(&copied));
+		assert_eq!(Icon::load(&copied[..]).unwrap(), icon);
+		// Chunks aren't duplicated if the encoded file already has them
+		let copied = copy_ancillary_chunks(&original, &copied).unwrap();
+		assert_eq!(
+			chunks(&copied)
+				.filter(|chunk| &chunk.kind == b"tEXt")
+				.count(),
+			1
+		);
+	}
+
+	/// Encode a PNG chunk, with its length and CRC
+	fn encode_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+		let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+		chunk.extend_from_slice(kind);
+		chunk.extend_from_slice(data);
+		let mut crc = flate2::Crc::new();
+		crc.update(&chunk[4..]);
+		chunk.extend_from_slice(&crc.sum().to_be_bytes());
+		chunk
+	}
+
+	#[test]
+	fn test_copy_ancillary_chunks_from_indexed() {
+		use flate2::{write::ZlibEncoder, Compression};
+		use std::io::Write;
+
+		let zlib = |data: &[u8]| {
+			let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+			encoder.write_all(data).unwrap();
+			encoder.finish().unwrap()
+		};
+		// A 32x32 DMI with a single transparent palette entry, like PNG
+		// optimizers write
+		let mut header = Vec::new();
+		header.extend_from_slice(&32u32.to_be_bytes());
+		header.extend_from_slice(&32u32.to_be_bytes());
+		header.extend_from_slice(&[8, 3, 0, 0, 0]);
+		let description = "# BEGIN DMI\nversion = 4.0\n\twidth = 32\n\theight = 32\nstate = \
+		                   \"state\"\n\tdirs = 1\n\tframes = 1\n# END DMI\n";
+		let mut ztxt = b"Description\0\0".to_vec();
+		ztxt.extend(zlib(description.as_bytes()));
+		let mut original = SIGNATURE.to_vec();
+		for (kind, data) in [
+			(b"IHDR", header),
+			(b"zTXt", ztxt),
+			(b"pHYs", vec![0, 0, 11, 19, 0, 0, 11, 19, 1]),
+			(b"PLTE", vec![0, 0, 0]),
+			(b"tRNS", vec![0]),
+			(b"bKGD", vec![0]),
+			(b"IDAT", zlib(&[0; 33 * 32])),
+			(b"IEND", Vec::new()),
+		] {
+			original.extend(encode_chunk(kind, &data));
+		}
+		let icon = Icon::load(&original[..]).unwrap();
+		let mut dmi = Vec::new();
+		icon.save(&mut dmi).unwrap();
+
+		let copied = copy_ancillary_chunks(&original, &dmi).unwrap();
+		let kinds = chunks(&copied).map(|chunk| chunk.kind).collect::<Vec<_>>();
+		assert!(kinds.contains(b"pHYs"));
+		for kind in [b"PLTE", b"tRNS", b"bKGD"] {
+			assert!(!kinds.contains(kind));
+		}
+		assert_eq!(Icon::load(&copied[..]).unwrap(), icon);
+	}
+
+	#[test]
+	fn test_structure_problems() {
+		let icon = Icon {
+			width: 32,
+			height: 32,
+			states: vec![IconState {
+				images: vec![DynamicImage::ImageRgba8(RgbaImage::new(32, 32))],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let mut dmi = Vec::new();
+		icon.save(&mut dmi).unwrap();
+		assert_eq!(structure_problems(&dmi), Vec::<String>::new());
+
+		// The last byte of IHDR's CRC, which is 8 + 25 bytes in
+		let mut bad_crc = dmi.clone();
+		bad_crc[32] ^= 1;
+		assert_eq!(structure_problems(&bad_crc), [
+			"chunk 0 (IHDR) has a bad CRC"
+		]);
+
+		let mut trailing = dmi.clone();
+		trailing.extend_from_slice(&dmi[8..33]);
+		assert_eq!(structure_problems(&trailing), [
+			"there are 2 IHDR chunks",
+			"1 chunk(s) follow IEND"
+		]);
+
+		let mut truncated = dmi.clone();
+		truncated.truncate(dmi.len() - 2);
+		assert_eq!(structure_problems(&truncated), [
+			"chunk 3 (IEND) is 0 bytes long, but the file ends first",
+			"there's no IEND chunk"
+		]);
+		assert_eq!(structure_problems(b"GIF89a").len(), 1);
+	}
+
+	#[test]
+	fn test_recompress() {
+		let icon = Icon {
+			width: 32,
+			height: 32,
+			states: vec![IconState {
+				name: "state".to_string(),
+				dirs: 1,
+				frames: 2,
+				images: vec![
+					DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+						32,
+						32,
+						image::Rgba([1, 2, 3, 4]),
+					)),
+					DynamicImage::ImageRgba8(RgbaImage::new(32, 32)),
+				],
+				delay: Some(vec![1.0, 2.0]),
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let mut dmi = Vec::new();
+		icon.save(&mut dmi).unwrap();
+		for compression in [PngCompression::Fast, PngCompression::Best] {
+			let recompressed = recompress(&dmi, compression).unwrap();
+			assert_eq!(
+				dmi_description(&recompressed).unwrap(),
+				dmi_description(&dmi).unwrap()
+			);
+			assert_eq!(Icon::load(&recompressed[..]).unwrap(), icon);
+		}
+	}
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MPL-2.0
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use std::{
+	io::{ErrorKind, Read, Write},
+	net::{TcpStream, ToSocketAddrs},
+	path::Path,
+	time::{Duration, Instant},
+};
+
+/// The largest response that's downloaded, which is far more than any real
+/// DMI needs
+const MAX_RESPONSE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Whether a source path is actually a URL to download
+pub fn is_url(path: &Path) -> bool {
+	path.to_str().is_some_and(|path| {
+		let lower = path.to_ascii_lowercase();
+		lower.starts_with("http://") || lower.starts_with("https://")
+	})
+}
+
+/// Download a file over HTTP into memory, giving up if the whole download
+/// takes longer than `timeout`. Redirects aren't followed, and responses over
+/// [`MAX_RESPONSE_SIZE`] are refused.
+pub fn fetch(url: &str, timeout: Duration) -> Result<Vec<u8>> {
+	let deadline = Instant::now() + timeout;
+	let (host, port, path) = parse_url(url)?;
+	let addrs = (host, port)
+		.to_socket_addrs()
+		.wrap_err_with(|| format!("failed to resolve {host}"))?;
+	let mut last_err = None;
+	let mut stream = None;
+	for addr in addrs {
+		match TcpStream::connect_timeout(&addr, timeout) {
+			Ok(connected) => {
+				stream = Some(connected);
+				break;
+			}
+			Err(err) => last_err = Some(err),
+		}
+	}
+	let mut stream = match (stream, last_err) {
+		(Some(stream), _) => stream,
+		(None, Some(err)) => {
+			return Err(err).wrap_err_with(|| format!("failed to connect to {host}:{port}"))
+		}
+		(None, None) => return Err(eyre!("{host} didn't resolve to any address")),
+	};
+	stream.set_read_timeout(Some(timeout))?;
+	stream.set_write_timeout(Some(timeout))?;
+
+	// HTTP/1.0 keeps the response simple: the server closes the connection
+	// when it's done, and never uses chunked encoding.
+	let host_header = if host.contains(':') {
+		format!("[{host}]")
+	} else {
+		host.to_string()
+	};
+	write!(
+		stream,
+		"GET {path} HTTP/1.0\r\nHost: {host_header}\r\nUser-Agent: dmi-copy/{}\r\nAccept: \
+		 */*\r\n\r\n",
+		env!("CARGO_PKG_VERSION")
+	)
+	.wrap_err("failed to send request")?;
+	// Each read only waits as long as the download has left, so a server
+	// that trickles the response out still runs out of time.
+	let timed_out = || eyre!("download didn't finish within {timeout:?}");
+	let mut response = Vec::new();
+	let mut buffer = [0; 8192];
+	loop {
+		let remaining = deadline.saturating_duration_since(Instant::now());
+		if remaining.is_zero() {
+			return Err(timed_out());
+		}
+		stream.set_read_timeout(Some(remaining))?;
+		let read = match stream.read(&mut buffer) {
+			Ok(0) => break,
+			Ok(read) => read,
+			Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+			Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+				return Err(timed_out());
+			}
+			Err(err) => return Err(err).wrap_err("failed to read response"),
+		};
+		if (response.len() + read) as u64 > MAX_RESPONSE_SIZE {
+			return Err(eyre!(
+				"response is larger than {} MiB",
+				MAX_RESPONSE_SIZE / 1024 / 1024
+			));
+		}
+		response.extend_from_slice(&buffer[..read]);
+	}
+	parse_response(response)
+}
+
+/// Split an `http://` URL into its host, port, and path. IPv6 hosts are
+/// written in brackets, like `[::1]:8080`, and returned without them.
+fn parse_url(url: &str) -> Result<(&str, u16, &str)> {
+	let (scheme, rest) = url
+		.split_once("://")
+		.ok_or_else(|| eyre!("invalid URL '{url}'"))?;
+	if scheme.eq_ignore_ascii_case("https") {
+		return Err(eyre!(
+			"https URLs aren't supported, since dmi-copy is built without TLS; use an http URL or \
+			 download the file first"
+		));
+	}
+	if !scheme.eq_ignore_ascii_case("http") {
+		return Err(eyre!("unsupported URL scheme '{scheme}'"));
+	}
+	let (authority, path) = match rest.find('/') {
+		Some(index) => rest.split_at(index),
+		None => (rest, "/"),
+	};
+	let (host, port) = match authority.strip_prefix('[') {
+		Some(bracketed) => {
+			let (host, rest) = bracketed
+				.split_once(']')
+				.ok_or_else(|| eyre!("unclosed '[' in URL '{url}'"))?;
+			match rest.strip_prefix(':') {
+				Some(port) => (host, Some(port)),
+				None if rest.is_empty() => (host, None),
+				None => return Err(eyre!("invalid host in URL '{url}'")),
+			}
+		}
+		None => match authority.rsplit_once(':') {
+			Some((host, port)) => (host, Some(port)),
+			None => (authority, None),
+		},
+	};
+	let port = match port {
+		Some(port) => port
+			.parse()
+			.map_err(|_| eyre!("invalid port '{port}' in URL '{url}'"))?,
+		None => 80,
+	};
+	if host.is_empty() {
+		return Err(eyre!("missing host in URL '{url}'"));
+	}
+	Ok((host, port, path))
+}
+
+/// Get the body of a successful HTTP response
+fn parse_response(mut response: Vec<u8>) -> Result<Vec<u8>> {
+	let header_end = response
+		.windows(4)
+		.position(|window| window == b"\r\n\r\n")
+		.ok_or_else(|| eyre!("malformed HTTP response"))?;
+	let head =
+		std::str::from_utf8(&response[..header_end]).wrap_err("malformed HTTP response headers")?;
+	let mut lines = head.split("\r\n");
+	let status = lines.next().unwrap_or_default();
+	let code = status
+		.split_whitespace()
+		.nth(1)
+		.ok_or_else(|| eyre!("malformed HTTP status line '{status}'"))?;
+	if code != "200" {
+		return Err(eyre!("server responded with '{status}'"));
+	}
+	let content_length = lines
+		.filter_map(|line| line.split_once(':'))
+		.find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+		.map(|(_, value)| value.trim().parse::<usize>())
+		.transpose()
+		.wrap_err("invalid Content-Length")?;
+
+	let body = response.split_off(header_end + 4);
+	match content_length {
+		Some(length) if length != body.len() => Err(eyre!(
+			"response was cut off after {} of {length} bytes",
+			body.len()
+		)),
+		_ => Ok(body),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_url() {
+		assert_eq!(
+			parse_url("http://assets.example:8080/icons/mob.dmi").unwrap(),
+			("assets.example", 8080, "/icons/mob.dmi")
+		);
+		assert_eq!(
+			parse_url("HTTP://assets.example").unwrap(),
+			("assets.example", 80, "/")
+		);
+		assert!(parse_url("https://assets.example/mob.dmi").is_err());
+		assert!(parse_url("ftp://assets.example/mob.dmi").is_err());
+		assert!(parse_url("http://:80/mob.dmi").is_err());
+		assert_eq!(
+			parse_url("http://[::1]:8080/mob.dmi").unwrap(),
+			("::1", 8080, "/mob.dmi")
+		);
+		assert_eq!(parse_url("http://[::1]").unwrap(), ("::1", 80, "/"));
+		assert!(parse_url("http://[::1/mob.dmi").is_err());
+		assert!(parse_url("http://[::1]x/mob.dmi").is_err());
+	}
+
+	#[test]
+	fn test_parse_response() {
+		let response = b"HTTP/1.0 200 OK\r\nContent-Length: 4\r\n\r\nbody".to_vec();
+		assert_eq!(parse_response(response).unwrap(), b"body");
+		let response = b"HTTP/1.0 200 OK\r\ncontent-length: 8\r\n\r\nbody".to_vec();
+		assert!(parse_response(response).is_err());
+		let response = b"HTTP/1.1 404 Not Found\r\n\r\n".to_vec();
+		assert!(parse_response(response).is_err());
+		assert_eq!(
+			parse_response(b"HTTP/1.0 200 OK\r\n\r\n".to_vec()).unwrap(),
+			b""
+		);
+	}
+
+	#[test]
+	fn test_fetch_deadline() {
+		use std::{net::TcpListener, thread};
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}/icon.dmi", listener.local_addr().unwrap());
+		let server = thread::spawn(move || {
+			let respond = |trickle: bool| {
+				let (mut stream, _) = listener.accept().unwrap();
+				let mut request = Vec::new();
+				while !request.ends_with(b"\r\n\r\n") {
+					let mut byte = [0];
+					stream.read_exact(&mut byte).unwrap();
+					request.push(byte[0]);
+				}
+				stream.write_all(b"HTTP/1.0 200 OK\r\n\r\n").unwrap();
+				if !trickle {
+					stream.write_all(b"body").unwrap();
+					return;
+				}
+				// Never stalls long enough for a single read to time out
+				while stream.write_all(b"x").is_ok() {
+					thread::sleep(Duration::from_millis(20));
+				}
+			};
+			respond(false);
+			respond(true);
+		});
+		assert_eq!(fetch(&url, Duration::from_secs(5)).unwrap(), b"body");
+		let start = Instant::now();
+		let err = fetch(&url, Duration::from_millis(200)).unwrap_err();
+		assert_eq!(err.to_string(), "download didn't finish within 200ms");
+		assert!(start.elapsed() < Duration::from_secs(2));
+		server.join().unwrap();
+	}
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MPL-2.0
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::IconState;
+use regex::{Regex, RegexBuilder};
+use std::{
+	collections::{BTreeMap, HashMap},
+	fs,
+	path::Path,
+};
+
+/// A sed-style `s/pattern/replacement/flags` substitution applied to state
+/// names
+#[derive(Debug, Clone)]
+pub struct RegexRename {
+	regex: Regex,
+	replacement: String,
+	global: bool,
+}
+
+impl RegexRename {
+	/// Parse a substitution like `s/^old_(.*)$/new_$1/`.
+	///
+	/// Any character may be used as the delimiter. The replacement may refer to
+	/// capture groups as `$1`/`${name}` or sed-style as `\1`. The `g` flag
+	/// replaces every match instead of only the first, and `i` makes the
+	/// pattern case-insensitive.
+	pub fn parse(arg: &str) -> Result<Self, String> {
+		let mut chars = arg.chars();
+		if chars.next() != Some('s') {
+			return Err(format!(
+				"expected a substitution like s/old/new/, got '{arg}'"
+			));
+		}
+		let delimiter = chars
+			.next()
+			.ok_or_else(|| "missing delimiter after 's'".to_string())?;
+
+		let mut parts = vec![String::new()];
+		let mut escaped = false;
+		for c in chars {
+			let part = parts.last_mut().expect("parts is never empty");
+			match c {
+				_ if escaped => {
+					escaped = false;
+					if c != delimiter {
+						part.push('\\');
+					}
+					part.push(c);
+				}
+				'\\' => escaped = true,
+				_ if c == delimiter => parts.push(String::new()),
+				_ => part.push(c),
+			}
+		}
+		let [pattern, replacement, flags] = <[String; 3]>::try_from(parts).map_err(|_| {
+			format!("expected s{delimiter}pattern{delimiter}replacement{delimiter}")
+		})?;
+
+		let mut global = false;
+		let mut builder = RegexBuilder::new(&pattern);
+		for flag in flags.chars() {
+			match flag {
+				'g' => global = true,
+				'i' => {
+					builder.case_insensitive(true);
+				}
+				_ => return Err(format!("unknown substitution flag '{flag}'")),
+			}
+		}
+		let regex = builder.build().map_err(|err| err.to_string())?;
+		Ok(Self {
+			regex,
+			replacement: sed_replacement(&replacement),
+			global,
+		})
+	}
+
+	/// Apply the substitution to a name
+	pub fn apply(&self, name: &str) -> String {
+		if self.global {
+			self.regex.replace_all(name, &self.replacement).into_owned()
+		} else {
+			self.regex.replace(name, &self.replacement).into_owned()
+		}
+	}
+}
+
+/// A file-driven mapping of old state names to new ones
+#[derive(Debug, Default)]
+pub struct RenameMap(BTreeMap<String, String>);
+
+impl RenameMap {
+	/// Read a rename map file
+	pub fn load(path: &Path) -> Result<Self> {
+		let contents = fs::read_to_string(path)
+			.wrap_err_with(|| format!("failed to read {}", path.display()))?;
+		Self::parse(&contents).wrap_err_with(|| format!("invalid rename map {}", path.display()))
+	}
+
+	/// Parse a rename map with one `old=new` mapping per line. Tabs or commas
+	/// may separate the names instead, for maps kept as TSV or CSV, and are
+	/// checked for in that order. Blank lines and `#` comments are skipped, and
+	/// the default state is written as `""`.
+	fn parse(contents: &str) -> Result<Self> {
+		let mut map = BTreeMap::new();
+		for (number, line) in contents.lines().enumerate() {
+			let line = line.trim_end_matches('\r');
+			if line.trim().is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let (old, new) = ['\t', '=', ',']
+				.iter()
+				.find_map(|&separator| line.split_once(separator))
+				.ok_or_else(|| eyre!("line {} isn't an old=new mapping", number + 1))?;
+			let name = |name: &str| match name.trim() {
+				"\"\"" => String::new(),
+				name => name.to_string(),
+			};
+			if map.insert(name(old), name(new)).is_some() {
+				return Err(eyre!("line {} maps '{}' again", number + 1, old.trim()));
+			}
+		}
+		Ok(Self(map))
+	}
+
+	/// The names of the states this map renames, in sorted order so warnings
+	/// about them come out the same way every time
+	pub fn old_names(&self) -> impl Iterator<Item = &str> {
+		self.0.keys().map(String::as_str)
+	}
+
+	/// Map a name to its new name, keeping unmapped names as they are
+	pub fn apply(&self, name: &str) -> String {
+		self.0.get(name).map_or(name, String::as_str).to_string()
+	}
+}
+
+/// Convert sed-style `\1` group references to the `${1}` syntax regex uses,
+/// and unescape literal backslashes
+fn sed_replacement(replacement: &str) -> String {
+	let mut output = String::with_capacity(replacement.len());
+	let mut chars = replacement.chars();
+	while let Some(c) = chars.next() {
+		match (c, chars.clone().next()) {
+			('\\', Some(digit @ '0'..='9')) => {
+				chars.next();
+				output.push_str(&format!("${{{digit}}}"));
+			}
+			('\\', Some('\\')) => {
+				chars.next();
+				output.push('\\');
+			}
+			_ => output.push(c),
+		}
+	}
+	output
+}
+
+/// Rename states with a function of their names, like a substitution's or a
+/// rename map's `apply`, returning the `(old, new)` names of every state that
+/// changed.
+///
+/// Fails without renaming anything if two different states would end up
+/// with the same name.
+pub fn rename_states(
+	states: &mut [IconState],
+	rename: impl Fn(&str) -> String,
+) -> Result<Vec<(String, String)>> {
+	let new_names = states
+		.iter()
+		.map(|state| rename(&state.name))
+		.collect::<Vec<_>>();
+
+	let mut originals = HashMap::<&str, &str>::new();
+	for (state, new_name) in states.iter().zip(&new_names) {
+		match originals.insert(new_name, &state.name) {
+			Some(other) if other != state.name => {
+				return Err(eyre!(
+					"states '{other}' and '{}' would both be renamed to '{new_name}'",
+					state.name
+				));
+			}
+			_ => {}
+		}
+	}
+
+	let mut renamed = Vec::new();
+	for (state, new_name) in states.iter_mut().zip(new_names) {
+		if state.name != new_name {
+			renamed.push((
+				std::mem::replace(&mut state.name, new_name.clone()),
+				new_name,
+			));
+		}
+	}
+	Ok(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_regex_rename() {
+		let rename = RegexRename::parse("s/^old_(.*)$/new_$1/").unwrap();
+		assert_eq!(rename.apply("old_walk"), "new_walk");
+		assert_eq!(rename.apply("walk"), "walk");
+
+		let rename = RegexRename::parse(r"s|a(\d)|b\1|g").unwrap();
+		assert_eq!(rename.apply("a1_a2"), "b1_b2");
+
+		let rename = RegexRename::parse(r"s/\//_/").unwrap();
+		assert_eq!(rename.apply("a/b"), "a_b");
+
+		let rename = RegexRename::parse("s/WALK/run/i").unwrap();
+		assert_eq!(rename.apply("walk_north"), "run_north");
+
+		assert!(RegexRename::parse("old/new").is_err());
+		assert!(RegexRename::parse("s/old/new").is_err());
+		assert!(RegexRename::parse("s/old/new/x").is_err());
+		assert!(RegexRename::parse("s/(/new/").is_err());
+	}
+
+	#[test]
+	fn test_rename_collisions() {
+		let state = |name: &str| IconState {
+			name: name.to_string(),
+			..Default::default()
+		};
+		let rename = RegexRename::parse("s/_[0-9]+$//").unwrap();
+
+		let mut states = vec![state("walk_1"), state("run")];
+		let renamed = rename_states(&mut states, |name| rename.apply(name)).unwrap();
+		assert_eq!(renamed, vec![("walk_1".to_string(), "walk".to_string())]);
+
+		let mut states = vec![state("walk_1"), state("walk_2")];
+		assert!(rename_states(&mut states, |name| rename.apply(name)).is_err());
+		assert_eq!(states[0].name, "walk_1");
+	}
+
+	#[test]
+	fn test_rename_map() {
+		let map = RenameMap::parse(
+			"# migration\nold_walk=walk\n\nold run\trun fast\r\nidle,\"\"\nbolt=a=b\n",
+		)
+		.unwrap();
+		assert_eq!(map.apply("old_walk"), "walk");
+		assert_eq!(map.apply("old run"), "run fast");
+		assert_eq!(map.apply("idle"), "");
+		assert_eq!(map.apply("bolt"), "a=b");
+		assert_eq!(map.apply("unmapped"), "unmapped");
+		assert_eq!(map.old_names().collect::<Vec<_>>(), [
+			"bolt", "idle", "old run", "old_walk"
+		]);
+
+		assert!(RenameMap::parse("walk\n").is_err());
+		assert!(RenameMap::parse("walk=a\nwalk=b\n").is_err());
+	}
+}
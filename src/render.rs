@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: MPL-2.0
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+/// Previews wider than this many pixels are scaled down to fit
+const MAX_PREVIEW_WIDTH: u32 = 64;
+/// Characters used for ASCII previews, from darkest to lightest
+const ASCII_RAMP: &[u8] = b"@%#*+=-:.";
+
+/// Render an image for the terminal, either with ANSI colors and half blocks,
+/// two pixels to a character, or as plain ASCII, one pixel to two characters
+pub fn preview(image: &DynamicImage, color: bool) -> String {
+	let mut image = image.to_rgba8();
+	if image.width() > MAX_PREVIEW_WIDTH {
+		let height = image.height() * MAX_PREVIEW_WIDTH / image.width();
+		image = imageops::resize(
+			&image,
+			MAX_PREVIEW_WIDTH,
+			height.max(1),
+			imageops::FilterType::Nearest,
+		);
+	}
+	if color {
+		preview_color(&image)
+	} else {
+		preview_ascii(&image)
+	}
+}
+
+fn preview_color(image: &RgbaImage) -> String {
+	let visible = |pixel: &Rgba<u8>| pixel[3] >= 128;
+	let mut out = String::new();
+	for y in (0..image.height()).step_by(2) {
+		for x in 0..image.width() {
+			let top = image.get_pixel(x, y);
+			let bottom = (y + 1 < image.height()).then(|| image.get_pixel(x, y + 1));
+			match (visible(top), bottom.filter(|pixel| visible(pixel))) {
+				(true, Some(bottom)) => out.push_str(&format!(
+					"\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀\x1b[0m",
+					top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+				)),
+				(true, None) => out.push_str(&format!(
+					"\x1b[38;2;{};{};{}m▀\x1b[0m",
+					top[0], top[1], top[2]
+				)),
+				(false, Some(bottom)) => out.push_str(&format!(
+					"\x1b[38;2;{};{};{}m▄\x1b[0m",
+					bottom[0], bottom[1], bottom[2]
+				)),
+				(false, None) => out.push(' '),
+			}
+		}
+		out.push('\n');
+	}
+	out
+}
+
+fn preview_ascii(image: &RgbaImage) -> String {
+	let mut out = String::new();
+	for row in image.rows() {
+		for pixel in row {
+			let glyph = if pixel[3] < 128 {
+				' '
+			} else {
+				let luma =
+					(pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+				ASCII_RAMP[luma as usize * (ASCII_RAMP.len() - 1) / 255] as char
+			};
+			// Characters are about twice as tall as they are wide.
+			out.push(glyph);
+			out.push(glyph);
+		}
+		out.push('\n');
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_preview() {
+		let mut image = RgbaImage::new(2, 2);
+		image.put_pixel(0, 0, Rgba([0, 0, 0, 255]));
+		image.put_pixel(1, 1, Rgba([255, 255, 255, 255]));
+		let image = DynamicImage::ImageRgba8(image);
+		assert_eq!(preview(&image, false), "@@  \n  ..\n");
+		assert_eq!(
+			preview(&image, true),
+			"\x1b[38;2;0;0;0m▀\x1b[0m\x1b[38;2;255;255;255m▄\x1b[0m\n"
+		);
+	}
+}
@@ -0,0 +1,496 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+use color_eyre::eyre::{Result, WrapErr};
+use serde::Serialize;
+use std::{
+	fs::{File, OpenOptions},
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+	time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How much of a copy's progress is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+	/// Print every state and the final summary
+	#[default]
+	Normal,
+	/// Print every state once the copy is done, grouped by what happened to
+	/// it, and then the final summary
+	Grouped,
+	/// Only print the final summary
+	SummaryOnly,
+	/// Print nothing
+	Quiet,
+}
+
+/// How a copy's progress is printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+	/// Plain lines of text
+	#[default]
+	Text,
+	/// Structured `tracing` events, with the file, state, and action as
+	/// fields
+	Tracing,
+}
+
+/// A summary of what a copy did to a single target file
+#[derive(Debug, Default, Serialize)]
+pub struct CopyReport {
+	/// How much of the copy's progress is printed
+	#[serde(skip)]
+	pub verbosity: Verbosity,
+	/// How the copy's progress is printed
+	#[serde(skip)]
+	pub log_format: LogFormat,
+	/// The files the icon states were copied from
+	pub sources: Vec<PathBuf>,
+	/// The file the icon states were copied into
+	pub target: PathBuf,
+	/// States that didn't exist in the target and were added
+	pub added: Vec<String>,
+	/// States that existed in the target and were replaced
+	pub replaced: Vec<String>,
+	/// States that already existed in the target with identical content
+	pub identical: Vec<String>,
+	/// Requested states that weren't copied
+	pub skipped: Vec<SkippedState>,
+	/// Requested states that matched nothing in the sources
+	pub unmatched: Vec<String>,
+	/// States in one source that were overridden by another source
+	pub shadowed: Vec<ShadowedState>,
+	/// States that failed to copy, and were skipped so the rest could be
+	pub failed: Vec<FailedState>,
+	/// Problems that didn't stop the copy
+	pub warnings: Vec<String>,
+}
+
+/// A requested state that wasn't copied, and why
+#[derive(Debug, Serialize)]
+pub struct SkippedState {
+	pub name: String,
+	pub reason: String,
+}
+
+/// A state that failed to copy, and the error it failed with
+#[derive(Debug, Serialize)]
+pub struct FailedState {
+	pub name: String,
+	pub error: String,
+}
+
+/// A single line of an append log
+#[derive(Debug, Serialize)]
+struct LogEntry<'a> {
+	timestamp: String,
+	user: Option<String>,
+	#[serde(flatten)]
+	report: &'a CopyReport,
+}
+
+/// A state in one source that was overridden by another source
+#[derive(Debug, Serialize)]
+pub struct ShadowedState {
+	pub name: String,
+	/// The source whose state wasn't copied
+	pub source: PathBuf,
+	/// The source whose state was copied instead
+	pub shadowed_by: PathBuf,
+}
+
+/// The contents of a report file, covering every processed target
+#[derive(Debug, Serialize)]
+struct ReportFile<'a> {
+	files: &'a [CopyReport],
+}
+
+impl CopyReport {
+	pub fn new(
+		sources: &[PathBuf],
+		target: &Path,
+		verbosity: Verbosity,
+		log_format: LogFormat,
+	) -> Self {
+		Self {
+			verbosity,
+			log_format,
+			sources: sources.to_vec(),
+			target: target.to_path_buf(),
+			..Default::default()
+		}
+	}
+
+	/// Print an informational message about the copy's progress
+	pub fn info(&self, message: impl std::fmt::Display) {
+		if matches!(self.verbosity, Verbosity::Normal | Verbosity::Grouped) {
+			match self.log_format {
+				LogFormat::Text => println!("{message}"),
+				LogFormat::Tracing => {
+					tracing::info!(file = %self.target.display(), "{message}");
+				}
+			}
+		}
+	}
+
+	/// Print what happened to a single state
+	fn state_action(&self, name: &str, action: &str, message: impl std::fmt::Display) {
+		if self.verbosity == Verbosity::Normal {
+			match self.log_format {
+				LogFormat::Text => println!("{message}"),
+				LogFormat::Tracing => tracing::info!(
+					file = %self.target.display(),
+					state = name,
+					action,
+					"{message}"
+				),
+			}
+		}
+	}
+
+	/// Print a warning to stderr and record it
+	pub fn warn(&mut self, message: impl std::fmt::Display) {
+		let message = message.to_string();
+		if self.verbosity != Verbosity::Quiet {
+			match self.log_format {
+				LogFormat::Text => eprintln!("warning: {message}"),
+				LogFormat::Tracing => {
+					tracing::warn!(file = %self.target.display(), "{message}");
+				}
+			}
+		}
+		self.warnings.push(message);
+	}
+
+	pub fn added(&mut self, name: &str) {
+		self.state_action(name, "added", format_args!("State '{name}' added"));
+		self.added.push(name.to_string());
+	}
+
+	pub fn replaced(&mut self, name: &str) {
+		self.state_action(name, "replaced", format_args!("State '{name}' replaced"));
+		self.replaced.push(name.to_string());
+	}
+
+	pub fn identical(&mut self, name: &str) {
+		self.state_action(
+			name,
+			"identical",
+			format_args!("State '{name}' identical in both files"),
+		);
+		self.identical.push(name.to_string());
+	}
+
+	/// Print the error a state failed with to stderr, and record it
+	pub fn failed(&mut self, name: &str, error: impl std::fmt::Display) {
+		let error = error.to_string();
+		if self.verbosity != Verbosity::Quiet {
+			match self.log_format {
+				LogFormat::Text => eprintln!("error: {error}"),
+				LogFormat::Tracing => tracing::error!(
+					file = %self.target.display(),
+					state = name,
+					action = "failed",
+					"{error}"
+				),
+			}
+		}
+		self.failed.push(FailedState {
+			name: name.to_string(),
+			error,
+		});
+	}
+
+	/// Record a requested state that isn't in the source, with a warning
+	/// unless it's `quiet`
+	pub fn unmatched(&mut self, name: &str, quiet: bool) {
+		if !quiet {
+			self.warn(format_args!("state '{name}' not found in source"));
+		}
+		self.skipped(name, "not found in source");
+		self.unmatched.push(name.to_string());
+	}
+
+	pub fn skipped(&mut self, name: &str, reason: &str) {
+		// Skipped states are only listed in the text summary's count, but are
+		// worth an event of their own.
+		if self.log_format == LogFormat::Tracing && self.verbosity == Verbosity::Normal {
+			tracing::info!(
+				file = %self.target.display(),
+				state = name,
+				action = "skipped",
+				reason,
+				"State '{name}' skipped: {reason}"
+			);
+		}
+		self.skipped.push(SkippedState {
+			name: name.to_string(),
+			reason: reason.to_string(),
+		});
+	}
+
+	pub fn shadowed(&mut self, name: &str, source: &Path, shadowed_by: &Path) {
+		self.state_action(
+			name,
+			"shadowed",
+			format_args!(
+				"State '{name}' in {} shadowed by {}",
+				source.display(),
+				shadowed_by.display()
+			),
+		);
+		self.shadowed.push(ShadowedState {
+			name: name.to_string(),
+			source: source.to_path_buf(),
+			shadowed_by: shadowed_by.to_path_buf(),
+		});
+	}
+
+	/// Print the number of states in each outcome
+	pub fn print_summary(&self) {
+		if self.verbosity == Verbosity::Grouped {
+			print!("{}", self.grouped());
+		}
+		if self.verbosity != Verbosity::Quiet {
+			let mut summary = format!(
+				"{} added, {} replaced, {} identical, {} skipped",
+				self.added.len(),
+				self.replaced.len(),
+				self.identical.len(),
+				self.skipped.len()
+			);
+			if !self.failed.is_empty() {
+				summary += &format!(", {} failed", self.failed.len());
+			}
+			match self.log_format {
+				LogFormat::Text => println!("{summary}"),
+				LogFormat::Tracing => tracing::info!(
+					file = %self.target.display(),
+					added = self.added.len(),
+					replaced = self.replaced.len(),
+					identical = self.identical.len(),
+					skipped = self.skipped.len(),
+					failed = self.failed.len(),
+					"{summary}"
+				),
+			}
+		}
+	}
+
+	/// List the states by what happened to them, leaving out empty groups
+	fn grouped(&self) -> String {
+		let mut out = String::new();
+		let names = |names: &[String]| names.iter().map(|name| format!("'{name}'")).collect();
+		let groups: [(&str, Vec<String>); 5] = [
+			("Added", names(&self.added)),
+			("Replaced", names(&self.replaced)),
+			("Identical", names(&self.identical)),
+			(
+				"Skipped",
+				self.skipped
+					.iter()
+					.map(|state| format!("'{}': {}", state.name, state.reason))
+					.collect(),
+			),
+			(
+				"Failed",
+				self.failed
+					.iter()
+					.map(|state| format!("'{}': {}", state.name, state.error))
+					.collect(),
+			),
+		];
+		for (outcome, lines) in groups.iter().filter(|(_, lines)| !lines.is_empty()) {
+			out += &format!(
+				"{outcome} in {} ({}):\n",
+				self.target.display(),
+				lines.len()
+			);
+			for line in lines {
+				out += &format!("  {line}\n");
+			}
+		}
+		out
+	}
+}
+
+/// Write the given reports to a file as JSON
+pub fn write_report_file(path: &Path, reports: &[CopyReport]) -> Result<()> {
+	let mut file = File::create(path)
+		.map(BufWriter::new)
+		.wrap_err("failed to create report file")?;
+	serde_json::to_writer_pretty(&mut file, &ReportFile { files: reports })
+		.wrap_err("failed to serialize report")?;
+	writeln!(file).wrap_err("failed to write report file")?;
+	file.flush().wrap_err("failed to write report file")
+}
+
+/// Append a JSON line describing the copy to a log file, creating it if
+/// needed
+pub fn append_log(path: &Path, report: &CopyReport) -> Result<()> {
+	let entry = LogEntry {
+		timestamp: utc_timestamp(SystemTime::now()),
+		user: std::env::var("USER")
+			.or_else(|_| std::env::var("USERNAME"))
+			.ok(),
+		report,
+	};
+	let mut line = serde_json::to_string(&entry).wrap_err("failed to serialize log entry")?;
+	line.push('\n');
+	// A single write keeps concurrent runs from interleaving their lines
+	OpenOptions::new()
+		.create(true)
+		.append(true)
+		.open(path)
+		.wrap_err("failed to open log file")?
+		.write_all(line.as_bytes())
+		.wrap_err("failed to write log file")
+}
+
+/// Format a time as an RFC 3339 timestamp in UTC, e.g.
+/// `2024-01-31T12:34:56Z`
+fn utc_timestamp(time: SystemTime) -> String {
+	let secs = time
+		.duration_since(UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or(0);
+	let (days, secs) = (secs / 86400, secs % 86400);
+	// Convert days since the epoch to a civil date, per Howard Hinnant's
+	// `civil_from_days`
+	let days = days as i64 + 719468;
+	let era = days.div_euclid(146097);
+	let day_of_era = days.rem_euclid(146097);
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let shifted_month = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+	let month = if shifted_month < 10 {
+		shifted_month + 3
+	} else {
+		shifted_month - 9
+	};
+	let year = year_of_era + era * 400 + i64::from(month <= 2);
+	format!(
+		"{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+		secs / 3600,
+		secs / 60 % 60,
+		secs % 60
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::schema::{assert_matches_schema, SchemaKind};
+
+	#[test]
+	fn test_grouped() {
+		let mut report =
+			CopyReport::new(&[], Path::new("t.dmi"), Verbosity::Quiet, LogFormat::Text);
+		report.added("a");
+		report.added("b");
+		report.skipped("c", "excluded");
+		assert_eq!(
+			report.grouped(),
+			"Added in t.dmi (2):\n  'a'\n  'b'\nSkipped in t.dmi (1):\n  'c': excluded\n"
+		);
+	}
+
+	/// Collects what a `tracing` subscriber writes
+	#[derive(Clone, Default)]
+	struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+	impl Write for Captured {
+		fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+			self.0.lock().unwrap().write(buf)
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn test_tracing_fields() {
+		let captured = Captured::default();
+		let writer = captured.clone();
+		let subscriber = tracing_subscriber::fmt()
+			.with_writer(move || writer.clone())
+			.without_time()
+			.with_target(false)
+			.finish();
+		tracing::subscriber::with_default(subscriber, || {
+			let mut report = CopyReport::new(
+				&[],
+				Path::new("t.dmi"),
+				Verbosity::Normal,
+				LogFormat::Tracing,
+			);
+			report.added("a");
+			report.skipped("b", "excluded");
+			report.failed("c", "broken");
+			report.warn("careful");
+			report.print_summary();
+		});
+		let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+		let lines = output.lines().collect::<Vec<_>>();
+		assert_eq!(lines, [
+			r#" INFO State 'a' added file=t.dmi state="a" action="added""#,
+			r#" INFO State 'b' skipped: excluded file=t.dmi state="b" action="skipped" reason="excluded""#,
+			r#"ERROR broken file=t.dmi state="c" action="failed""#,
+			r#" WARN careful file=t.dmi"#,
+			" INFO 1 added, 0 replaced, 0 identical, 1 skipped, 1 failed file=t.dmi added=1 \
+			 replaced=0 identical=0 skipped=1 failed=1",
+		]);
+	}
+
+	#[test]
+	fn test_report_matches_schema() {
+		let report = CopyReport {
+			skipped: vec![SkippedState {
+				name: "a".to_string(),
+				reason: "b".to_string(),
+			}],
+			shadowed: vec![ShadowedState {
+				name: "a".to_string(),
+				source: PathBuf::from("b"),
+				shadowed_by: PathBuf::from("c"),
+			}],
+			failed: vec![FailedState {
+				name: "a".to_string(),
+				error: "b".to_string(),
+			}],
+			..Default::default()
+		};
+		let value = serde_json::to_value(ReportFile {
+			files: std::slice::from_ref(&report),
+		})
+		.unwrap();
+		assert_matches_schema(SchemaKind::Report, "", &value);
+		let report = &value["files"][0];
+		assert_matches_schema(SchemaKind::Report, "/$defs/copyReport", report);
+		let items = "/$defs/copyReport/properties";
+		for field in ["skipped", "shadowed", "failed"] {
+			assert_matches_schema(
+				SchemaKind::Report,
+				&format!("{items}/{field}/items"),
+				&report[field][0],
+			);
+		}
+	}
+	use std::time::Duration;
+
+	#[test]
+	fn test_utc_timestamp() {
+		assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+		assert_eq!(
+			utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+			"2000-02-29T12:34:56Z"
+		);
+		assert_eq!(
+			utc_timestamp(UNIX_EPOCH + Duration::from_secs(1_735_689_599)),
+			"2024-12-31T23:59:59Z"
+		);
+	}
+}
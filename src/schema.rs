@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+
+/// A structured output format with a JSON Schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaKind {
+	/// The file written by --report-file
+	Report,
+	/// The output of info --format json
+	Info,
+	/// The output of --list-source-states
+	SourceStates,
+}
+
+/// The JSON Schema of a structured output format
+pub fn schema(kind: SchemaKind) -> &'static str {
+	match kind {
+		SchemaKind::Report => include_str!("schemas/report.json"),
+		SchemaKind::Info => include_str!("schemas/info.json"),
+		SchemaKind::SourceStates => include_str!("schemas/source-states.json"),
+	}
+}
+
+/// Check that a serialized value has exactly the properties the schema at
+/// `pointer` in a schema lists, so the schemas can't drift from the structs
+/// they describe
+#[cfg(test)]
+pub fn assert_matches_schema(kind: SchemaKind, pointer: &str, value: &serde_json::Value) {
+	let schema = serde_json::from_str::<serde_json::Value>(schema(kind)).unwrap();
+	let object = schema.pointer(pointer).unwrap();
+	let mut properties = object["properties"]
+		.as_object()
+		.unwrap()
+		.keys()
+		.collect::<Vec<_>>();
+	let mut required = object["required"]
+		.as_array()
+		.unwrap()
+		.iter()
+		.map(|key| key.as_str().unwrap())
+		.collect::<Vec<_>>();
+	let mut keys = value.as_object().unwrap().keys().collect::<Vec<_>>();
+	properties.sort();
+	required.sort();
+	keys.sort();
+	assert_eq!(keys, properties, "{kind:?} schema at {pointer:?}");
+	assert_eq!(required, properties, "{kind:?} schema at {pointer:?}");
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_schemas_are_json() {
+		for kind in SchemaKind::value_variants() {
+			let schema = serde_json::from_str::<serde_json::Value>(schema(*kind)).unwrap();
+			assert!(schema["$schema"].is_string());
+		}
+	}
+}
@@ -0,0 +1,471 @@
+// SPDX-License-Identifier: MPL-2.0
+use clap::ValueEnum;
+use color_eyre::eyre::{Result, WrapErr};
+use dmi::icon::{Icon, IconState};
+use regex::Regex;
+
+/// How requested state names are matched against the states in a source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum MatchMode {
+	/// The state's name is exactly the requested one
+	#[default]
+	Exact,
+	/// The state's name contains the requested one
+	Contains,
+	/// The state's name matches a glob, where `*` matches anything, `?`
+	/// matches a single character, and `[...]` matches a set of characters
+	Glob,
+	/// The state's name contains a match for a regular expression
+	Regex,
+}
+
+/// A way of canonicalizing state names, so names that only differ by it are
+/// treated as the same state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum NameNormalization {
+	/// Ignore leading and trailing whitespace
+	Trim,
+	/// Ignore case
+	Lower,
+	/// Treat runs of spaces, dashes, and underscores as a single underscore
+	Underscore,
+}
+
+/// Canonicalize a state name. The normalizations are always applied in the
+/// order trim, lower, then underscore, whatever order they're given in.
+pub fn normalize_name(name: &str, normalizations: &[NameNormalization]) -> String {
+	let mut name = name.to_string();
+	if normalizations.contains(&NameNormalization::Trim) {
+		name = name.trim().to_string();
+	}
+	if normalizations.contains(&NameNormalization::Lower) {
+		name = name.to_lowercase();
+	}
+	if normalizations.contains(&NameNormalization::Underscore) {
+		name = name
+			.split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+			.collect::<Vec<_>>()
+			.join("_");
+		while name.contains("__") {
+			name = name.replace("__", "_");
+		}
+	}
+	name
+}
+
+/// A single requested state name, or a pattern of them
+#[derive(Debug)]
+pub struct Pattern {
+	text: String,
+	/// The text with the name normalizations applied, for exact and contains
+	/// matching
+	normalized: String,
+	mode: MatchMode,
+	regex: Option<Regex>,
+}
+
+impl Pattern {
+	fn new(text: String, mode: MatchMode, normalizations: &[NameNormalization]) -> Result<Self> {
+		let regex = match mode {
+			MatchMode::Exact | MatchMode::Contains => None,
+			MatchMode::Glob => Some(glob_to_regex(&text)),
+			MatchMode::Regex => Some(text.clone()),
+		}
+		.map(|regex| Regex::new(&regex))
+		.transpose()
+		.wrap_err_with(|| format!("invalid state pattern '{text}'"))?;
+		Ok(Self {
+			normalized: normalize_name(&text, normalizations),
+			text,
+			mode,
+			regex,
+		})
+	}
+
+	/// The name or pattern as it was requested
+	pub fn text(&self) -> &str {
+		&self.text
+	}
+
+	/// Whether a state name, already normalized, matches
+	fn matches(&self, name: &str) -> bool {
+		match (&self.regex, self.mode) {
+			(Some(regex), _) => regex.is_match(name),
+			(None, MatchMode::Contains) => name.contains(&self.normalized),
+			(None, _) => name == self.normalized,
+		}
+	}
+}
+
+/// Translate a glob into an anchored regular expression
+pub fn glob_to_regex(glob: &str) -> String {
+	let mut regex = String::from("^");
+	let mut chars = glob.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'*' => regex.push_str(".*"),
+			'?' => regex.push('.'),
+			'[' => {
+				let set = chars.by_ref().take_while(|&c| c != ']').collect::<String>();
+				let (negated, set) = match set.strip_prefix('!') {
+					Some(set) => ("^", set),
+					None => ("", set.as_str()),
+				};
+				// Ranges like `a-z` are kept, but anything else is literal.
+				let set = set
+					.split('-')
+					.map(regex::escape)
+					.collect::<Vec<_>>()
+					.join("-");
+				regex.push_str(&format!("[{negated}{set}]"));
+			}
+			c => regex.push_str(&regex::escape(&c.to_string())),
+		}
+	}
+	regex.push('$');
+	regex
+}
+
+/// Matches icon state names against the requested states.
+///
+/// Requested names prefixed with `!` are exclusions, which are applied after
+/// every inclusion regardless of the order they were given in, much like a
+/// `.gitignore` file. If only exclusions were requested, every other state
+/// matches, which is only useful alongside a [`StructureFilter`].
+///
+/// With name normalizations, state names are normalized before they're
+/// matched, and so are requested names matched exactly or by containment.
+/// Globs and regexes are matched against the normalized names as written.
+#[derive(Debug)]
+pub struct StateMatcher {
+	include: Vec<Pattern>,
+	exclude: Vec<Pattern>,
+	normalizations: Vec<NameNormalization>,
+}
+
+impl StateMatcher {
+	/// Match the requested names as patterns of the given kind
+	pub fn with_mode(
+		requested: &[String],
+		mode: MatchMode,
+		normalizations: &[NameNormalization],
+	) -> Result<Self> {
+		let (exclude, include): (Vec<_>, Vec<_>) = requested
+			.iter()
+			.cloned()
+			.partition(|name| name.starts_with('!'));
+		Ok(Self {
+			include: include
+				.into_iter()
+				.map(|name| Pattern::new(name, mode, normalizations))
+				.collect::<Result<_>>()?,
+			exclude: exclude
+				.into_iter()
+				.map(|name| Pattern::new(name[1..].to_string(), mode, normalizations))
+				.collect::<Result<_>>()?,
+			normalizations: normalizations.to_vec(),
+		})
+	}
+
+	/// The patterns that were requested for inclusion
+	pub fn inclusions(&self) -> &[Pattern] {
+		&self.include
+	}
+
+	/// Whether a state name was explicitly requested for inclusion
+	pub fn is_requested(&self, name: &str) -> bool {
+		let name = normalize_name(name, &self.normalizations);
+		self.include.iter().any(|included| included.matches(&name))
+	}
+
+	/// Whether a pattern that was requested for inclusion matches a state name
+	pub fn pattern_matches(&self, pattern: &Pattern, name: &str) -> bool {
+		pattern.matches(&normalize_name(name, &self.normalizations))
+	}
+
+	/// Whether a state name was explicitly excluded
+	pub fn is_excluded(&self, name: &str) -> bool {
+		let name = normalize_name(name, &self.normalizations);
+		self.exclude.iter().any(|excluded| excluded.matches(&name))
+	}
+
+	/// Whether a state with this name should be copied
+	pub fn matches(&self, name: &str) -> bool {
+		(self.include.is_empty() || self.is_requested(name)) && !self.is_excluded(name)
+	}
+}
+
+/// Narrows the selected states down to only animated or only static ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFilter {
+	/// Only states with more than one frame
+	Animated,
+	/// Only states with a single frame
+	Static,
+}
+
+impl AnimationFilter {
+	pub fn matches(self, state: &IconState) -> bool {
+		match self {
+			Self::Animated => state.frames > 1,
+			Self::Static => state.frames <= 1,
+		}
+	}
+
+	/// Why a state that doesn't match was skipped
+	pub fn skip_reason(self) -> &'static str {
+		match self {
+			Self::Animated => "not animated",
+			Self::Static => "animated",
+		}
+	}
+}
+
+/// Narrows the selected states down by their frame and direction counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StructureFilter {
+	/// Only states with more than this many frames
+	pub frames_gt: Option<u32>,
+	/// Only states with fewer than this many frames
+	pub frames_lt: Option<u32>,
+	/// Only states with exactly this many frames
+	pub frames: Option<u32>,
+	/// Only states with exactly this many directions
+	pub dirs: Option<u8>,
+}
+
+impl StructureFilter {
+	/// Whether no predicates were given, so every state matches
+	pub fn is_empty(&self) -> bool {
+		*self == Self::default()
+	}
+
+	pub fn matches(&self, state: &IconState) -> bool {
+		self.frames_gt.is_none_or(|frames| state.frames > frames)
+			&& self.frames_lt.is_none_or(|frames| state.frames < frames)
+			&& self.frames.is_none_or(|frames| state.frames == frames)
+			&& self.dirs.is_none_or(|dirs| state.dirs == dirs)
+	}
+}
+
+/// Which source wins when several have a state with the same name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum SourcePriority {
+	/// The earliest source with the state wins
+	First,
+	/// The latest source with the state wins, so later sources override
+	/// earlier ones like layers
+	#[default]
+	Last,
+}
+
+/// A source's state that lost to a same-named state in another source
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shadowed {
+	pub name: String,
+	/// The index of the source whose state lost
+	pub source: usize,
+	/// The index of the source whose state won
+	pub winner: usize,
+}
+
+/// Combine the states of several sources into one list, with one state per
+/// name across sources.
+///
+/// States keep the position their name first appeared at. Duplicate names
+/// within a single source are left alone.
+pub fn merge_sources(
+	sources: &[Icon],
+	priority: SourcePriority,
+) -> (Vec<IconState>, Vec<Shadowed>) {
+	let mut merged: Vec<(usize, IconState)> = Vec::new();
+	let mut shadowed = Vec::new();
+	for (source, icon) in sources.iter().enumerate() {
+		for state in &icon.states {
+			let existing = merged
+				.iter_mut()
+				.find(|(other, existing)| *other != source && existing.name == state.name);
+			match (existing, priority) {
+				(Some((winner, _)), SourcePriority::First) => shadowed.push(Shadowed {
+					name: state.name.clone(),
+					source,
+					winner: *winner,
+				}),
+				(Some(existing), SourcePriority::Last) => {
+					shadowed.push(Shadowed {
+						name: state.name.clone(),
+						source: existing.0,
+						winner: source,
+					});
+					*existing = (source, state.clone());
+				}
+				(None, _) => merged.push((source, state.clone())),
+			}
+		}
+	}
+	(
+		merged.into_iter().map(|(_, state)| state).collect(),
+		shadowed,
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn matcher(requested: &[&str]) -> StateMatcher {
+		StateMatcher::with_mode(
+			&requested.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+			MatchMode::Exact,
+			&[],
+		)
+		.unwrap()
+	}
+
+	#[test]
+	fn test_exclusions_apply_after_inclusions() {
+		let matcher = matcher(&["!walk", "walk", "run"]);
+		assert!(!matcher.matches("walk"));
+		assert!(matcher.matches("run"));
+		assert!(!matcher.matches("idle"));
+		let included = matcher
+			.inclusions()
+			.iter()
+			.map(Pattern::text)
+			.collect::<Vec<_>>();
+		assert_eq!(included, ["walk", "run"]);
+	}
+
+	#[test]
+	fn test_match_modes() {
+		let with_mode = |requested: &[&str], mode| {
+			StateMatcher::with_mode(
+				&requested.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+				mode,
+				&[],
+			)
+		};
+		let matcher = with_mode(&["walk", "!walk_old"], MatchMode::Contains).unwrap();
+		assert!(matcher.matches("walk"));
+		assert!(matcher.matches("slow walk"));
+		assert!(!matcher.matches("walk_old"));
+		assert!(!matcher.matches("run"));
+
+		let matcher =
+			with_mode(&["walk_*", "idle?", "[rR]un", "!*_[!0-9]"], MatchMode::Glob).unwrap();
+		assert!(matcher.matches("walk_1"));
+		assert!(!matcher.matches("walk_x"));
+		assert!(!matcher.matches("slow walk_1"));
+		assert!(matcher.matches("idle2"));
+		assert!(!matcher.matches("idle"));
+		assert!(matcher.matches("Run"));
+		assert!(matcher.matches("run"));
+		assert!(!matcher.matches("ruN"));
+
+		let matcher = with_mode(&["^walk_\\d+$", "!_2"], MatchMode::Regex).unwrap();
+		assert!(matcher.matches("walk_1"));
+		assert!(!matcher.matches("walk_2"));
+		assert!(!matcher.matches("walk_"));
+		assert!(with_mode(&["("], MatchMode::Regex).is_err());
+		assert!(with_mode(&["("], MatchMode::Glob).is_ok());
+	}
+
+	#[test]
+	fn test_name_normalization() {
+		use NameNormalization::*;
+		assert_eq!(normalize_name(" Walk North ", &[Trim]), "Walk North");
+		assert_eq!(
+			normalize_name("Walk North", &[Lower, Underscore]),
+			"walk_north"
+		);
+		assert_eq!(
+			normalize_name("walk - north__2", &[Underscore]),
+			"walk_north_2"
+		);
+		assert_eq!(normalize_name(" Walk ", &[]), " Walk ");
+
+		let requested = ["Walk North".to_string(), "!walk_north_old".to_string()];
+		let matcher =
+			StateMatcher::with_mode(&requested, MatchMode::Exact, &[Lower, Underscore]).unwrap();
+		assert!(matcher.matches("walk_north"));
+		assert!(matcher.matches("WALK-NORTH"));
+		assert!(!matcher.matches("Walk North Old"));
+		assert_eq!(matcher.inclusions()[0].text(), "Walk North");
+	}
+
+	#[test]
+	fn test_only_exclusions_match_everything_else() {
+		let matcher = matcher(&["!walk"]);
+		assert!(!matcher.matches("walk"));
+		assert!(matcher.matches("run"));
+	}
+
+	#[test]
+	fn test_structure_filter() {
+		let state = |dirs, frames| IconState {
+			dirs,
+			frames,
+			..Default::default()
+		};
+		assert!(StructureFilter::default().matches(&state(1, 1)));
+
+		let filter = StructureFilter {
+			frames_gt: Some(2),
+			dirs: Some(4),
+			..Default::default()
+		};
+		assert!(filter.matches(&state(4, 3)));
+		assert!(!filter.matches(&state(4, 2)));
+		assert!(!filter.matches(&state(1, 8)));
+
+		let filter = StructureFilter {
+			frames_lt: Some(4),
+			frames: Some(8),
+			..Default::default()
+		};
+		assert!(!filter.matches(&state(1, 8)));
+	}
+
+	#[test]
+	fn test_merge_sources() {
+		let state = |name: &str, frames| IconState {
+			name: name.to_string(),
+			frames,
+			..Default::default()
+		};
+		let icon = |states| Icon {
+			states,
+			..Default::default()
+		};
+		let sources = [
+			icon(vec![state("walk", 1), state("idle", 1)]),
+			icon(vec![state("run", 2), state("walk", 2)]),
+		];
+		let summary = |states: Vec<IconState>| {
+			states
+				.into_iter()
+				.map(|state| (state.name, state.frames))
+				.collect::<Vec<_>>()
+		};
+
+		let (states, shadowed) = merge_sources(&sources, SourcePriority::Last);
+		assert_eq!(summary(states), [
+			("walk".to_string(), 2),
+			("idle".to_string(), 1),
+			("run".to_string(), 2)
+		]);
+		assert_eq!(shadowed, [Shadowed {
+			name: "walk".to_string(),
+			source: 0,
+			winner: 1
+		}]);
+
+		let (states, shadowed) = merge_sources(&sources, SourcePriority::First);
+		assert_eq!(summary(states)[0], ("walk".to_string(), 1));
+		assert_eq!(shadowed, [Shadowed {
+			name: "walk".to_string(),
+			source: 1,
+			winner: 0
+		}]);
+	}
+}
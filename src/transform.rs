@@ -0,0 +1,689 @@
+// SPDX-License-Identifier: MPL-2.0
+use crate::direction::Direction;
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use dmi::icon::IconState;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+use std::{
+	io::{self, Cursor, Write},
+	process::{Command, Stdio},
+};
+
+/// Pipe every frame of an icon state through an external command.
+///
+/// The protocol is intentionally simple: the command is run once per image,
+/// receiving the image as a PNG on stdin, and must write the transformed image
+/// as a PNG of the same dimensions to stdout. The state name, frame index, and
+/// direction index are exposed to the command through the `DMI_COPY_STATE`,
+/// `DMI_COPY_FRAME`, and `DMI_COPY_DIR` environment variables.
+pub fn external_command(state: &mut IconState, cmd: &str) -> Result<()> {
+	let dirs = state.dirs.max(1) as usize;
+	for (index, image) in state.images.iter_mut().enumerate() {
+		let (frame, dir) = (index / dirs, index % dirs);
+		*image = run_command(cmd, image, &state.name, frame, dir)
+			.wrap_err_with(|| format!("failed to transform frame {frame}, dir {dir}"))?;
+	}
+	Ok(())
+}
+
+/// Build a copy of `existing` where only the frames facing `dir` (and the
+/// delays, which every direction shares) are taken from `new`.
+///
+/// Both states must have the same number of directions and frames.
+pub fn splice_direction(
+	existing: &IconState,
+	new: &IconState,
+	dir: Direction,
+) -> Result<IconState> {
+	if (existing.dirs, existing.frames) != (new.dirs, new.frames) {
+		return Err(eyre!(
+			"source has {} dir(s) and {} frame(s), but the target has {} dir(s) and {} frame(s)",
+			new.dirs,
+			new.frames,
+			existing.dirs,
+			existing.frames
+		));
+	}
+	let dirs = existing.dirs as usize;
+	if dir.index() >= dirs {
+		return Err(eyre!("a state with {dirs} dir(s) has no {dir} direction"));
+	}
+	if new.images.len() != existing.images.len() {
+		return Err(eyre!("source and target have a different number of images"));
+	}
+
+	let mut spliced = existing.clone();
+	for index in (dir.index()..spliced.images.len()).step_by(dirs) {
+		spliced.images[index] = new.images[index].clone();
+	}
+	spliced.delay.clone_from(&new.delay);
+	Ok(spliced)
+}
+
+/// Collapse runs of consecutive pixel-identical frames into a single frame,
+/// merging their delays so playback is unchanged.
+///
+/// A DMI has no way to reference the same image from several frames, so
+/// only adjacent duplicates can be merged. Returns the number of frames that
+/// were removed.
+pub fn dedupe_frames(state: &mut IconState) -> usize {
+	let dirs = state.dirs.max(1) as usize;
+	let frames = state.frames as usize;
+	if frames < 2 || state.images.len() != frames * dirs {
+		return 0;
+	}
+	let old_delays = state.delay.clone().unwrap_or_else(|| vec![1.0; frames]);
+
+	let mut images = Vec::with_capacity(state.images.len());
+	let mut delays: Vec<f32> = Vec::with_capacity(frames);
+	for (frame, frame_images) in state.images.chunks(dirs).enumerate() {
+		let delay = old_delays.get(frame).copied().unwrap_or(1.0);
+		if images.len() >= dirs && images[images.len() - dirs..] == *frame_images {
+			*delays.last_mut().expect("a frame was already kept") += delay;
+		} else {
+			images.extend_from_slice(frame_images);
+			delays.push(delay);
+		}
+	}
+
+	let removed = frames - delays.len();
+	if removed > 0 {
+		state.frames = delays.len() as u32;
+		state.images = images;
+		state.delay = if delays.len() > 1 { Some(delays) } else { None };
+	}
+	removed
+}
+
+/// Round each of a state's delays to the nearest multiple of `base` ticks,
+/// never rounding a delay down to nothing.
+///
+/// Returns whether any delay changed.
+pub fn normalize_delays(state: &mut IconState, base: f32) -> bool {
+	let Some(delays) = &mut state.delay else {
+		return false;
+	};
+	let mut changed = false;
+	for delay in delays {
+		let normalized = ((*delay / base).round() * base).max(base);
+		if normalized != *delay {
+			*delay = normalized;
+			changed = true;
+		}
+	}
+	changed
+}
+
+/// Give every frame of an animated state the same delay, in ticks. Static
+/// states are left alone, since delays have no effect on them.
+///
+/// Returns whether any delay changed.
+pub fn set_delay(state: &mut IconState, delay: f32) -> bool {
+	if state.frames <= 1 {
+		return false;
+	}
+	let delays = vec![delay; state.frames as usize];
+	let changed = state.delay.as_ref() != Some(&delays);
+	state.delay = Some(delays);
+	changed
+}
+
+/// Cut a state down to at most `max` frames, either by keeping the first
+/// `max` frames and dropping the rest along with their delays, or if `sample`
+/// is set, by keeping every Nth frame and folding the dropped frames' delays
+/// into the kept ones, so the animation takes just as long.
+///
+/// Returns whether any frames were removed.
+pub fn limit_frames(state: &mut IconState, max: u32, sample: bool) -> bool {
+	let dirs = state.dirs.max(1) as usize;
+	let frames = state.frames as usize;
+	let max = max.max(1) as usize;
+	if frames <= max || state.images.len() != frames * dirs {
+		return false;
+	}
+	let old_delays = state.delay.clone().unwrap_or_else(|| vec![1.0; frames]);
+
+	let step = if sample { frames.div_ceil(max) } else { 1 };
+	let mut images = Vec::with_capacity(max * dirs);
+	let mut delays = Vec::with_capacity(max);
+	// Sampling steps through every frame, while truncating stops at `max`
+	for (frame, frame_images) in state.images.chunks(dirs).enumerate().take(max * step) {
+		let delay = old_delays.get(frame).copied().unwrap_or(1.0);
+		if frame % step == 0 {
+			images.extend_from_slice(frame_images);
+			delays.push(delay);
+		} else {
+			*delays.last_mut().expect("the first frame is always kept") += delay;
+		}
+	}
+	state.frames = delays.len() as u32;
+	state.images = images;
+	state.delay = if delays.len() > 1 { Some(delays) } else { None };
+	true
+}
+
+/// Extend a state to `frames` frames by repeating its last frame, along with
+/// that frame's delay.
+///
+/// Fails if the state already has more frames than that.
+pub fn pad_frames(state: &mut IconState, frames: u32) -> Result<()> {
+	if state.frames > frames {
+		return Err(eyre!(
+			"can't pad {} frame(s) down to {frames}",
+			state.frames
+		));
+	}
+	let dirs = state.dirs.max(1) as usize;
+	let old_frames = state.frames as usize;
+	if old_frames == frames as usize || state.images.len() != old_frames * dirs {
+		return Ok(());
+	}
+
+	let last_frame = state.images[state.images.len() - dirs..].to_vec();
+	for _ in old_frames..frames as usize {
+		state.images.extend_from_slice(&last_frame);
+	}
+	let mut delays = state.delay.take().unwrap_or_default();
+	delays.resize(old_frames, 1.0);
+	let last_delay = delays.last().copied().unwrap_or(1.0);
+	delays.resize(frames as usize, last_delay);
+	state.delay = Some(delays);
+	state.frames = frames;
+	Ok(())
+}
+
+/// Give a replacement state the delays, loop flag, and rewind flag of the
+/// state it replaces, keeping only its own images.
+///
+/// Fails if the replaced state has a different number of frames, since its
+/// delays wouldn't fit, or would be missing for an animated replacement.
+pub fn keep_animation_metadata(state: &mut IconState, target: &IconState) -> Result<()> {
+	if state.frames != target.frames {
+		return Err(eyre!(
+			"can't keep the target's animation for {} frame(s) with {} frame(s) from the source",
+			target.frames,
+			state.frames
+		));
+	}
+	state.delay.clone_from(&target.delay);
+	state.loop_flag = target.loop_flag;
+	state.rewind = target.rewind;
+	Ok(())
+}
+
+/// Reshape a state to have `dirs` directions.
+///
+/// Expanding a single direction copies it to every direction. Expanding four
+/// directions to eight fills the diagonals the way BYOND draws them for
+/// four-direction states, from east and west. Collapsing eight directions to
+/// four keeps the cardinal directions, and collapsing to one keeps `keep`.
+pub fn set_dirs(state: &mut IconState, dirs: u8, keep: Direction) -> Result<()> {
+	let old_dirs = state.dirs.max(1);
+	if old_dirs == dirs {
+		return Ok(());
+	}
+	let sources: Vec<usize> = match (old_dirs, dirs) {
+		(_, 1) if keep.index() >= old_dirs as usize => {
+			return Err(eyre!(
+				"a state with {old_dirs} dir(s) has no {keep} direction to keep"
+			));
+		}
+		(_, 1) => vec![keep.index()],
+		(1, _) => vec![0; dirs as usize],
+		(4, 8) => [
+			Direction::South,
+			Direction::North,
+			Direction::East,
+			Direction::West,
+			Direction::East,
+			Direction::West,
+			Direction::East,
+			Direction::West,
+		]
+		.iter()
+		.map(|dir| dir.index())
+		.collect(),
+		(8, 4) => (0..4).collect(),
+		_ => return Err(eyre!("can't reshape {old_dirs} dir(s) into {dirs}")),
+	};
+	if state.images.len() != state.frames as usize * old_dirs as usize {
+		return Err(eyre!(
+			"state has the wrong number of images for its dirs and frames"
+		));
+	}
+
+	state.images = state
+		.images
+		.chunks(old_dirs as usize)
+		.flat_map(|frame| sources.iter().map(|&dir| frame[dir].clone()))
+		.collect();
+	state.dirs = dirs;
+	Ok(())
+}
+
+/// What fills the pixels uncovered by shifting a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OffsetMode {
+	/// Pixels shifted off one edge come back in on the other
+	Wrap,
+	/// The edge pixels are stretched to fill the gap
+	Clamp,
+	/// The gap is left transparent
+	#[default]
+	Transparent,
+}
+
+/// Shift every frame of a state by `dx` pixels right and `dy` pixels down,
+/// keeping their dimensions
+pub fn offset_frames(state: &mut IconState, dx: i32, dy: i32, mode: OffsetMode) {
+	for image in &mut state.images {
+		*image = DynamicImage::ImageRgba8(offset_image(image, dx, dy, mode));
+	}
+}
+
+fn offset_image(image: &DynamicImage, dx: i32, dy: i32, mode: OffsetMode) -> RgbaImage {
+	let (width, height) = image.dimensions();
+	RgbaImage::from_fn(width, height, |x, y| {
+		let source_x = x as i64 - dx as i64;
+		let source_y = y as i64 - dy as i64;
+		let (width, height) = (width as i64, height as i64);
+		let source = match mode {
+			OffsetMode::Wrap => Some((source_x.rem_euclid(width), source_y.rem_euclid(height))),
+			OffsetMode::Clamp => {
+				Some((source_x.clamp(0, width - 1), source_y.clamp(0, height - 1)))
+			}
+			OffsetMode::Transparent => ((0..width).contains(&source_x)
+				&& (0..height).contains(&source_y))
+			.then_some((source_x, source_y)),
+		};
+		match source {
+			Some((x, y)) => image.get_pixel(x as u32, y as u32),
+			None => Rgba([0, 0, 0, 0]),
+		}
+	})
+}
+
+/// Make every pixel with an alpha below `threshold` fully transparent,
+/// returning how many pixels were changed. Frames without any such pixels
+/// are left as they are.
+pub fn clear_faint_pixels(state: &mut IconState, threshold: u8) -> usize {
+	let mut cleared = 0;
+	for image in &mut state.images {
+		let faint = |pixel: &Rgba<u8>| pixel[3] < threshold && pixel.0 != [0; 4];
+		let mut rgba = image.to_rgba8();
+		let count = rgba.pixels().filter(|pixel| faint(pixel)).count();
+		if count == 0 {
+			continue;
+		}
+		for pixel in rgba.pixels_mut() {
+			if faint(pixel) {
+				*pixel = Rgba([0, 0, 0, 0]);
+			}
+		}
+		*image = DynamicImage::ImageRgba8(rgba);
+		cleared += count;
+	}
+	cleared
+}
+
+/// Make every pixel whose color is within `tolerance` of `key` in each
+/// channel fully transparent, returning how many pixels were changed. Frames
+/// without any such pixels are left as they are.
+pub fn mask_color(state: &mut IconState, key: [u8; 3], tolerance: u8) -> usize {
+	let mut masked = 0;
+	for image in &mut state.images {
+		let matches = |pixel: &Rgba<u8>| {
+			pixel[3] != 0
+				&& pixel.0[..3]
+					.iter()
+					.zip(key)
+					.all(|(&channel, key)| channel.abs_diff(key) <= tolerance)
+		};
+		let mut rgba = image.to_rgba8();
+		let count = rgba.pixels().filter(|pixel| matches(pixel)).count();
+		if count == 0 {
+			continue;
+		}
+		for pixel in rgba.pixels_mut() {
+			if matches(pixel) {
+				*pixel = Rgba([0, 0, 0, 0]);
+			}
+		}
+		*image = DynamicImage::ImageRgba8(rgba);
+		masked += count;
+	}
+	masked
+}
+
+fn run_command(
+	cmd: &str,
+	image: &DynamicImage,
+	state: &str,
+	frame: usize,
+	dir: usize,
+) -> Result<DynamicImage> {
+	let mut input = Cursor::new(Vec::new());
+	image
+		.write_to(&mut input, ImageFormat::Png)
+		.wrap_err("failed to encode frame as png")?;
+	let input = input.into_inner();
+
+	let (shell, flag) = if cfg!(windows) {
+		("cmd", "/C")
+	} else {
+		("sh", "-c")
+	};
+	let mut child = Command::new(shell)
+		.arg(flag)
+		.arg(cmd)
+		.env("DMI_COPY_STATE", state)
+		.env("DMI_COPY_FRAME", frame.to_string())
+		.env("DMI_COPY_DIR", dir.to_string())
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.wrap_err_with(|| format!("failed to run transform command '{cmd}'"))?;
+
+	// Feed stdin from another thread, so a command that starts writing its
+	// output before consuming all of its input can't deadlock us.
+	let mut stdin = child.stdin.take().expect("stdin should be piped");
+	let (output, written) = std::thread::scope(|scope| {
+		let writer = scope.spawn(move || stdin.write_all(&input));
+		let output = child.wait_with_output();
+		(output, writer.join().expect("stdin writer shouldn't panic"))
+	});
+	let output = output.wrap_err("failed to wait for transform command")?;
+	match written {
+		// A command that exits without reading its input is its own business,
+		// so a broken pipe here is not an error.
+		Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+			return Err(err).wrap_err("failed to write the frame to transform command");
+		}
+		_ => {}
+	}
+
+	if !output.status.success() {
+		return Err(eyre!("transform command exited with {}", output.status));
+	}
+
+	let transformed = image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)
+		.wrap_err("transform command did not output a valid png")?;
+	if transformed.width() != image.width() || transformed.height() != image.height() {
+		return Err(eyre!(
+			"transform command changed the frame size from {}x{} to {}x{}",
+			image.width(),
+			image.height(),
+			transformed.width(),
+			transformed.height()
+		));
+	}
+	Ok(transformed)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use dmi::icon::Looping;
+	use image::{Rgba, RgbaImage};
+
+	fn frame(value: u8) -> DynamicImage {
+		DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([value, 0, 0, 255])))
+	}
+
+	fn animated_state(frames: &[u8], delays: &[f32]) -> IconState {
+		IconState {
+			name: "test".to_string(),
+			frames: frames.len() as u32,
+			images: frames.iter().copied().map(frame).collect(),
+			delay: Some(delays.to_vec()),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_keep_animation_metadata() {
+		let mut target = animated_state(&[1, 2], &[3.0, 4.0]);
+		target.rewind = true;
+		target.loop_flag = Looping::new(2);
+		let mut state = animated_state(&[5, 6], &[1.0, 1.0]);
+		keep_animation_metadata(&mut state, &target).unwrap();
+		assert_eq!(state.delay, target.delay);
+		assert_eq!((state.loop_flag, state.rewind), (target.loop_flag, true));
+		assert_eq!(state.images, animated_state(&[5, 6], &[]).images);
+
+		let mut longer = animated_state(&[5, 6, 7], &[1.0; 3]);
+		assert!(keep_animation_metadata(&mut longer, &target).is_err());
+
+		let still = IconState {
+			delay: None,
+			..animated_state(&[1], &[])
+		};
+		let mut animated = animated_state(&[5, 6], &[1.0, 2.0]);
+		assert!(keep_animation_metadata(&mut animated, &still).is_err());
+		assert_eq!(animated.delay, Some(vec![1.0, 2.0]));
+	}
+
+	#[test]
+	fn test_splice_direction() {
+		let existing = IconState {
+			dirs: 4,
+			frames: 2,
+			images: (0..8).map(frame).collect(),
+			delay: Some(vec![1.0, 1.0]),
+			..Default::default()
+		};
+		let new = IconState {
+			images: (10..18).map(frame).collect(),
+			delay: Some(vec![2.0, 3.0]),
+			..existing.clone()
+		};
+		let spliced = splice_direction(&existing, &new, Direction::North).unwrap();
+		let expected = [0, 11, 2, 3, 4, 15, 6, 7].map(frame);
+		assert_eq!(spliced.images, expected);
+		assert_eq!(spliced.delay, Some(vec![2.0, 3.0]));
+
+		let single = IconState {
+			images: vec![frame(0)],
+			..Default::default()
+		};
+		assert!(splice_direction(&existing, &single, Direction::South).is_err());
+		assert!(splice_direction(&single, &single, Direction::East).is_err());
+	}
+
+	#[test]
+	fn test_dedupe_frames_merges_delays() {
+		let mut state = animated_state(&[1, 1, 2, 1, 1, 1], &[1.0, 2.0, 1.0, 1.0, 1.0, 3.0]);
+		assert_eq!(dedupe_frames(&mut state), 3);
+		assert_eq!(state.frames, 3);
+		assert_eq!(state.images, vec![frame(1), frame(2), frame(1)]);
+		assert_eq!(state.delay, Some(vec![3.0, 1.0, 5.0]));
+	}
+
+	#[test]
+	fn test_dedupe_frames_collapses_to_static() {
+		let mut state = animated_state(&[4, 4], &[1.0, 1.0]);
+		assert_eq!(dedupe_frames(&mut state), 1);
+		assert_eq!(state.frames, 1);
+		assert_eq!(state.delay, None);
+	}
+
+	#[test]
+	fn test_dedupe_frames_compares_every_dir() {
+		let mut state = IconState {
+			dirs: 2,
+			frames: 2,
+			images: vec![frame(1), frame(2), frame(1), frame(3)],
+			delay: Some(vec![1.0, 1.0]),
+			..Default::default()
+		};
+		assert_eq!(dedupe_frames(&mut state), 0);
+		assert_eq!(state.frames, 2);
+	}
+
+	#[test]
+	fn test_pad_frames() {
+		let mut state = animated_state(&[1, 2], &[1.0, 3.0]);
+		pad_frames(&mut state, 4).unwrap();
+		assert_eq!(state.frames, 4);
+		assert_eq!(state.images, vec![frame(1), frame(2), frame(2), frame(2)]);
+		assert_eq!(state.delay, Some(vec![1.0, 3.0, 3.0, 3.0]));
+
+		let mut state = IconState {
+			frames: 1,
+			images: vec![frame(5)],
+			..Default::default()
+		};
+		pad_frames(&mut state, 2).unwrap();
+		assert_eq!(state.images, vec![frame(5), frame(5)]);
+		assert_eq!(state.delay, Some(vec![1.0, 1.0]));
+
+		assert!(pad_frames(&mut state, 1).is_err());
+	}
+
+	#[test]
+	fn test_set_dirs() {
+		let state = |dirs: u8, frames: u32| IconState {
+			dirs,
+			frames,
+			images: (0..dirs as u32 * frames)
+				.map(|value| frame(value as u8))
+				.collect(),
+			..Default::default()
+		};
+
+		let mut expanded = state(1, 2);
+		set_dirs(&mut expanded, 4, Direction::South).unwrap();
+		assert_eq!(expanded.dirs, 4);
+		assert_eq!(expanded.images, [0, 0, 0, 0, 1, 1, 1, 1].map(frame));
+
+		let mut diagonals = state(4, 1);
+		set_dirs(&mut diagonals, 8, Direction::South).unwrap();
+		assert_eq!(diagonals.images, [0, 1, 2, 3, 2, 3, 2, 3].map(frame));
+
+		let mut cardinals = state(8, 1);
+		set_dirs(&mut cardinals, 4, Direction::South).unwrap();
+		assert_eq!(cardinals.images, [0, 1, 2, 3].map(frame));
+
+		let mut collapsed = state(4, 2);
+		set_dirs(&mut collapsed, 1, Direction::East).unwrap();
+		assert_eq!(collapsed.dirs, 1);
+		assert_eq!(collapsed.images, [2, 6].map(frame));
+
+		assert!(set_dirs(&mut state(4, 1), 1, Direction::Northeast).is_err());
+	}
+
+	#[test]
+	fn test_normalize_delays() {
+		let mut state = animated_state(&[1, 2, 3], &[1.4, 0.2, 2.0]);
+		assert!(normalize_delays(&mut state, 1.0));
+		assert_eq!(state.delay, Some(vec![1.0, 1.0, 2.0]));
+		assert!(!normalize_delays(&mut state, 1.0));
+
+		let mut state = animated_state(&[1, 2], &[0.7, 1.2]);
+		assert!(normalize_delays(&mut state, 0.5));
+		assert_eq!(state.delay, Some(vec![0.5, 1.0]));
+	}
+
+	#[test]
+	fn test_offset_frames() {
+		let image = RgbaImage::from_fn(3, 1, |x, _| Rgba([x as u8 + 1, 0, 0, 255]));
+		let shifted = |dx, mode| {
+			let mut state = IconState {
+				images: vec![DynamicImage::ImageRgba8(image.clone())],
+				..Default::default()
+			};
+			offset_frames(&mut state, dx, 0, mode);
+			state.images[0]
+				.to_rgba8()
+				.pixels()
+				.map(|pixel| pixel[0])
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(shifted(1, OffsetMode::Wrap), [3, 1, 2]);
+		assert_eq!(shifted(-1, OffsetMode::Wrap), [2, 3, 1]);
+		assert_eq!(shifted(1, OffsetMode::Clamp), [1, 1, 2]);
+		assert_eq!(shifted(-2, OffsetMode::Clamp), [3, 3, 3]);
+		assert_eq!(shifted(1, OffsetMode::Transparent), [0, 1, 2]);
+		assert_eq!(shifted(5, OffsetMode::Transparent), [0, 0, 0]);
+		assert_eq!(shifted(0, OffsetMode::Transparent), [1, 2, 3]);
+	}
+
+	#[test]
+	fn test_clear_faint_pixels() {
+		let image = RgbaImage::from_fn(4, 1, |x, _| Rgba([9, 9, 9, [255, 10, 3, 0][x as usize]]));
+		let mut state = IconState {
+			images: vec![DynamicImage::ImageRgba8(image)],
+			..Default::default()
+		};
+		assert_eq!(clear_faint_pixels(&mut state, 11), 3);
+		let pixels = state.images[0]
+			.to_rgba8()
+			.pixels()
+			.map(|pixel| pixel.0)
+			.collect::<Vec<_>>();
+		assert_eq!(pixels, [[9, 9, 9, 255], [0; 4], [0; 4], [0; 4]]);
+		assert_eq!(clear_faint_pixels(&mut state, 11), 0);
+	}
+
+	#[test]
+	fn test_mask_color() {
+		let colors = [[255, 0, 255], [250, 4, 255], [240, 0, 255], [0, 0, 0]];
+		let image = RgbaImage::from_fn(4, 1, |x, _| {
+			let [r, g, b] = colors[x as usize];
+			Rgba([r, g, b, 255])
+		});
+		let mut state = IconState {
+			images: vec![DynamicImage::ImageRgba8(image)],
+			..Default::default()
+		};
+		assert_eq!(mask_color(&mut state, [255, 0, 255], 5), 2);
+		let pixels = state.images[0]
+			.to_rgba8()
+			.pixels()
+			.map(|pixel| pixel.0)
+			.collect::<Vec<_>>();
+		assert_eq!(pixels, [[0; 4], [0; 4], [240, 0, 255, 255], [0, 0, 0, 255]]);
+		assert_eq!(mask_color(&mut state, [255, 0, 255], 5), 0);
+	}
+
+	#[test]
+	fn test_set_delay() {
+		let mut state = animated_state(&[1, 2, 3], &[1.0, 2.0, 3.0]);
+		assert!(set_delay(&mut state, 2.0));
+		assert_eq!(state.delay, Some(vec![2.0; 3]));
+		assert!(!set_delay(&mut state, 2.0));
+
+		let mut state = animated_state(&[1, 2], &[]);
+		state.delay = None;
+		assert!(set_delay(&mut state, 0.5));
+		assert_eq!(state.delay, Some(vec![0.5; 2]));
+
+		let mut state = animated_state(&[1], &[]);
+		state.delay = None;
+		assert!(!set_delay(&mut state, 2.0));
+		assert_eq!(state.delay, None);
+	}
+
+	#[test]
+	fn test_limit_frames_truncates() {
+		let mut state = animated_state(&[1, 2, 3, 4, 5], &[1.0, 2.0, 3.0, 4.0, 5.0]);
+		assert!(limit_frames(&mut state, 3, false));
+		assert_eq!(state.frames, 3);
+		assert_eq!(state.images, vec![frame(1), frame(2), frame(3)]);
+		assert_eq!(state.delay, Some(vec![1.0, 2.0, 3.0]));
+		assert!(!limit_frames(&mut state, 3, false));
+	}
+
+	#[test]
+	fn test_limit_frames_samples() {
+		let mut state = animated_state(&[1, 2, 3, 4, 5], &[1.0; 5]);
+		assert!(limit_frames(&mut state, 2, true));
+		assert_eq!(state.images, vec![frame(1), frame(4)]);
+		assert_eq!(state.delay, Some(vec![3.0, 2.0]));
+
+		let mut state = animated_state(&[1, 2, 3, 4, 5, 6, 7], &[1.0; 7]);
+		assert!(limit_frames(&mut state, 3, true));
+		assert_eq!(state.images, vec![frame(1), frame(4), frame(7)]);
+		assert_eq!(state.delay, Some(vec![3.0, 3.0, 1.0]));
+	}
+}
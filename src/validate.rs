@@ -0,0 +1,207 @@
+// SPDX-License-Identifier: MPL-2.0
+use color_eyre::eyre::{eyre, Result};
+use dmi::icon::{Icon, IconState};
+use std::{collections::BTreeMap, path::Path};
+
+/// List the ways a state breaks the invariants a DMI needs to be saved and
+/// read back correctly
+pub fn state_problems(state: &IconState, width: u32, height: u32) -> Vec<String> {
+	let mut problems = Vec::new();
+	if ![1, 4, 8].contains(&state.dirs) {
+		problems.push(format!("has {} dirs, not 1, 4, or 8", state.dirs));
+	}
+	if state.frames == 0 {
+		problems.push("has no frames".to_string());
+	}
+	let expected_images = state.dirs as usize * state.frames as usize;
+	if state.images.len() != expected_images {
+		problems.push(format!(
+			"has {} image(s), but {} dir(s) and {} frame(s) need {expected_images}",
+			state.images.len(),
+			state.dirs,
+			state.frames
+		));
+	}
+	if let Some(image) = state
+		.images
+		.iter()
+		.find(|image| (image.width(), image.height()) != (width, height))
+	{
+		problems.push(format!(
+			"has a {}x{} image in a file of {width}x{height} icons",
+			image.width(),
+			image.height()
+		));
+	}
+	match &state.delay {
+		Some(delay) if delay.len() != state.frames as usize => problems.push(format!(
+			"has {} delay(s) for {} frame(s)",
+			delay.len(),
+			state.frames
+		)),
+		// An animation needs its delays, or the dmi crate refuses to save it.
+		None if state.frames > 1 => {
+			problems.push(format!("has no delays for {} frame(s)", state.frames));
+		}
+		_ => {}
+	}
+	problems
+}
+
+/// Check every state of an icon, failing with the first invalid state's name
+/// and problems
+pub fn check(icon: &Icon) -> Result<()> {
+	for state in &icon.states {
+		let problems = state_problems(state, icon.width, icon.height);
+		if !problems.is_empty() {
+			return Err(eyre!("state '{}' {}", state.name, problems.join(", and ")));
+		}
+	}
+	Ok(())
+}
+
+/// Check that replacing `old` with `new` doesn't change the size of the
+/// state's images
+pub fn check_same_size(old: &IconState, new: &IconState) -> Result<()> {
+	let size = |state: &IconState| {
+		state
+			.images
+			.first()
+			.map(|image| (image.width(), image.height()))
+	};
+	match (size(old), size(new)) {
+		(Some(old_size), Some(new_size)) if old_size != new_size => Err(eyre!(
+			"replacing state '{}' would change its size from {}x{} to {}x{}",
+			old.name,
+			old_size.0,
+			old_size.1,
+			new_size.0,
+			new_size.1
+		)),
+		_ => Ok(()),
+	}
+}
+
+/// Check that every file has icons of the same size, `expected` if it's
+/// given, failing with the files grouped by their size otherwise
+pub fn check_dimensions<'a>(
+	files: impl IntoIterator<Item = (&'a Path, &'a Icon)>,
+	expected: Option<(u32, u32)>,
+) -> Result<()> {
+	let mut groups = BTreeMap::<(u32, u32), Vec<&Path>>::new();
+	for (path, icon) in files {
+		groups
+			.entry((icon.width, icon.height))
+			.or_default()
+			.push(path);
+	}
+	let matching = match expected {
+		Some(size) => groups.keys().all(|&key| key == size),
+		None => groups.len() <= 1,
+	};
+	if matching {
+		return Ok(());
+	}
+	let listing = groups
+		.iter()
+		.map(|((width, height), paths)| {
+			let paths = paths
+				.iter()
+				.map(|path| path.display().to_string())
+				.collect::<Vec<_>>();
+			format!("  {width}x{height}: {}", paths.join(", "))
+		})
+		.collect::<Vec<_>>()
+		.join("\n");
+	Err(match expected {
+		Some((width, height)) => eyre!("not every file has {width}x{height} icons:\n{listing}"),
+		None => eyre!("not every file has icons of the same size:\n{listing}"),
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::{DynamicImage, RgbaImage};
+
+	fn state(dirs: u8, frames: u32, images: usize) -> IconState {
+		IconState {
+			name: "test".to_string(),
+			dirs,
+			frames,
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::new(32, 32)); images],
+			delay: (frames > 1).then(|| vec![1.0; frames as usize]),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_state_problems() {
+		assert!(state_problems(&state(4, 2, 8), 32, 32).is_empty());
+		assert_eq!(state_problems(&state(4, 2, 7), 32, 32).len(), 1);
+		assert_eq!(state_problems(&state(3, 1, 3), 32, 32).len(), 1);
+		assert_eq!(state_problems(&state(1, 1, 1), 16, 16).len(), 1);
+
+		let mut delays = state(1, 2, 2);
+		delays.delay = Some(vec![1.0]);
+		assert_eq!(state_problems(&delays, 32, 32), [
+			"has 1 delay(s) for 2 frame(s)"
+		]);
+		delays.delay = None;
+		assert_eq!(state_problems(&delays, 32, 32), [
+			"has no delays for 2 frame(s)"
+		]);
+	}
+
+	#[test]
+	fn test_check_names_the_state() {
+		let icon = Icon {
+			width: 32,
+			height: 32,
+			states: vec![state(1, 1, 1), IconState {
+				name: "broken".to_string(),
+				..state(1, 2, 1)
+			}],
+			..Default::default()
+		};
+		let err = check(&icon).unwrap_err().to_string();
+		assert!(err.starts_with("state 'broken' has 1 image(s)"), "{err}");
+	}
+
+	#[test]
+	fn test_check_same_size() {
+		let small = state(1, 1, 1);
+		let large = IconState {
+			images: vec![DynamicImage::ImageRgba8(RgbaImage::new(64, 64))],
+			..state(1, 1, 0)
+		};
+		assert!(check_same_size(&small, &state(4, 2, 8)).is_ok());
+		assert_eq!(
+			check_same_size(&small, &large).unwrap_err().to_string(),
+			"replacing state 'test' would change its size from 32x32 to 64x64"
+		);
+	}
+
+	#[test]
+	fn test_check_dimensions() {
+		let icon = |width, height| Icon {
+			width,
+			height,
+			..Default::default()
+		};
+		let (small, large) = (icon(32, 32), icon(64, 64));
+		let files = [
+			(Path::new("a.dmi"), &small),
+			(Path::new("b.dmi"), &large),
+			(Path::new("c.dmi"), &small),
+		];
+		assert!(check_dimensions(files[..1].iter().copied(), None).is_ok());
+		assert!(check_dimensions(files[..1].iter().copied(), Some((32, 32))).is_ok());
+		assert!(check_dimensions(files[..1].iter().copied(), Some((64, 64))).is_err());
+		let err = check_dimensions(files, None).unwrap_err().to_string();
+		assert_eq!(
+			err,
+			"not every file has icons of the same size:\n  32x32: a.dmi, c.dmi\n  64x64: b.dmi"
+		);
+	}
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MPL-2.0
+//! Helpers shared by the integration tests
+// Each test file only uses some of them.
+#![allow(dead_code)]
+
+use dmi::icon::{Icon, IconState};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::{
+	fs,
+	path::Path,
+	process::{Command, Output},
+};
+
+/// A 32x32 state with a frame of each shade of red
+pub fn state(name: &str, shades: &[u8]) -> IconState {
+	IconState {
+		name: name.to_string(),
+		frames: shades.len() as u32,
+		images: shades
+			.iter()
+			.map(|&shade| {
+				DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([shade, 0, 0, 255])))
+			})
+			.collect(),
+		delay: (shades.len() > 1).then(|| vec![1.0; shades.len()]),
+		..Default::default()
+	}
+}
+
+/// Save a 32x32 icon with these states
+pub fn save(path: &Path, states: Vec<IconState>) {
+	let icon = Icon {
+		width: 32,
+		height: 32,
+		states,
+		..Default::default()
+	};
+	icon.save(&mut fs::File::create(path).unwrap()).unwrap();
+}
+
+pub fn load(path: &Path) -> Icon {
+	Icon::load(fs::File::open(path).unwrap()).unwrap()
+}
+
+/// Run dmi-copy with these arguments
+pub fn dmi_copy(args: &[&str]) -> Output {
+	Command::new(env!("CARGO_BIN_EXE_dmi-copy"))
+		.args(args)
+		.output()
+		.unwrap()
+}
+
+/// Run dmi-copy with these arguments, failing the test unless it succeeds
+pub fn dmi_copy_ok(args: &[&str]) -> Output {
+	let output = dmi_copy(args);
+	assert!(
+		output.status.success(),
+		"dmi-copy {args:?} failed: {}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	output
+}
@@ -0,0 +1,376 @@
+// SPDX-License-Identifier: MPL-2.0
+mod common;
+
+use common::{dmi_copy, dmi_copy_ok, load, save, state};
+use dmi::icon::IconState;
+use image::RgbaImage;
+use std::{fs, path::Path};
+
+#[test]
+fn frame_count_mismatch_fails_unless_allowed() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&source, vec![state("walk", &[1, 2, 3])]);
+	save(&target, vec![state("walk", &[4, 5])]);
+	let (source_arg, target_arg) = (source.to_str().unwrap(), target.to_str().unwrap());
+	let before = fs::read(&target).unwrap();
+
+	let output = dmi_copy(&["walk", "from", source_arg, "to", target_arg]);
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("3 frame(s) in the source, but 2"));
+	assert_eq!(fs::read(&target).unwrap(), before);
+
+	dmi_copy_ok(&[
+		"walk",
+		"from",
+		source_arg,
+		"to",
+		target_arg,
+		"--match-frames=replace",
+	]);
+	assert_eq!(load(&target).states[0].frames, 3);
+}
+
+#[test]
+fn max_frames_checks_the_source_frame_count() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&source, vec![state("walk", &[1, 2, 3, 4])]);
+	save(&target, vec![state("idle", &[5])]);
+	let (source, target) = (source.to_str().unwrap(), target.to_str().unwrap());
+
+	let args = [
+		"walk",
+		"from",
+		source,
+		"to",
+		target,
+		"--max-frames-keep",
+		"2",
+	];
+	let output = dmi_copy(&[&args[..], &["--max-frames", "3"]].concat());
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("has 4 frames"));
+
+	dmi_copy_ok(&[&args[..], &["--max-frames", "4"]].concat());
+	assert_eq!(load(Path::new(target)).states[1].frames, 2);
+}
+
+#[test]
+fn at_index_inserts_new_states_in_place() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&source, vec![state("walk", &[1]), state("run", &[2])]);
+	save(&target, vec![state("idle", &[3]), state("sleep", &[4])]);
+	let (source_arg, target_arg) = (source.to_str().unwrap(), target.to_str().unwrap());
+
+	dmi_copy_ok(&[
+		"walk",
+		"run",
+		"from",
+		source_arg,
+		"to",
+		target_arg,
+		"--at-index",
+		"1",
+	]);
+	let names = load(&target)
+		.states
+		.into_iter()
+		.map(|state| state.name)
+		.collect::<Vec<_>>();
+	assert_eq!(names, ["idle", "walk", "run", "sleep"]);
+}
+
+#[test]
+fn max_copies_refuses_too_many_states() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	let names = (0..12)
+		.map(|index| format!("state{index}"))
+		.collect::<Vec<_>>();
+	save(
+		&source,
+		names.iter().map(|name| state(name, &[1])).collect(),
+	);
+	save(&target, vec![state("idle", &[2])]);
+	let before = fs::read(&target).unwrap();
+	let args = names
+		.iter()
+		.map(String::as_str)
+		.chain([
+			"from",
+			source.to_str().unwrap(),
+			"to",
+			target.to_str().unwrap(),
+		])
+		.collect::<Vec<_>>();
+
+	let output = dmi_copy(&[&args[..], &["--max-copies", "11"]].concat());
+	assert!(!output.status.success());
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(stderr.contains("12 states were selected, more than the limit of 11"));
+	// Only the first few are named, the rest are counted.
+	assert!(stderr.contains("'state0', 'state1'"));
+	assert!(stderr.contains("'state9', and 2 more"));
+	assert!(!stderr.contains("'state10'"));
+	assert_eq!(fs::read(&target).unwrap(), before);
+
+	dmi_copy_ok(&[&args[..], &["--max-copies", "12"]].concat());
+	assert_eq!(load(&target).states.len(), 13);
+}
+
+#[test]
+fn rename_names_are_stable() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	save(&source, vec![state("walk", &[1]), state("run", &[2])]);
+	let copy_into = |name: &str| {
+		let target = dir.path().join(name);
+		save(&target, vec![
+			state("walk", &[3]),
+			state("walk_1", &[4]),
+			state("run", &[5]),
+		]);
+		dmi_copy_ok(&[
+			"walk",
+			"run",
+			"from",
+			source.to_str().unwrap(),
+			"to",
+			target.to_str().unwrap(),
+			"--on-conflict",
+			"rename",
+		]);
+		target
+	};
+
+	let first = copy_into("first.dmi");
+	let names = load(&first)
+		.states
+		.into_iter()
+		.map(|state| state.name)
+		.collect::<Vec<_>>();
+	// Taken suffixes are skipped over, and the originals are left alone.
+	assert_eq!(names, ["walk", "walk_1", "run", "walk_2", "run_1"]);
+	let second = copy_into("second.dmi");
+	assert_eq!(fs::read(first).unwrap(), fs::read(second).unwrap());
+}
+
+#[test]
+fn no_clobber_newer_protects_states_from_older_sources() {
+	let dir = tempfile::tempdir().unwrap();
+	let old = dir.path().join("old.dmi");
+	let new = dir.path().join("new.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&old, vec![state("walk", &[1, 2, 3])]);
+	save(&new, vec![state("run", &[4])]);
+	save(&target, vec![state("walk", &[5, 6]), state("run", &[7])]);
+	let now = std::time::SystemTime::now();
+	for (path, age) in [(&old, 300), (&target, 200), (&new, 100)] {
+		fs::File::options()
+			.write(true)
+			.open(path)
+			.unwrap()
+			.set_modified(now - std::time::Duration::from_secs(age))
+			.unwrap();
+	}
+
+	// The frame counts differ too, which mustn't matter for a state that's
+	// left alone.
+	let output = dmi_copy_ok(&[
+		"walk",
+		"run",
+		"from",
+		old.to_str().unwrap(),
+		new.to_str().unwrap(),
+		"to",
+		target.to_str().unwrap(),
+		"--no-clobber-newer",
+	]);
+	assert!(String::from_utf8_lossy(&output.stderr)
+		.contains("not replacing state 'walk', the target is newer than the source"));
+	let states = load(&target).states;
+	assert_eq!(states[0], state("walk", &[5, 6]));
+	assert_eq!(states[1], state("run", &[4]));
+}
+
+#[test]
+fn normalized_name_collisions_fail() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&source, vec![
+		state("Walk North", &[1]),
+		state("walk north", &[2]),
+	]);
+	save(&target, vec![state("walk_north", &[3])]);
+	let before = fs::read(&target).unwrap();
+
+	let output = dmi_copy(&[
+		"Walk North",
+		"walk north",
+		"from",
+		source.to_str().unwrap(),
+		"to",
+		target.to_str().unwrap(),
+		"--normalize-names",
+		"lower,underscore",
+	]);
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr)
+		.contains("states 'Walk North' and 'walk north' have the same name once normalized"));
+	assert_eq!(fs::read(&target).unwrap(), before);
+}
+
+#[cfg(unix)]
+#[test]
+fn transform_cmd_pipes_every_frame() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	let small = dir.path().join("small.png");
+	save(&source, vec![state("walk", &[1, 2])]);
+	save(&target, vec![state("idle", &[3])]);
+	RgbaImage::new(16, 16).save(&small).unwrap();
+	let before = fs::read(&target).unwrap();
+	let copy = |cmd: &str| {
+		dmi_copy(&[
+			"walk",
+			"from",
+			source.to_str().unwrap(),
+			"to",
+			target.to_str().unwrap(),
+			"--transform-cmd",
+			cmd,
+		])
+	};
+
+	for (cmd, error) in [
+		(
+			"cat >/dev/null; exit 3",
+			"transform command exited with exit status: 3",
+		),
+		("echo hello", "transform command did not output a valid png"),
+		(
+			&format!("cat {}", small.display()),
+			"transform command changed the frame size from 32x32 to 16x16",
+		),
+	] {
+		let output = copy(cmd);
+		assert!(!output.status.success(), "{cmd}");
+		let stderr = String::from_utf8_lossy(&output.stderr);
+		assert!(stderr.contains(error), "{cmd}: {stderr}");
+		assert_eq!(fs::read(&target).unwrap(), before, "{cmd}");
+	}
+
+	let output = copy("cat");
+	assert!(
+		output.status.success(),
+		"{}",
+		String::from_utf8_lossy(&output.stderr)
+	);
+	assert_eq!(load(&target).states[1], state("walk", &[1, 2]));
+}
+
+#[test]
+fn skip_errors_saves_the_other_states() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	let with_dirs = |name: &str, shades: &[u8]| IconState {
+		dirs: shades.len() as u8,
+		frames: 1,
+		delay: None,
+		..state(name, shades)
+	};
+	save(&source, vec![
+		with_dirs("walk", &[1, 2, 3, 4]),
+		with_dirs("run", &[1, 2, 3, 4, 5, 6, 7, 8]),
+	]);
+	save(&target, vec![state("idle", &[6])]);
+	let args = [
+		"walk",
+		"run",
+		"from",
+		source.to_str().unwrap(),
+		"to",
+		target.to_str().unwrap(),
+		"--set-dirs",
+		"1",
+		"--keep-dir",
+		"northeast",
+	];
+	let before = fs::read(&target).unwrap();
+
+	// A 4-dir state has no northeast to keep, which fails the whole copy...
+	let output = dmi_copy(&args);
+	assert!(!output.status.success());
+	assert_eq!(fs::read(&target).unwrap(), before);
+
+	// ...unless it's skipped, and the rest are still copied.
+	let output = dmi_copy(&[&args[..], &["--skip-errors"]].concat());
+	assert!(!output.status.success());
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert!(stderr.contains("a state with 4 dir(s) has no northeast direction to keep"));
+	assert!(stderr.contains("1 state(s) failed to copy"));
+	let states = load(&target).states;
+	let names = states
+		.iter()
+		.map(|state| state.name.as_str())
+		.collect::<Vec<_>>();
+	assert_eq!(names, ["idle", "run"]);
+	assert_eq!(states[1], state("run", &[7]));
+}
+
+#[test]
+fn emit_patch_holds_only_changed_states() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	let patch = dir.path().join("patch.dmi");
+	save(&source, vec![
+		state("walk", &[1]),
+		state("idle", &[2]),
+		state("run", &[3]),
+	]);
+	save(&target, vec![
+		state("walk", &[4]),
+		state("idle", &[2]),
+		state("sleep", &[5]),
+	]);
+
+	dmi_copy_ok(&[
+		"walk",
+		"idle",
+		"run",
+		"from",
+		source.to_str().unwrap(),
+		"to",
+		target.to_str().unwrap(),
+		"--emit-patch",
+		patch.to_str().unwrap(),
+	]);
+	assert_eq!(load(&patch).states, [
+		state("walk", &[1]),
+		state("run", &[3])
+	]);
+	assert_eq!(load(&target).states.len(), 4);
+
+	// Nothing changes the second time, so there's no patch.
+	fs::remove_file(&patch).unwrap();
+	dmi_copy_ok(&[
+		"walk",
+		"from",
+		source.to_str().unwrap(),
+		"to",
+		target.to_str().unwrap(),
+		"--emit-patch",
+		patch.to_str().unwrap(),
+	]);
+	assert!(!patch.exists());
+}
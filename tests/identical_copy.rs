@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: MPL-2.0
+mod common;
+
+use common::{dmi_copy_ok, save, state};
+use std::{fs, path::Path};
+
+#[test]
+fn identical_copy_leaves_target_byte_identical() {
+	let dir = tempfile::tempdir().unwrap();
+	let source = dir.path().join("source.dmi");
+	let target = dir.path().join("target.dmi");
+	save(&source, vec![state("walk", &[1]), state("idle", &[2])]);
+	save(&target, vec![state("idle", &[2]), state("walk", &[1])]);
+	let (source, target) = (source.to_str().unwrap(), target.to_str().unwrap());
+	// Encode the target differently from how a copy would, so re-encoding it
+	// would be sure to change its bytes.
+	dmi_copy_ok(&["normalize", target, "--compression", "best"]);
+	let before = fs::read(target).unwrap();
+
+	dmi_copy_ok(&["--from", source, "--to", target, "--state", "walk,idle"]);
+	assert_eq!(fs::read(target).unwrap(), before);
+
+	save(Path::new(source), vec![state("walk", &[3])]);
+	dmi_copy_ok(&["--from", source, "--to", target, "--state", "walk"]);
+	assert_ne!(fs::read(target).unwrap(), before);
+}
@@ -0,0 +1,34 @@
+// SPDX-License-Identifier: MPL-2.0
+mod common;
+
+use common::{dmi_copy_ok, load, save, state};
+use std::fs;
+
+#[test]
+fn normalize_keeps_the_states() {
+	let dir = tempfile::tempdir().unwrap();
+	let file = dir.path().join("icon.dmi");
+	save(&file, vec![state("idle", &[1]), state("walk", &[2, 3, 4])]);
+	let path = file.to_str().unwrap();
+	let states = load(&file).states;
+
+	for compression in ["fast", "best", "default"] {
+		let before = fs::read(&file).unwrap();
+		let output = dmi_copy_ok(&["normalize", path, "--compression", compression]);
+		assert!(
+			String::from_utf8_lossy(&output.stdout).starts_with(&format!("Normalized {path} ("))
+		);
+		assert_eq!(load(&file).states, states, "{compression}");
+		if compression == "fast" {
+			assert_ne!(fs::read(&file).unwrap(), before);
+		}
+	}
+
+	dmi_copy_ok(&["normalize", path, "--canonical"]);
+	assert_eq!(load(&file).states, states);
+	// Canonical output only depends on the states.
+	let canonical = fs::read(&file).unwrap();
+	dmi_copy_ok(&["normalize", path, "--canonical", "--compression", "best"]);
+	dmi_copy_ok(&["normalize", path, "--canonical"]);
+	assert_eq!(fs::read(&file).unwrap(), canonical);
+}